@@ -19,8 +19,15 @@ app.get('*', (req, res) => {
     res.sendFile(path.join(__dirname, '../dist/index.html'));
 });
 
-// Initialize WebSocket server
-const wss = new WebSocket.Server({ server });
+// Initialize WebSocket servers, the game and the chat have their own channels
+const wss = new WebSocket.Server({ noServer: true });
+const chatWss = new WebSocket.Server({ noServer: true });
+
+server.on('upgrade', (request, socket, head) => {
+    const { pathname } = new URL(request.url, 'http://localhost');
+    const target = pathname === '/chat' ? chatWss : wss;
+    target.handleUpgrade(request, socket, head, (ws) => target.emit('connection', ws, request));
+});
 
 // Game state
 let games = new Map();
@@ -84,11 +91,6 @@ wss.on('connection', (ws) => {
                     gameRef(data.playerId);
                     break;
 
-                case "GAME_CHAT":
-                    console.log('received game chat broadcast request');
-                    gameChat(data.gameId, data.sender, data.text);
-                    break;
-
                 case "END_GAME":
                     console.log('received end game request');
                     handlePlayerDisconnect(data.playerId, data.gameId);
@@ -159,28 +161,6 @@ wss.on('connection', (ws) => {
         broadcastLobby();
     }
 
-    function gameChat(gameId, sender, text) {
-        const game = games.get(gameId);
-        if (!game)
-            return ws.send(JSON.stringify({
-                type: 'ERROR',
-                message: 'Game not found'
-            }));
-
-        // Notify both players of the chat
-        game.players.forEach(pid => {
-            const connection = playerConnections.get(pid);
-            if (connection && connection.ws.readyState === WebSocket.OPEN) {
-                connection.ws.send(JSON.stringify({
-                    type: 'GAME_CHAT',
-                    gameId,
-                    sender,
-                    text
-                }));
-            }
-        });
-    }
-
     function handleJoinGame(ws, name, requestedGameId) {
         // Check if game exists and can be joined
         const game = games.get(requestedGameId);
@@ -434,6 +414,71 @@ wss.on('connection', (ws) => {
     }
 });
 
+// The chat rooms, keyed by gameId
+let chatRooms = new Map();
+
+// Chat connection handler, the players of a game join its room
+chatWss.on('connection', (ws) => {
+    let room = null;
+
+    ws.on('message', (message) => {
+        try {
+            // we must have this here to ping the reverse proxy that we need the tunnel established, health_check
+            if (message == "health_check" || message == "init_tunnel") {
+                ws.send(message)
+                return
+            }
+
+            const data = JSON.parse(message);
+            switch (data.type) {
+                case "JOIN_CHAT":
+                    leaveRoom();
+                    room = data.gameId;
+                    if (!chatRooms.has(room))
+                        chatRooms.set(room, new Set());
+                    chatRooms.get(room).add(ws);
+                    break;
+
+                case "GAME_CHAT":
+                    console.log('received game chat broadcast request');
+                    if (!room || !chatRooms.has(room))
+                        return ws.send(JSON.stringify({
+                            type: 'ERROR',
+                            message: 'Join the chat of a game first'
+                        }));
+
+                    chatRooms.get(room).forEach(client => {
+                        if (client.readyState === WebSocket.OPEN) {
+                            client.send(JSON.stringify({
+                                type: 'GAME_CHAT',
+                                gameId: room,
+                                sender: data.sender,
+                                text: data.text
+                            }));
+                        }
+                    });
+                    break;
+
+                default:
+                    console.log('Unknown chat message type:', data.type);
+            }
+        } catch (error) {
+            console.error('Error processing chat message:', error);
+        }
+    });
+
+    ws.on('close', leaveRoom);
+
+    function leaveRoom() {
+        if (!room || !chatRooms.has(room))
+            return;
+
+        chatRooms.get(room).delete(ws);
+        if (chatRooms.get(room).size === 0)
+            chatRooms.delete(room);
+    }
+});
+
 // Helper function to check for win or draw
 function checkGameStatus(board) {
     // Win patterns: rows, columns, diagonals
@@ -0,0 +1,108 @@
+use js_sys::{Reflect, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+
+use super::{AssetCache, CachedAsset, cache_key, quota_or};
+use crate::js_glue::js_imports::{
+    cs_cache_size, cs_clear, cs_delete_asset, cs_evict, cs_evict_lru, cs_get_asset, cs_put_asset, cs_retain_prefix, cs_touch_asset,
+};
+
+/// A cache backend on top of the browser's Cache Storage API (`caches.open`); each namespace gets its own cache.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct CacheStorageCache {
+    cache_name: String,
+}
+
+impl CacheStorageCache {
+    pub(crate) fn new(cache_name: &str) -> Self {
+        CacheStorageCache {
+            cache_name: cache_name.to_string(),
+        }
+    }
+}
+
+impl AssetCache for CacheStorageCache {
+    async fn get(&self, session: &str, url: &str) -> Result<Option<CachedAsset>, String> {
+        let entry = cs_get_asset(&self.cache_name, &cache_key(session, url))
+            .await
+            .map_err(cache_storage_error)?;
+
+        if entry.is_null() || entry.is_undefined() {
+            return Ok(None);
+        }
+
+        let field = |name: &str| Reflect::get(&entry, &JsValue::from_str(name)).map_err(cache_storage_error);
+        let body = field("body")?
+            .dyn_into::<Uint8Array>()
+            .map_err(|_| "expected the cached body to be a Uint8Array".to_string())?;
+
+        Ok(Some(CachedAsset {
+            body: body.to_vec(),
+            content_type: field("_type")?.as_string().unwrap_or_default(),
+            expires_at: field("_exp")?.as_f64().unwrap_or_default(),
+            last_used: field("_used")?.as_f64().unwrap_or_default(),
+        }))
+    }
+
+    async fn put(&self, session: &str, url: &str, asset: CachedAsset) -> Result<(), String> {
+        cs_put_asset(
+            &self.cache_name,
+            &cache_key(session, url),
+            Uint8Array::from(asset.body.as_slice()),
+            &asset.content_type,
+            asset.expires_at,
+            asset.last_used,
+        )
+        .await
+        .map_err(|e| quota_or(e, cache_storage_error))?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, session: &str, url: &str) -> Result<(), String> {
+        cs_delete_asset(&self.cache_name, &cache_key(session, url))
+            .await
+            .map_err(cache_storage_error)?;
+        Ok(())
+    }
+
+    async fn touch(&self, session: &str, url: &str, now: f64) -> Result<(), String> {
+        cs_touch_asset(&self.cache_name, &cache_key(session, url), now)
+            .await
+            .map_err(cache_storage_error)?;
+        Ok(())
+    }
+
+    async fn size(&self) -> Result<u64, String> {
+        let size = cs_cache_size(&self.cache_name).await.map_err(cache_storage_error)?;
+        Ok(size.as_f64().unwrap_or_default() as u64)
+    }
+
+    async fn evict(&self, now: f64) -> Result<u64, String> {
+        let freed = cs_evict(&self.cache_name, now).await.map_err(cache_storage_error)?;
+        Ok(freed.as_f64().unwrap_or_default() as u64)
+    }
+
+    async fn evict_lru(&self, bytes: u64) -> Result<u64, String> {
+        let freed = cs_evict_lru(&self.cache_name, bytes as f64).await.map_err(cache_storage_error)?;
+        Ok(freed.as_f64().unwrap_or_default() as u64)
+    }
+
+    async fn clear(&self) -> Result<(), String> {
+        cs_clear(&self.cache_name).await.map_err(cache_storage_error)?;
+        Ok(())
+    }
+
+    async fn retain_session(&self, session: &str) -> Result<(), String> {
+        cs_retain_prefix(&self.cache_name, &cache_key(session, ""))
+            .await
+            .map_err(cache_storage_error)?;
+        Ok(())
+    }
+}
+
+fn cache_storage_error(e: JsValue) -> String {
+    format!(
+        "error interacting with the Cache Storage: {}",
+        e.as_string().unwrap_or(format!("error unwrappable: {:?}", e))
+    )
+}
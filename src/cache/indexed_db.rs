@@ -0,0 +1,120 @@
+use js_sys::{Reflect, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+
+use super::{
+    AssetCache, CachedAsset, cache_key,
+    migrations::{current_version, migrations},
+    quota_or,
+};
+use crate::js_glue::js_imports::{
+    clear_cache, clear_expired_cache, clear_other_sessions, idb_cache_size, idb_delete_asset, idb_evict_lru, idb_get_asset, idb_put_asset,
+    idb_touch_asset, run_migrations,
+};
+
+/// A cache backend on top of IndexedDB; each namespace gets its own database.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct IndexedDbCache {
+    db_name: String,
+}
+
+impl IndexedDbCache {
+    pub(crate) fn new(db_name: &str) -> Self {
+        IndexedDbCache {
+            db_name: db_name.to_string(),
+        }
+    }
+
+    /// Brings the database schema up to date, see [`super::migrations`]. Resolves with the `CacheMigrationReport` of
+    /// the migrations that got applied.
+    pub(crate) async fn migrate(&self) -> Result<JsValue, String> {
+        let migrations = serde_wasm_bindgen::to_value(&migrations()).map_err(|e| e.to_string())?;
+        run_migrations(&self.db_name, migrations, current_version()).await.map_err(idb_error)
+    }
+}
+
+impl AssetCache for IndexedDbCache {
+    async fn get(&self, session: &str, url: &str) -> Result<Option<CachedAsset>, String> {
+        let entry = idb_get_asset(&self.db_name, &cache_key(session, url)).await.map_err(idb_error)?;
+
+        if entry.is_null() || entry.is_undefined() {
+            return Ok(None);
+        }
+
+        asset_from_entry(&entry).map(Some)
+    }
+
+    async fn put(&self, session: &str, url: &str, asset: CachedAsset) -> Result<(), String> {
+        idb_put_asset(
+            &self.db_name,
+            &cache_key(session, url),
+            session,
+            url,
+            Uint8Array::from(asset.body.as_slice()),
+            &asset.content_type,
+            asset.expires_at,
+            asset.last_used,
+        )
+        .await
+        .map_err(|e| quota_or(e, idb_error))?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, session: &str, url: &str) -> Result<(), String> {
+        idb_delete_asset(&self.db_name, &cache_key(session, url)).await.map_err(idb_error)?;
+        Ok(())
+    }
+
+    async fn touch(&self, session: &str, url: &str, now: f64) -> Result<(), String> {
+        idb_touch_asset(&self.db_name, &cache_key(session, url), now).await.map_err(idb_error)?;
+        Ok(())
+    }
+
+    async fn size(&self) -> Result<u64, String> {
+        let size = idb_cache_size(&self.db_name).await.map_err(idb_error)?;
+        Ok(size.as_f64().unwrap_or_default() as u64)
+    }
+
+    async fn evict(&self, now: f64) -> Result<u64, String> {
+        let freed = clear_expired_cache(&self.db_name, now).await.map_err(idb_error)?;
+        Ok(freed.as_f64().unwrap_or_default() as u64)
+    }
+
+    async fn evict_lru(&self, bytes: u64) -> Result<u64, String> {
+        let freed = idb_evict_lru(&self.db_name, bytes as f64).await.map_err(idb_error)?;
+        Ok(freed.as_f64().unwrap_or_default() as u64)
+    }
+
+    async fn clear(&self) -> Result<(), String> {
+        clear_cache(&self.db_name).await.map_err(idb_error)?;
+        Ok(())
+    }
+
+    async fn retain_session(&self, session: &str) -> Result<(), String> {
+        clear_other_sessions(&self.db_name, session).await.map_err(idb_error)?;
+        Ok(())
+    }
+}
+
+// The entries are stored as `{ key, session, url, body, size, _type, _exp, _used }` objects.
+fn asset_from_entry(entry: &JsValue) -> Result<CachedAsset, String> {
+    let field = |name: &str| Reflect::get(entry, &JsValue::from_str(name)).map_err(idb_error);
+
+    let body = field("body")?
+        .dyn_into::<Uint8Array>()
+        .map_err(|_| "expected the cached body to be a Uint8Array".to_string())?;
+
+    Ok(CachedAsset {
+        body: body.to_vec(),
+        content_type: field("_type")?.as_string().unwrap_or_default(),
+        expires_at: field("_exp")?.as_f64().unwrap_or_default(),
+        last_used: field("_used")?.as_f64().unwrap_or_default(),
+    })
+}
+
+fn idb_error(e: JsValue) -> String {
+    format!(
+        "error interacting with IndexDB: {}",
+        e.as_string().unwrap_or(format!("error unwrappable: {:?}", e))
+    )
+}
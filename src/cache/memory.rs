@@ -0,0 +1,225 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use super::{AssetCache, CachedAsset, cache_key};
+
+thread_local! {
+    // The in-memory stores, one per namespace. They live as long as the wasm instance does.
+    static MEMORY_CACHES: RefCell<HashMap<String, HashMap<String, CachedAsset>>> = RefCell::new(HashMap::new());
+}
+
+/// A cache backend that keeps the assets in the wasm memory. Nothing survives a page reload.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct MemoryCache {
+    namespace: String,
+}
+
+impl MemoryCache {
+    pub(crate) fn new(namespace: &str) -> Self {
+        MemoryCache {
+            namespace: namespace.to_string(),
+        }
+    }
+
+    fn with_store<T>(&self, f: impl FnOnce(&mut HashMap<String, CachedAsset>) -> T) -> T {
+        MEMORY_CACHES.with_borrow_mut(|caches| f(caches.entry(self.namespace.clone()).or_default()))
+    }
+}
+
+impl AssetCache for MemoryCache {
+    async fn get(&self, session: &str, url: &str) -> Result<Option<CachedAsset>, String> {
+        Ok(self.with_store(|store| store.get(&cache_key(session, url)).cloned()))
+    }
+
+    async fn put(&self, session: &str, url: &str, asset: CachedAsset) -> Result<(), String> {
+        self.with_store(|store| store.insert(cache_key(session, url), asset));
+        Ok(())
+    }
+
+    async fn delete(&self, session: &str, url: &str) -> Result<(), String> {
+        self.with_store(|store| store.remove(&cache_key(session, url)));
+        Ok(())
+    }
+
+    async fn touch(&self, session: &str, url: &str, now: f64) -> Result<(), String> {
+        self.with_store(|store| {
+            if let Some(asset) = store.get_mut(&cache_key(session, url)) {
+                asset.last_used = now;
+            }
+        });
+        Ok(())
+    }
+
+    async fn size(&self) -> Result<u64, String> {
+        Ok(self.with_store(|store| store.values().map(|asset| asset.body.len() as u64).sum()))
+    }
+
+    async fn evict(&self, now: f64) -> Result<u64, String> {
+        Ok(self.with_store(|store| {
+            let mut freed = 0;
+            store.retain(|_, asset| {
+                let expired = asset.is_expired(now);
+                if expired {
+                    freed += asset.body.len() as u64;
+                }
+                !expired
+            });
+            freed
+        }))
+    }
+
+    async fn evict_lru(&self, bytes: u64) -> Result<u64, String> {
+        Ok(self.with_store(|store| {
+            let mut entries = store.iter().map(|(key, asset)| (key.clone(), asset.last_used)).collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+            let mut freed = 0;
+            for (key, _) in entries {
+                if freed >= bytes {
+                    break;
+                }
+                freed += store.remove(&key).map_or(0, |asset| asset.body.len() as u64);
+            }
+            freed
+        }))
+    }
+
+    async fn clear(&self) -> Result<(), String> {
+        self.with_store(|store| store.clear());
+        Ok(())
+    }
+
+    async fn retain_session(&self, session: &str) -> Result<(), String> {
+        let prefix = cache_key(session, "");
+        self.with_store(|store| store.retain(|key, _| key.starts_with(&prefix)));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    use super::*;
+    use crate::cache::{lookup, store};
+
+    // the memory backend never yields, a single poll drives the futures to completion
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        match pin!(fut).poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(val) => val,
+            Poll::Pending => panic!("the memory cache is not expected to yield"),
+        }
+    }
+
+    fn asset(len: usize, expires_at: f64) -> CachedAsset {
+        CachedAsset {
+            body: vec![0; len],
+            content_type: "text/plain".to_string(),
+            expires_at,
+            last_used: 0.0,
+        }
+    }
+
+    #[test]
+    fn entries_are_namespaced_by_provider_and_session() {
+        let provider_a = MemoryCache::new("provider_a");
+        let provider_b = MemoryCache::new("provider_b");
+
+        block_on(provider_a.put("session", "http://a/file", asset(4, 10.0))).unwrap();
+        assert!(block_on(provider_a.get("session", "http://a/file")).unwrap().is_some());
+        assert!(block_on(provider_a.get("other_session", "http://a/file")).unwrap().is_none());
+        assert!(block_on(provider_b.get("session", "http://a/file")).unwrap().is_none());
+
+        block_on(provider_b.put("session", "http://b/file", asset(4, 10.0))).unwrap();
+        block_on(provider_a.clear()).unwrap();
+        assert_eq!(block_on(provider_a.size()).unwrap(), 0);
+        assert_eq!(block_on(provider_b.size()).unwrap(), 4);
+    }
+
+    #[test]
+    fn replaced_sessions_are_purged() {
+        let cache = MemoryCache::new("replaced");
+        block_on(cache.put("old_session", "http://a/file", asset(4, 10.0))).unwrap();
+        block_on(cache.put("session", "http://a/file", asset(8, 10.0))).unwrap();
+
+        block_on(cache.retain_session("session")).unwrap();
+        assert!(block_on(cache.get("old_session", "http://a/file")).unwrap().is_none());
+        assert!(block_on(cache.get("session", "http://a/file")).unwrap().is_some());
+        assert_eq!(block_on(cache.size()).unwrap(), 8);
+    }
+
+    #[test]
+    fn expired_entries_are_a_miss() {
+        let cache = MemoryCache::new("expiry");
+        block_on(cache.put("session", "http://a/old", asset(4, 10.0))).unwrap();
+        block_on(cache.put("session", "http://a/new", asset(4, 30.0))).unwrap();
+
+        assert!(block_on(lookup(&cache, "session", "http://a/old", 20.0)).unwrap().is_none());
+        assert!(block_on(lookup(&cache, "session", "http://a/new", 20.0)).unwrap().is_some());
+        assert_eq!(block_on(cache.size()).unwrap(), 4);
+
+        assert_eq!(block_on(cache.evict(40.0)).unwrap(), 4);
+        assert_eq!(block_on(cache.size()).unwrap(), 0);
+    }
+
+    #[test]
+    fn store_respects_the_limit() {
+        let cache = MemoryCache::new("limit");
+        assert!(block_on(store(&cache, 1024, None, "session", "http://a/small", asset(512, 10.0), 0.0)).unwrap());
+        assert!(!block_on(store(&cache, 1024, None, "session", "http://a/large", asset(1025, 10.0), 0.0)).unwrap());
+        assert!(block_on(cache.get("session", "http://a/large")).unwrap().is_none());
+
+        // the limit is byte accurate
+        assert!(block_on(store(&cache, 1024, None, "session", "http://a/exact", asset(512, 10.0), 0.0)).unwrap());
+        assert_eq!(block_on(cache.size()).unwrap(), 1024);
+
+        // an overwritten asset only counts once
+        assert!(block_on(store(&cache, 1024, None, "session", "http://a/exact", asset(512, 10.0), 0.0)).unwrap());
+        assert!(block_on(cache.get("session", "http://a/small")).unwrap().is_some());
+        assert_eq!(block_on(cache.size()).unwrap(), 1024);
+    }
+
+    #[test]
+    fn store_evicts_the_least_recently_used_assets() {
+        let cache = MemoryCache::new("lru");
+        let used = |last_used| CachedAsset {
+            last_used,
+            ..asset(400, 100.0)
+        };
+        assert!(block_on(store(&cache, 1024, None, "session", "http://a/old", used(1.0), 0.0)).unwrap());
+        assert!(block_on(store(&cache, 1024, None, "session", "http://a/new", used(2.0), 0.0)).unwrap());
+
+        // a hit makes `old` the most recently used asset
+        assert!(block_on(lookup(&cache, "session", "http://a/old", 3.0)).unwrap().is_some());
+        assert!(block_on(store(&cache, 1024, None, "session", "http://a/next", used(4.0), 0.0)).unwrap());
+        assert!(block_on(cache.get("session", "http://a/new")).unwrap().is_none());
+        assert!(block_on(cache.get("session", "http://a/old")).unwrap().is_some());
+
+        // expired assets go before the ones still in use
+        block_on(cache.put(
+            "session",
+            "http://a/expired",
+            CachedAsset {
+                last_used: 10.0,
+                ..asset(224, 5.0)
+            },
+        ))
+        .unwrap();
+        assert!(block_on(store(&cache, 1024, None, "session", "http://a/last", used(11.0), 6.0)).unwrap());
+        assert!(block_on(cache.get("session", "http://a/expired")).unwrap().is_none());
+        assert!(block_on(cache.get("session", "http://a/old")).unwrap().is_none());
+        assert_eq!(block_on(cache.size()).unwrap(), 800);
+    }
+
+    #[test]
+    fn store_respects_the_storage_headroom() {
+        let cache = MemoryCache::new("headroom");
+        assert!(!block_on(store(&cache, 4096, Some(256), "session", "http://a/file", asset(512, 10.0), 0.0)).unwrap());
+        assert!(block_on(store(&cache, 4096, Some(512), "session", "http://a/file", asset(512, 10.0), 0.0)).unwrap());
+
+        // an asset that can't fit even in an empty cache doesn't evict the others
+        assert!(!block_on(store(&cache, 4096, Some(256), "session", "http://a/large", asset(1024, 10.0), 0.0)).unwrap());
+        assert!(block_on(cache.get("session", "http://a/file")).unwrap().is_some());
+    }
+}
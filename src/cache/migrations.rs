@@ -0,0 +1,146 @@
+//! The schema of the IndexedDB asset cache is versioned. Each [`Migration`] moves a database from the previous version to
+//! its own; the steps are applied by `run_migrations` in `glue_indexed_db.js` during the `onupgradeneeded` event.
+//!
+//! Migrations are append-only: released migrations must never be edited, a schema change is a new migration with the
+//! next version.
+
+use serde::Serialize;
+
+/// The object store holding the cached assets.
+pub(crate) const STATIC_STORE: &str = "static";
+
+/// A single schema operation. It is applied inside the upgrade transaction, if any step fails the whole upgrade is
+/// rolled back.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(crate) enum MigrationStep {
+    /// Creates the store if it does not exist yet.
+    CreateStore { store: &'static str, key_path: &'static str },
+    /// Creates the index if it does not exist yet.
+    CreateIndex {
+        store: &'static str,
+        name: &'static str,
+        key_path: &'static str,
+        unique: bool,
+    },
+    /// Drops the index if it exists.
+    DeleteIndex { store: &'static str, name: &'static str },
+    /// Runs every entry of the store through the named transform in `glue_indexed_db.js`. Entries the transform maps
+    /// to `null` are dropped.
+    RewriteEntries { store: &'static str, transform: &'static str },
+    /// Drops the store together with its entries and creates it anew.
+    #[allow(dead_code)] // no released migration needs it yet
+    RebuildStore { store: &'static str, key_path: &'static str },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub steps: Vec<MigrationStep>,
+}
+
+/// All the migrations of the asset cache, in ascending version order.
+pub(crate) fn migrations() -> Vec<Migration> {
+    vec![
+        Migration {
+            // versions before 2 predate the per-provider databases, the shared one is deleted on init instead
+            version: 2,
+            description: "create the static asset store",
+            steps: vec![
+                MigrationStep::CreateStore {
+                    store: STATIC_STORE,
+                    key_path: "key",
+                },
+                MigrationStep::CreateIndex {
+                    store: STATIC_STORE,
+                    name: "url",
+                    key_path: "url",
+                    unique: false,
+                },
+                MigrationStep::CreateIndex {
+                    store: STATIC_STORE,
+                    name: "session",
+                    key_path: "session",
+                    unique: false,
+                },
+                MigrationStep::CreateIndex {
+                    store: STATIC_STORE,
+                    name: "_exp",
+                    key_path: "_exp",
+                    unique: false,
+                },
+                MigrationStep::CreateIndex {
+                    store: STATIC_STORE,
+                    name: "body",
+                    key_path: "body",
+                    unique: false,
+                },
+                MigrationStep::CreateIndex {
+                    store: STATIC_STORE,
+                    name: "_type",
+                    key_path: "_type",
+                    unique: false,
+                },
+            ],
+        },
+        Migration {
+            // binary values are valid IndexedDB keys, the `body` index held a second copy of every asset
+            version: 3,
+            description: "drop the unused body and _type indexes",
+            steps: vec![
+                MigrationStep::DeleteIndex {
+                    store: STATIC_STORE,
+                    name: "body",
+                },
+                MigrationStep::DeleteIndex {
+                    store: STATIC_STORE,
+                    name: "_type",
+                },
+            ],
+        },
+        Migration {
+            // the cache size is the sum of the recorded sizes, entries written before this version have none
+            version: 4,
+            description: "record the byte size of the cached assets",
+            steps: vec![MigrationStep::RewriteEntries {
+                store: STATIC_STORE,
+                transform: "record_size",
+            }],
+        },
+        Migration {
+            // the size is summed with a key cursor on this index, without loading the bodies
+            version: 5,
+            description: "index the byte size of the cached assets",
+            steps: vec![MigrationStep::CreateIndex {
+                store: STATIC_STORE,
+                name: "size",
+                key_path: "size",
+                unique: false,
+            }],
+        },
+        Migration {
+            // the least recently used entries are found with a key cursor on this index, entries written before their
+            // use was recorded go first
+            version: 6,
+            description: "index the last use of the cached assets",
+            steps: vec![
+                MigrationStep::RewriteEntries {
+                    store: STATIC_STORE,
+                    transform: "record_use",
+                },
+                MigrationStep::CreateIndex {
+                    store: STATIC_STORE,
+                    name: "_used",
+                    key_path: "_used",
+                    unique: false,
+                },
+            ],
+        },
+    ]
+}
+
+/// The version the cache databases are opened with.
+pub(crate) fn current_version() -> u32 {
+    migrations().last().map(|migration| migration.version).unwrap_or(1)
+}
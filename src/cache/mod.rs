@@ -0,0 +1,296 @@
+//! The static asset cache. The backing store is pluggable through the [`AssetCache`] trait; the backend in use is selected
+//! with `InitConfig.cacheBackend`.
+
+pub(crate) mod cache_storage;
+pub(crate) mod indexed_db;
+pub(crate) mod memory;
+pub(crate) mod migrations;
+
+use cache_storage::CacheStorageCache;
+use indexed_db::IndexedDbCache;
+use memory::MemoryCache;
+
+use wasm_bindgen::JsValue;
+
+use crate::js_glue::js_imports::get_storage_estimate;
+
+/// The error backends report when the browser refuses a write for lack of storage quota.
+pub(crate) const QUOTA_EXCEEDED: &str = "QuotaExceededError";
+
+/// An asset as kept in the cache.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CachedAsset {
+    pub body: Vec<u8>,
+    pub content_type: String,
+    /// The expiry timestamp of the asset, in milliseconds since the unix epoch.
+    pub expires_at: f64,
+    /// When the asset was last stored or served, in milliseconds since the unix epoch. The least recently used assets
+    /// are evicted first when the cache is full.
+    pub last_used: f64,
+}
+
+impl CachedAsset {
+    pub(crate) fn is_expired(&self, now: f64) -> bool {
+        self.expires_at <= now
+    }
+}
+
+/// The operations a cache backend has to provide. Entries are namespaced by the tunnel session they were fetched with.
+///
+/// Each backend instance is scoped to a single provider, operations on it never touch the entries of other providers.
+pub(crate) trait AssetCache {
+    /// Returns the asset if present. Expired assets are returned as is, it is up to the caller to check for expiry.
+    async fn get(&self, session: &str, url: &str) -> Result<Option<CachedAsset>, String>;
+
+    /// Fails with [`QUOTA_EXCEEDED`] if the browser has no room left for the asset.
+    async fn put(&self, session: &str, url: &str, asset: CachedAsset) -> Result<(), String>;
+
+    async fn delete(&self, session: &str, url: &str) -> Result<(), String>;
+
+    /// Records that the asset was served at `now`.
+    async fn touch(&self, session: &str, url: &str, now: f64) -> Result<(), String>;
+
+    /// Returns the size of the cached assets in bytes.
+    async fn size(&self) -> Result<u64, String>;
+
+    /// Removes all the assets that have expired by `now`. Returns the number of bytes freed.
+    async fn evict(&self, now: f64) -> Result<u64, String>;
+
+    /// Removes the least recently used assets until `bytes` bytes are freed or the cache is empty. Returns the number
+    /// of bytes freed.
+    async fn evict_lru(&self, bytes: u64) -> Result<u64, String>;
+
+    /// Removes all the assets.
+    async fn clear(&self) -> Result<(), String>;
+
+    /// Removes the assets of every session but `session`, those of a replaced session can no longer be looked up.
+    async fn retain_session(&self, session: &str) -> Result<(), String>;
+}
+
+/// The cache backends that can be selected with `InitConfig.cacheBackend`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) enum CacheBackendKind {
+    #[default]
+    IndexedDb,
+    CacheStorage,
+    Memory,
+}
+
+impl CacheBackendKind {
+    pub(crate) fn parse(val: &str) -> Option<Self> {
+        match val {
+            "indexedDB" => Some(CacheBackendKind::IndexedDb),
+            "cacheStorage" => Some(CacheBackendKind::CacheStorage),
+            "memory" => Some(CacheBackendKind::Memory),
+            _ => None,
+        }
+    }
+}
+
+/// The cache backend of a provider.
+#[derive(Debug, Clone)]
+pub(crate) enum CacheBackend {
+    IndexedDb(IndexedDbCache),
+    CacheStorage(CacheStorageCache),
+    Memory(MemoryCache),
+}
+
+impl Default for CacheBackend {
+    fn default() -> Self {
+        CacheBackend::Memory(MemoryCache::default())
+    }
+}
+
+impl CacheBackend {
+    /// Creates the backend of `kind` scoped to `namespace`.
+    pub(crate) fn new(kind: CacheBackendKind, namespace: &str) -> Self {
+        match kind {
+            CacheBackendKind::IndexedDb => CacheBackend::IndexedDb(IndexedDbCache::new(namespace)),
+            CacheBackendKind::CacheStorage => CacheBackend::CacheStorage(CacheStorageCache::new(namespace)),
+            CacheBackendKind::Memory => CacheBackend::Memory(MemoryCache::new(namespace)),
+        }
+    }
+
+    /// Whether the assets are written to the origin's storage, and so count against its quota.
+    pub(crate) fn uses_storage_quota(&self) -> bool {
+        !matches!(self, CacheBackend::Memory(_))
+    }
+}
+
+impl AssetCache for CacheBackend {
+    async fn get(&self, session: &str, url: &str) -> Result<Option<CachedAsset>, String> {
+        match self {
+            CacheBackend::IndexedDb(cache) => cache.get(session, url).await,
+            CacheBackend::CacheStorage(cache) => cache.get(session, url).await,
+            CacheBackend::Memory(cache) => cache.get(session, url).await,
+        }
+    }
+
+    async fn put(&self, session: &str, url: &str, asset: CachedAsset) -> Result<(), String> {
+        match self {
+            CacheBackend::IndexedDb(cache) => cache.put(session, url, asset).await,
+            CacheBackend::CacheStorage(cache) => cache.put(session, url, asset).await,
+            CacheBackend::Memory(cache) => cache.put(session, url, asset).await,
+        }
+    }
+
+    async fn delete(&self, session: &str, url: &str) -> Result<(), String> {
+        match self {
+            CacheBackend::IndexedDb(cache) => cache.delete(session, url).await,
+            CacheBackend::CacheStorage(cache) => cache.delete(session, url).await,
+            CacheBackend::Memory(cache) => cache.delete(session, url).await,
+        }
+    }
+
+    async fn touch(&self, session: &str, url: &str, now: f64) -> Result<(), String> {
+        match self {
+            CacheBackend::IndexedDb(cache) => cache.touch(session, url, now).await,
+            CacheBackend::CacheStorage(cache) => cache.touch(session, url, now).await,
+            CacheBackend::Memory(cache) => cache.touch(session, url, now).await,
+        }
+    }
+
+    async fn size(&self) -> Result<u64, String> {
+        match self {
+            CacheBackend::IndexedDb(cache) => cache.size().await,
+            CacheBackend::CacheStorage(cache) => cache.size().await,
+            CacheBackend::Memory(cache) => cache.size().await,
+        }
+    }
+
+    async fn evict(&self, now: f64) -> Result<u64, String> {
+        match self {
+            CacheBackend::IndexedDb(cache) => cache.evict(now).await,
+            CacheBackend::CacheStorage(cache) => cache.evict(now).await,
+            CacheBackend::Memory(cache) => cache.evict(now).await,
+        }
+    }
+
+    async fn evict_lru(&self, bytes: u64) -> Result<u64, String> {
+        match self {
+            CacheBackend::IndexedDb(cache) => cache.evict_lru(bytes).await,
+            CacheBackend::CacheStorage(cache) => cache.evict_lru(bytes).await,
+            CacheBackend::Memory(cache) => cache.evict_lru(bytes).await,
+        }
+    }
+
+    async fn clear(&self) -> Result<(), String> {
+        match self {
+            CacheBackend::IndexedDb(cache) => cache.clear().await,
+            CacheBackend::CacheStorage(cache) => cache.clear().await,
+            CacheBackend::Memory(cache) => cache.clear().await,
+        }
+    }
+
+    async fn retain_session(&self, session: &str) -> Result<(), String> {
+        match self {
+            CacheBackend::IndexedDb(cache) => cache.retain_session(session).await,
+            CacheBackend::CacheStorage(cache) => cache.retain_session(session).await,
+            CacheBackend::Memory(cache) => cache.retain_session(session).await,
+        }
+    }
+}
+
+/// Looks up `url` in the cache, expired assets are dropped and reported as a miss. A hit counts as a use of the asset.
+pub(crate) async fn lookup(cache: &impl AssetCache, session: &str, url: &str, now: f64) -> Result<Option<CachedAsset>, String> {
+    match cache.get(session, url).await? {
+        Some(asset) if asset.is_expired(now) => {
+            cache.delete(session, url).await?;
+            Ok(None)
+        }
+        Some(asset) => {
+            cache.touch(session, url, now).await?;
+            Ok(Some(asset))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Stores the asset if doing so keeps the cache under `limit` bytes and within the `headroom` the browser has left,
+/// see [`StorageEstimate::headroom`]. The asset replaces the one cached for `url`. To make room the assets expired by
+/// `now` are evicted first, then the least recently used ones. Returns whether the asset was stored.
+///
+/// When the browser still runs out of quota, the assets expired by `now` are evicted and the asset is not cached.
+pub(crate) async fn store(
+    cache: &impl AssetCache,
+    limit: u64,
+    headroom: Option<u64>,
+    session: &str,
+    url: &str,
+    asset: CachedAsset,
+    now: f64,
+) -> Result<bool, String> {
+    let size = asset.body.len() as u64;
+    if size > limit {
+        return Ok(false);
+    }
+
+    // the replaced asset is stale, its bytes are not counted against the new one
+    cache.delete(session, url).await?;
+
+    // the bytes to free, within the cache's limit and within what the browser has left
+    // the size is read once, the evictions report what they freed
+    let needed = |used: u64| (used + size).saturating_sub(limit).max(size.saturating_sub(headroom.unwrap_or(u64::MAX)));
+    let used = cache.size().await?;
+    // evicting every asset would not make room, they are kept
+    if needed(used) > used {
+        return Ok(false);
+    }
+
+    if needed(used) > 0 {
+        let used = used.saturating_sub(cache.evict(now).await?);
+
+        let needed = needed(used);
+        if needed > 0 && cache.evict_lru(needed).await? < needed {
+            return Ok(false);
+        }
+    }
+
+    match cache.put(session, url, asset).await {
+        Ok(()) => Ok(true),
+        Err(e) if e == QUOTA_EXCEEDED => {
+            // making room for the assets that come after this one
+            cache.evict(now).await?;
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The storage estimate of the origin, from `navigator.storage.estimate()`. The values are in bytes.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StorageEstimate {
+    pub quota: u64,
+    pub usage: u64,
+}
+
+impl StorageEstimate {
+    /// The number of bytes the origin can still write before reaching its quota.
+    pub(crate) fn headroom(&self) -> u64 {
+        self.quota.saturating_sub(self.usage)
+    }
+}
+
+/// Returns `None` if the estimate is not available.
+pub(crate) async fn storage_estimate() -> Option<StorageEstimate> {
+    let estimate = get_storage_estimate().await.ok()?;
+    let field = |name: &str| js_sys::Reflect::get(&estimate, &name.into()).ok().and_then(|val| val.as_f64());
+
+    Some(StorageEstimate {
+        quota: field("quota")? as u64,
+        usage: field("usage").unwrap_or_default() as u64,
+    })
+}
+
+// Quota errors are passed on as is, callers handle them apart from the other failures.
+fn quota_or(e: JsValue, or: fn(JsValue) -> String) -> String {
+    match e.as_string() {
+        Some(val) if val == QUOTA_EXCEEDED => val,
+        _ => or(e),
+    }
+}
+
+// Entries are namespaced by the tunnel session they were fetched with.
+pub(crate) fn cache_key(session: &str, url: &str) -> String {
+    format!("{}::{}", session, url)
+}
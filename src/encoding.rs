@@ -0,0 +1,311 @@
+use std::io::{Read, Write};
+
+use wasm_bindgen::prelude::*;
+
+use crate::js_imports_prelude::*;
+
+/// The `Accept-Encoding` sent with every tunneled request, these are the codings [`decode`] understands.
+pub(crate) const ACCEPT_ENCODING: &str = "gzip, deflate, br, zstd";
+
+/// Request bodies smaller than this are sent as is, compressing them costs more than it saves. The value is in bytes.
+pub(crate) const DEFAULT_COMPRESSION_THRESHOLD: u64 = 1024;
+
+/// The content types request bodies are compressed for by default, already compressed formats gain nothing.
+const DEFAULT_COMPRESSIBLE_TYPES: [&str; 5] = [
+    "application/json",
+    "application/xml",
+    "application/javascript",
+    "application/x-www-form-urlencoded",
+    "text/*",
+];
+
+/// A content coding as found in the `Content-Encoding` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ContentEncoding {
+    Identity,
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+impl ContentEncoding {
+    pub(crate) fn parse(coding: &str) -> Result<Self, String> {
+        // codings are case-insensitive, `x-gzip` is an alias of `gzip` (RFC 9110 section 8.4.1.3)
+        match coding.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Ok(ContentEncoding::Identity),
+            "gzip" | "x-gzip" => Ok(ContentEncoding::Gzip),
+            "deflate" => Ok(ContentEncoding::Deflate),
+            "br" => Ok(ContentEncoding::Brotli),
+            "zstd" => Ok(ContentEncoding::Zstd),
+            val => Err(format!("unsupported content encoding: {}", val)),
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Identity => "identity",
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+            ContentEncoding::Brotli => "br",
+            ContentEncoding::Zstd => "zstd",
+        }
+    }
+
+    fn encode(&self, body: &[u8]) -> Result<Vec<u8>, String> {
+        let res = match self {
+            ContentEncoding::Identity => return Ok(body.to_vec()),
+            ContentEncoding::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body).and_then(|_| encoder.finish())
+            }
+            ContentEncoding::Deflate => {
+                let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body).and_then(|_| encoder.finish())
+            }
+            ContentEncoding::Brotli => {
+                // quality 5 and a 4MB window (lgwin 22) trade ratio for speed on the main thread
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+                encoder.write_all(body).map(|_| encoder.into_inner())
+            }
+            ContentEncoding::Zstd => return Err("zstd is not supported for request bodies".to_string()),
+        };
+
+        res.map_err(|e| format!("failed to encode {:?} content: {}", self, e))
+    }
+
+    fn decode(&self, body: Vec<u8>) -> Result<Vec<u8>, String> {
+        let mut decoded = Vec::new();
+        let res = match self {
+            ContentEncoding::Identity => return Ok(body),
+            ContentEncoding::Gzip => flate2::read::MultiGzDecoder::new(body.as_slice()).read_to_end(&mut decoded),
+            ContentEncoding::Deflate => {
+                // `deflate` is specified as zlib-wrapped, some servers send the raw stream anyway
+                match flate2::read::ZlibDecoder::new(body.as_slice()).read_to_end(&mut decoded) {
+                    Ok(val) => Ok(val),
+                    Err(_) => {
+                        decoded.clear();
+                        flate2::read::DeflateDecoder::new(body.as_slice()).read_to_end(&mut decoded)
+                    }
+                }
+            }
+            ContentEncoding::Brotli => brotli::Decompressor::new(body.as_slice(), 4096).read_to_end(&mut decoded),
+            ContentEncoding::Zstd => {
+                let mut source = body.as_slice();
+                ruzstd::streaming_decoder::StreamingDecoder::new(&mut source)
+                    .map_err(|e| format!("invalid zstd frame: {}", e))?
+                    .read_to_end(&mut decoded)
+            }
+        };
+
+        res.map_err(|e| format!("failed to decode {:?} content: {}", self, e))?;
+        Ok(decoded)
+    }
+}
+
+/// Decodes `body` according to the value of its `Content-Encoding` header. The codings are listed in the order they
+/// were applied, so they are undone from last to first.
+pub(crate) fn decode(body: Vec<u8>, content_encoding: Option<&str>) -> Result<Vec<u8>, String> {
+    let Some(content_encoding) = content_encoding else {
+        return Ok(body);
+    };
+
+    let codings = content_encoding.split(',').map(ContentEncoding::parse).collect::<Result<Vec<_>, _>>()?;
+
+    codings.iter().rev().try_fold(body, |body, coding| coding.decode(body))
+}
+
+/// The compression of tunneled request bodies. It is configured with `InitConfig.requestCompression` and can be
+/// overridden per request with the `compress` fetch option.
+///
+/// Either option accepts a boolean, or an object which also enables the compression:
+/// ```js
+/// export interface RequestCompression {
+///    // The coding the bodies are compressed with. Defaults to "gzip".
+///    encoding: "gzip" | "br" | undefined;
+///    // The size below which bodies are sent uncompressed, in bytes. Defaults to 1024.
+///    threshold: number | undefined;
+///    // The content types to compress, `type/*` matches a whole type.
+///    // Defaults to JSON, XML, JavaScript, url-encoded forms and text.
+///    contentTypes: string[] | undefined;
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub(crate) struct RequestCompression {
+    pub(crate) enabled: bool,
+    pub(crate) encoding: ContentEncoding,
+    // The value is in bytes.
+    pub(crate) threshold: u64,
+    pub(crate) content_types: Vec<String>,
+}
+
+impl Default for RequestCompression {
+    fn default() -> Self {
+        RequestCompression {
+            enabled: false,
+            encoding: ContentEncoding::Gzip,
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            content_types: DEFAULT_COMPRESSIBLE_TYPES.iter().map(|val| val.to_string()).collect(),
+        }
+    }
+}
+
+impl RequestCompression {
+    /// Parses `val` on top of `base`, the settings it does not mention are kept. `option` names the option in errors.
+    pub(crate) fn from_js(val: &JsValue, base: &RequestCompression, option: &str) -> Result<Self, JsError> {
+        let mut compression = base.clone();
+        if let Some(enabled) = val.as_bool() {
+            compression.enabled = enabled;
+            return Ok(compression);
+        }
+
+        if !val.is_object() {
+            return Err(JsError::new(&format!("expected `{}` value to be a boolean or an object", option)));
+        }
+
+        compression.enabled = true;
+        for entry in object_entries(val.unchecked_ref()).iter() {
+            let entry = js_sys::Array::from(&entry); // [key, value] result from Object.entries
+            let key = entry.get(0).as_string().ok_or(JsError::new("expected object key to be a string"))?;
+            let value = entry.get(1);
+
+            match key.as_str() {
+                "encoding" => {
+                    compression.encoding = match value.as_string().as_deref() {
+                        Some("gzip") => ContentEncoding::Gzip,
+                        Some("br") => ContentEncoding::Brotli,
+                        _ => {
+                            return Err(JsError::new(&format!("expected `{}.encoding` to be one of \"gzip\" or \"br\"", option)));
+                        }
+                    };
+                }
+
+                "threshold" => {
+                    let threshold = value
+                        .as_f64()
+                        .ok_or(JsError::new(&format!("expected `{}.threshold` value to be a number", option)))?;
+
+                    if threshold < 0.0 {
+                        return Err(JsError::new(&format!("expected `{}.threshold` value to be a positive number", option)));
+                    }
+
+                    compression.threshold = threshold as u64;
+                }
+
+                "contentTypes" => {
+                    if !value.is_instance_of::<js_sys::Array>() {
+                        return Err(JsError::new(&format!("expected `{}.contentTypes` value to be an array", option)));
+                    }
+
+                    compression.content_types = js_sys::Array::from(&value)
+                        .iter()
+                        .map(|val| {
+                            val.as_string()
+                                .map(|val| val.trim().to_ascii_lowercase())
+                                .ok_or(JsError::new(&format!("expected `{}.contentTypes` value to be a string", option)))
+                        })
+                        .collect::<Result<_, _>>()?;
+                }
+
+                _ => {
+                    // we rather pipe the issues now than have them silently ignored
+                    return Err(JsError::new(&format!("unexpected key in `{}`: {}", option, key)));
+                }
+            }
+        }
+
+        Ok(compression)
+    }
+
+    /// Compresses `body` if the compression is enabled and applies to it. `None` means the body is to be sent as is,
+    /// that is also the case when compressing does not make it smaller.
+    pub(crate) fn compress(&self, body: &[u8], content_type: &str) -> Result<Option<Vec<u8>>, String> {
+        if !self.enabled || (body.len() as u64) < self.threshold || !self.compresses(content_type) {
+            return Ok(None);
+        }
+
+        let compressed = self.encoding.encode(body)?;
+        Ok((compressed.len() < body.len()).then_some(compressed))
+    }
+
+    fn compresses(&self, content_type: &str) -> bool {
+        // the parameters, e.g. `; charset=utf-8`, play no part
+        let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        self.content_types.iter().any(|allowed| match allowed.strip_suffix("/*") {
+            Some(kind) => mime.split('/').next() == Some(kind),
+            None => *allowed == mime,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"layer8 layer8 layer8 layer8 layer8 layer8";
+
+    #[test]
+    fn decodes_gzip_and_deflate() {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(BODY).unwrap();
+        assert_eq!(decode(gzip.finish().unwrap(), Some("gzip")).unwrap(), BODY);
+
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        zlib.write_all(BODY).unwrap();
+        assert_eq!(decode(zlib.finish().unwrap(), Some("deflate")).unwrap(), BODY);
+
+        let mut raw = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        raw.write_all(BODY).unwrap();
+        assert_eq!(decode(raw.finish().unwrap(), Some("Deflate")).unwrap(), BODY);
+    }
+
+    #[test]
+    fn decodes_brotli_and_zstd() {
+        let mut brotli = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+        brotli.write_all(BODY).unwrap();
+        assert_eq!(decode(brotli.into_inner(), Some("br")).unwrap(), BODY);
+
+        // ruzstd only decodes, the frame is the output of `zstd -19 --no-check` for BODY
+        let zstd = vec![
+            0x28, 0xb5, 0x2f, 0xfd, 0x00, 0x68, 0x75, 0x00, 0x00, 0x40, 0x6c, 0x61, 0x79, 0x65, 0x72, 0x38, 0x20, 0x6c, 0x01, 0x00, 0x1a, 0x0b, 0x17,
+        ];
+        assert_eq!(decode(zstd.clone(), Some("zstd")).unwrap(), BODY);
+        assert!(decode(zstd[..zstd.len() - 4].to_vec(), Some("zstd")).is_err());
+        assert!(decode(BODY.to_vec(), Some("br")).is_err());
+    }
+
+    #[test]
+    fn undoes_stacked_codings_in_reverse() {
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        zlib.write_all(BODY).unwrap();
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&zlib.finish().unwrap()).unwrap();
+
+        assert_eq!(decode(gzip.finish().unwrap(), Some("deflate, gzip")).unwrap(), BODY);
+    }
+
+    #[test]
+    fn compresses_allowed_bodies_above_the_threshold() {
+        let compression = RequestCompression {
+            enabled: true,
+            ..Default::default()
+        };
+        let body = BODY.repeat(64);
+
+        let compressed = compression.compress(&body, "application/json; charset=utf-8").unwrap().unwrap();
+        assert_eq!(decode(compressed, Some(compression.encoding.as_str())).unwrap(), body);
+        assert!(compression.compress(&body, "text/plain").unwrap().is_some());
+
+        assert!(compression.compress(&body, "image/png").unwrap().is_none());
+        assert!(compression.compress(BODY, "application/json").unwrap().is_none());
+        assert!(RequestCompression::default().compress(&body, "application/json").unwrap().is_none());
+    }
+
+    #[test]
+    fn identity_and_unknown_codings() {
+        assert_eq!(decode(BODY.to_vec(), None).unwrap(), BODY);
+        assert_eq!(decode(BODY.to_vec(), Some("identity")).unwrap(), BODY);
+        assert!(decode(BODY.to_vec(), Some("compress")).is_err());
+        assert!(decode(BODY.to_vec(), Some("gzip")).is_err());
+    }
+}
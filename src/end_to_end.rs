@@ -0,0 +1,148 @@
+//! End-to-end encryption between the client and the provider. The tunnel's symmetric key is shared with the proxy, so
+//! on top of it the bodies are encrypted with a key derived from an ephemeral client key and the provider's long-term
+//! key, which the proxy cannot derive.
+//!
+//! The provider learns the ephemeral key from the [`E2E_KEY_HEADER`] request header and marks the responses it
+//! encrypted with the [`E2E_HEADER`] header. Only the bodies are covered, the headers, status and URL are not.
+
+use std::collections::HashMap;
+
+use layer8_primitives::crypto::{self, Jwk, generate_key_pair, jwk_from_map};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use wasm_bindgen::prelude::*;
+
+use crate::key_pinning::verify_pinned_key;
+
+/// The path the provider serves its long-term public key (a JWK) at.
+pub(crate) const PROVIDER_KEY_PATH: &str = "/.well-known/layer8/provider-key";
+
+/// The request header carrying the client's ephemeral public key, base64 encoded.
+pub(crate) const E2E_KEY_HEADER: &str = "x-layer8-e2e-key";
+
+/// The response header marking an end-to-end encrypted body.
+pub(crate) const E2E_HEADER: &str = "x-layer8-e2e";
+
+/// The response header the proxy marks the error responses it generated itself with, it has no key to seal them.
+pub(crate) const PROXY_ERROR_HEADER: &str = "x-layer8-proxy-error";
+
+/// The header set on the responses handed to the app whose body was not encrypted by the provider. Such a body may
+/// have been written by the proxy.
+pub(crate) const UNVERIFIED_HEADER: &str = "x-layer8-e2e-unverified";
+
+/// Where the provider's long-term public key comes from. Without a pinned key nor thumbprints the bodies are only
+/// encrypted up to the proxy.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ProviderKeyConfig {
+    // The value of `InitConfig.providerPublicKey`.
+    pub(crate) public_key: Option<Map<String, Value>>,
+    // The value of `InitConfig.providerKeyThumbprints`.
+    pub(crate) thumbprints: Vec<String>,
+}
+
+impl ProviderKeyConfig {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.public_key.is_some() || !self.thumbprints.is_empty()
+    }
+
+    pub(crate) fn parse_public_key(val: &JsValue) -> Result<Map<String, Value>, JsError> {
+        serde_wasm_bindgen::from_value::<Map<String, Value>>(val.clone())
+            .map_err(|e| JsError::new(&format!("expected `InitConfig.providerPublicKey` value to be a JWK object: {}", e)))
+    }
+
+    /// Fails unless `key` matches the configured thumbprints, a pinned key is checked as well.
+    pub(crate) fn verify(&self, key: &Map<String, Value>) -> Result<(), String> {
+        verify_pinned_key(key, &self.thumbprints, "provider", "InitConfig.providerKeyThumbprints")
+    }
+}
+
+/// A response body with its end-to-end encryption undone.
+#[derive(Debug, PartialEq)]
+pub(crate) struct OpenedBody {
+    pub(crate) body: Vec<u8>,
+    /// Whether the provider encrypted the body, `false` for the error responses of the proxy.
+    pub(crate) verified: bool,
+}
+
+/// The key material of the end-to-end encryption with a provider.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct EndToEnd {
+    pub(crate) provider_public_key: Jwk,
+    // (private, public), generated anew for every tunnel
+    pub(crate) ephemeral_client_key_pair: (Jwk, Jwk),
+    shared_key: Jwk,
+}
+
+impl EndToEnd {
+    /// Derives the end-to-end key from a fresh ephemeral key pair and the provider's verified public key.
+    pub(crate) fn new(provider_public_key: Map<String, Value>) -> Result<Self, String> {
+        let provider_public_key = jwk_from_map(provider_public_key)?;
+        let (private_jwk, public_jwk) = generate_key_pair(crypto::KeyUse::Ecdh)?;
+        let shared_key = private_jwk.get_ecdh_shared_secret(&provider_public_key)?;
+
+        Ok(EndToEnd {
+            provider_public_key,
+            ephemeral_client_key_pair: (private_jwk, public_jwk),
+            shared_key,
+        })
+    }
+
+    /// Encrypts the request body for the provider and advertises the ephemeral key in `headers`.
+    pub(crate) fn seal(&self, body: &[u8], headers: &mut HashMap<String, String>) -> Result<Vec<u8>, String> {
+        headers.insert(E2E_KEY_HEADER.to_string(), self.ephemeral_client_key_pair.1.export_as_base64());
+        if body.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.shared_key.symmetric_encrypt(body)
+    }
+
+    /// Decrypts a response body. `sealed` is whether the provider marked it as end-to-end encrypted, an unmarked body
+    /// could have been forged by the proxy and is refused. The exception are the error responses the proxy marked as
+    /// its own with `proxy_error`, they are passed on unverified.
+    pub(crate) fn open(&self, body: Vec<u8>, sealed: bool, proxy_error: bool, status: u16) -> Result<OpenedBody, String> {
+        if !sealed {
+            // the proxy answers on its own when the provider can't be reached or the tunnel is refused, it has no key
+            // to seal those error responses with
+            if proxy_error && status >= 400 {
+                return Ok(OpenedBody { body, verified: false });
+            }
+
+            return Err("expected the provider to end-to-end encrypt the response, refusing it".to_string());
+        }
+
+        let body = match body.is_empty() {
+            true => body,
+            false => self.shared_key.symmetric_decrypt(&body)?,
+        };
+
+        Ok(OpenedBody { body, verified: true })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_proxy_errors_pass_unsealed() {
+        let end_to_end = EndToEnd::default();
+        let unverified = |body: &[u8]| OpenedBody {
+            body: body.to_vec(),
+            verified: false,
+        };
+        assert_eq!(
+            end_to_end.open(b"bad gateway".to_vec(), false, true, 502).unwrap(),
+            unverified(b"bad gateway")
+        );
+        assert_eq!(
+            end_to_end.open(b"unauthorized".to_vec(), false, true, 401).unwrap(),
+            unverified(b"unauthorized")
+        );
+
+        // an unmarked error body could come from anyone on the way
+        assert!(end_to_end.open(b"forged".to_vec(), false, false, 500).is_err());
+        assert!(end_to_end.open(b"forged".to_vec(), false, true, 200).is_err());
+        assert!(end_to_end.open(b"forged".to_vec(), false, false, 304).is_err());
+    }
+}
@@ -3,37 +3,30 @@ use std::{cell::RefCell, collections::HashMap};
 
 use wasm_bindgen::prelude::*;
 
+use crate::cache::{AssetCache, CacheBackend};
+use crate::js_glue::js_imports::{delete_unused_db, request_persistent_storage};
 use crate::js_imports_prelude::*;
 use crate::network_state::{NetworkState, NetworkStateHandler};
-use crate::types::{DbCache, InitConfig, Uniqueness};
+use crate::tab_share;
+use crate::types::InitConfig;
 
 const INTERCEPTOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// The prefix of the per-provider cache databases. It used to name the single database all the providers shared.
 pub(crate) const INDEXED_DB_CACHE: &str = "_layer8cache";
 /// The cache time-to-live for the IndexedDB cache is 2 days.
-pub(crate) const INDEXED_DB_CACHE_TTL: i32 = 60 * 60 * 24 * 2 * 1000; // 2 days in milliseconds
+pub(crate) const INDEXED_DB_CACHE_TTL: f64 = 60.0 * 60.0 * 24.0 * 2.0 * 1000.0; // 2 days in milliseconds
 
 thread_local! {
     /// This is the cache for all the NetworkStates present. It is the single source of truth for the state of the system.
     pub(crate) static PROVIDER_REGISTER: RefCell<HashMap<String, NetworkState>> = RefCell::new(HashMap::new());
 
+    /// The `CacheMigrationReport` of the last initialization of each provider, see `NetworkStateHandler.cacheMigration`.
+    pub(crate) static CACHE_MIGRATIONS: RefCell<HashMap<String, JsValue>> = RefCell::new(HashMap::new());
+
     static COUNTER: RefCell<i32> = const { RefCell::new(0) };
 
-    /// The cache instantiates with the `_layer8cache` IndexedDB.
-    static INDEXED_DBS: HashMap<String, crate::types::DbCache> = HashMap::from([
-        (
-            INDEXED_DB_CACHE.to_string(),
-            DbCache {
-                store: "static".to_string(),
-                key_path: "url".to_string(),
-                indexes: crate::types::Indexes{
-                    url: Uniqueness { unique: true },
-                    _exp: Uniqueness { unique: false },
-                    body: Uniqueness { unique: false },
-                    _type: Uniqueness { unique: false },
-                }
-            },
-        )
-    ]);
+    // Whether the shared cache database predating the per-provider ones was deleted during this page load.
+    static LEGACY_CACHE_DELETED: RefCell<bool> = const { RefCell::new(false) };
 }
 
 /// This function is called to check if the encrypted tunnel is open.
@@ -78,6 +71,9 @@ pub async fn persistence_check() -> i32 {
 ///
 /// If a client for the provider already exists, no calls are made to the proxy.
 ///
+/// The IndexedDB cache is migrated to the current schema first, the handler the promise resolves with reports the
+/// applied migrations in `cacheMigration`. A migration that can't be applied rejects the promise.
+///
 /// The config object is expected to have the following structure:
 /// ```js
 /// export interface InitConfig {
@@ -87,10 +83,35 @@ pub async fn persistence_check() -> i32 {
 ///    proxy: string;
 ///    // Deprecated: `staticPath` is used for backwards compatibility, use `staticPaths` instead.
 ///    staticPath: string | undefined;
-///    // The list of paths to serve static assets from.
-///    staticPaths: string[] | undefined;
-///    // The maximum size of assets to cache. The value is in MB.
+///    // The rules of the paths to serve static assets from, matched against the URL path.
+///    // A string is a path prefix, see `StaticPathRule` for the structured rules. The strings, and `staticPath`, no
+///    // longer match anywhere in the URL: they match from the root of the path, `"static"` is read as `"/static"`.
+///    staticPaths: (string | StaticPathRule)[] | undefined;
+///    // The maximum size of assets to cache for this provider. The value is in MB.
 ///    cacheAssetLimit: number | undefined;
+///    // The time-to-live of the cached assets for this provider. The value is in seconds.
+///    cacheTtl: number | undefined;
+///    // The backend to cache the assets in. Defaults to "indexedDB".
+///    cacheBackend: "indexedDB" | "cacheStorage" | "memory" | undefined;
+///    // Asks the browser to persist the origin's storage so cached assets are not evicted under storage pressure.
+///    persistStorage: boolean | undefined;
+///    // Compresses request bodies before they are encrypted, off by default.
+///    // Individual requests can override it with the `compress` fetch option.
+///    requestCompression: boolean | RequestCompression | undefined;
+///    // The RFC 7638 SHA-256 thumbprints of the proxy's accepted ECDH keys, the tunnel is refused on a mismatch.
+///    proxyPublicKeys: string[] | undefined;
+///    // The provider's long-term ECDH public key, enables the end-to-end encryption of the bodies with the provider.
+///    // The headers, status and URL are not covered, see `InitConfig`.
+///    providerPublicKey: JsonWebKey | undefined;
+///    // The thumbprints of the provider's accepted keys. Without `providerPublicKey` the key is fetched from the
+///    // provider's `/.well-known/layer8/provider-key` and verified against them.
+///    providerKeyThumbprints: string[] | undefined;
+///    // Keeps the tunnel session across page loads, it is restored instead of establishing a new tunnel while the
+///    // up-JWT is valid. The cached assets are scoped to the tunnel session, they only outlive the page load with it.
+///    persistSession: boolean | undefined;
+///    // Shares one tunnel per provider between the tabs of the origin, the other tabs forward their requests to the
+///    // tab that established it.
+///    shareTunnel: boolean | undefined;
 /// }
 /// ```
 #[allow(non_snake_case)]
@@ -99,32 +120,70 @@ pub async fn init_encrypted_tunnel(init_config: js_sys::Object, _: Option<String
     console_log!(&format!("Interceptor version is {}", INTERCEPTOR_VERSION));
 
     let init_config = InitConfig::new(init_config).await?;
+    let provider = get_base_url(&init_config.provider);
 
-    let cache = INDEXED_DBS.with(|v| {
-        let val = v.get(INDEXED_DB_CACHE).expect_throw("expected indexed db to be present; qed");
-        val.clone()
-    });
+    // the entries of the shared database can't be told apart per provider, it is dropped instead of migrated
+    if !LEGACY_CACHE_DELETED.replace(true) {
+        if let Err(_e) = delete_unused_db(INDEXED_DB_CACHE).await {
+            console_error!(&format!(
+                "Failed to delete the legacy cache database {}. Error: {:?}",
+                INDEXED_DB_CACHE, _e
+            ));
+        }
+    }
 
-    clear_expired_cache(INDEXED_DB_CACHE, cache);
+    let cache = CacheBackend::new(init_config.cache_backend, &cache_db_name(&provider));
+    let report = match &cache {
+        // the schema must be current before the cache is touched, a failing migration fails the initialization
+        CacheBackend::IndexedDb(cache) => cache.migrate().await.map_err(|e| {
+            console_error!(&format!("Failed to migrate the cache of provider: {}. Error: {}", provider, e));
+            JsError::new(&e)
+        })?,
+        _ => JsValue::NULL,
+    };
+    CACHE_MIGRATIONS.with_borrow_mut(|map| map.insert(provider.clone(), report));
 
-    let provider = get_base_url(&init_config.provider);
+    if let Err(_e) = cache.evict(js_sys::Date::now()).await {
+        console_error!(&format!("Failed to clear the expired cache of provider: {}. Error: {}", provider, _e));
+    }
+
+    // the browser is free to deny the request, caching then works as usual but can be evicted under storage pressure
+    if init_config.persist_storage {
+        match request_persistent_storage().await {
+            Ok(_persisted) => console_log!(&format!("Persistent storage granted: {:?}", _persisted.as_bool())),
+            Err(_e) => console_error!(&format!("Failed to request persistent storage: {:?}", _e)),
+        }
+    }
+
+    if init_config.share_tunnel {
+        tab_share::join(&provider, &init_config).await.map_err(|e| {
+            console_error!(&format!("Failed to share the tunnel with provider: {}. Error: {}", provider, e));
+            JsError::new(&e)
+        })?;
+
+        return Ok(NetworkStateHandler(provider));
+    }
 
     // before we initialize creation of a client check if one is already linked with the provider
-    if PROVIDER_REGISTER.with_borrow_mut(|map| map.get(&provider).cloned()).is_none() {
+    if PROVIDER_REGISTER.with_borrow_mut(|map| map.get(&provider).cloned()).is_none()
+        && NetworkState::restore(&provider, &init_config).await.is_none()
+    {
         console_log!(&format!("Establishing encrypted tunnel with provider: {}", provider));
-        let mut network_state = NetworkState::new(&provider, &init_config.proxy).await.map_err(|e| {
+        NetworkState::new(&provider, &init_config).await.map_err(|e| {
             console_error!(&format!("Failed to establish encrypted tunnel with provider: {}. Error: {}", provider, e));
             JsError::new(&e)
         })?;
-
-        network_state.static_paths = init_config.static_paths;
-        PROVIDER_REGISTER.with_borrow_mut(|map| map.insert(provider.clone(), network_state.clone()));
     }
 
     console_log!(&format!("Encrypted tunnel established with provider: {}", provider));
     Ok(NetworkStateHandler(provider))
 }
 
+/// The cache is namespaced per provider, clearing a provider's cache leaves the other providers untouched.
+pub(crate) fn cache_db_name(provider: &str) -> String {
+    format!("{}::{}", INDEXED_DB_CACHE, get_base_url(provider))
+}
+
 pub(crate) fn get_base_url(url: &str) -> String {
     console_log!(&format!("Rebuilding URL: `{}`", url));
 
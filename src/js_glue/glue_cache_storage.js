@@ -0,0 +1,123 @@
+// The Cache Storage API keys entries by Request, we map the cache keys onto synthetic same-origin URLs.
+function request_for(key) {
+    return new Request('/__layer8_cache__/' + encodeURIComponent(key))
+}
+
+export async function cs_get_asset(cache_name, key) {
+    const cache = await caches.open(cache_name)
+    const response = await cache.match(request_for(key))
+    if (!response)
+        return null
+
+    return {
+        body: new Uint8Array(await response.arrayBuffer()),
+        _type: response.headers.get('content-type'),
+        _exp: Number(response.headers.get('x-layer8-exp')),
+        _used: Number(response.headers.get('x-layer8-used')) || 0,
+    }
+}
+
+export async function cs_put_asset(cache_name, key, body, file_type, _exp, _used) {
+    const cache = await caches.open(cache_name)
+    try {
+        await cache.put(request_for(key), new Response(body, {
+            headers: {
+                'content-type': file_type,
+                'x-layer8-exp': String(_exp),
+                'x-layer8-size': String(body.byteLength),
+                'x-layer8-used': String(_used),
+            }
+        }))
+    } catch (error) {
+        // quota errors are surfaced as a plain string so they can be told apart from the other failures on the Rust side
+        if (error && error.name === 'QuotaExceededError')
+            throw 'QuotaExceededError'
+        throw error
+    }
+}
+
+export async function cs_delete_asset(cache_name, key) {
+    const cache = await caches.open(cache_name)
+    await cache.delete(request_for(key))
+}
+
+// The metadata lives in the headers of the cached response, which can't be changed in place.
+export async function cs_touch_asset(cache_name, key, now) {
+    const cache = await caches.open(cache_name)
+    const request = request_for(key)
+    const response = await cache.match(request)
+    if (!response)
+        return
+
+    const headers = new Headers(response.headers)
+    headers.set('x-layer8-used', String(now))
+    await cache.put(request, new Response(await response.blob(), { headers: headers }))
+}
+
+// Drops the least recently used entries until `bytes` bytes are freed or the cache is empty, returns the number of
+// bytes freed.
+export async function cs_evict_lru(cache_name, bytes) {
+    const cache = await caches.open(cache_name)
+    const entries = []
+    for (const request of await cache.keys()) {
+        const response = await cache.match(request)
+        if (response)
+            entries.push({
+                request: request,
+                used: Number(response.headers.get('x-layer8-used')) || 0,
+                size: Number(response.headers.get('x-layer8-size')) || 0,
+            })
+    }
+    entries.sort((a, b) => a.used - b.used)
+
+    let freed = 0
+    for (const entry of entries) {
+        if (freed >= bytes)
+            break
+        await cache.delete(entry.request)
+        freed += entry.size
+    }
+
+    return freed
+}
+
+// returns the size of the cache in bytes
+export async function cs_cache_size(cache_name) {
+    const cache = await caches.open(cache_name)
+    let size = 0
+    for (const request of await cache.keys()) {
+        const response = await cache.match(request)
+        if (response)
+            size += Number(response.headers.get('x-layer8-size')) || 0
+    }
+
+    return size
+}
+
+// Drops the entries that have expired by `now`, returns the number of bytes freed.
+export async function cs_evict(cache_name, now) {
+    const cache = await caches.open(cache_name)
+    let freed = 0
+    for (const request of await cache.keys()) {
+        const response = await cache.match(request)
+        if (response && Number(response.headers.get('x-layer8-exp')) <= now) {
+            await cache.delete(request)
+            freed += Number(response.headers.get('x-layer8-size')) || 0
+        }
+    }
+
+    return freed
+}
+
+export async function cs_retain_prefix(cache_name, prefix) {
+    const cache = await caches.open(cache_name)
+    const path = new URL(request_for(prefix).url).pathname
+    for (const request of await cache.keys()) {
+        if (!new URL(request.url).pathname.startsWith(path))
+            await cache.delete(request)
+    }
+}
+
+export async function cs_clear(cache_name) {
+    await caches.delete(cache_name)
+}
@@ -1,6 +1,7 @@
 // We have this here as there is no native support for IndexedDB in wasm_bindgen: <https://github.com/rustwasm/gloo/issues/68#issuecomment-606951683>
 
-function open_db(db_name, db_cache) {
+// Opens the database at its current version. The schema is owned by the migrations, see `run_migrations`.
+function open_db(db_name) {
     if (!db_name) {
         console.error('The db_name is required.');
         return null;
@@ -8,188 +9,414 @@ function open_db(db_name, db_cache) {
 
     let db;
     try {
-        db = window.indexedDB.open(db_name, 2)
+        db = globalThis.indexedDB.open(db_name)
     } catch (e) {
         console.error('Error opening IndexedDB database: ', e)
         return null
     }
 
-    // if the db_cache object is not provided, return the db object
-    if (!db_cache) {
-        return db
-    }
+    return db
+}
 
-    db.onupgradeneeded = function () {
-        var objectStore = db.result.createObjectStore(db_cache.store, {
-            keyPath: db_cache.key_path,
-        })
+// The named entry transforms available to `rewrite_entries` migration steps. A transform returns the rewritten entry,
+// or `null` to drop it.
+const TRANSFORMS = {
+    record_size: function (entry) {
+        entry.size = entry.body.byteLength
+        return entry
+    },
 
-        objectStore.createIndex('url', 'url', {
-            unique: db_cache.indexes.url.unique
-        })
-        objectStore.createIndex('_exp', '_exp', {
-            unique: db_cache.indexes._exp.unique
-        })
-        objectStore.createIndex('body', 'body', {
-            unique: db_cache.indexes.body.unique
-        })
-        objectStore.createIndex('_type', '_type', {
-            unique: db_cache.indexes._type.unique
-        })
-    }
+    // the entries without a recorded use are left out of the `_used` index otherwise
+    record_use: function (entry) {
+        if (typeof entry._used !== 'number')
+            entry._used = 0
+        return entry
+    },
+}
 
-    return db
+// The schema operations a migration step can perform, see `MigrationStep` in `src/cache/migrations.rs`.
+const STEPS = {
+    create_store: function (db, transaction, step) {
+        if (!db.objectStoreNames.contains(step.store))
+            db.createObjectStore(step.store, { keyPath: step.key_path })
+    },
+
+    create_index: function (db, transaction, step) {
+        var store = transaction.objectStore(step.store)
+        if (!store.indexNames.contains(step.name))
+            store.createIndex(step.name, step.key_path, { unique: step.unique })
+    },
+
+    delete_index: function (db, transaction, step) {
+        var store = transaction.objectStore(step.store)
+        if (store.indexNames.contains(step.name))
+            store.deleteIndex(step.name)
+    },
+
+    rewrite_entries: function (db, transaction, step) {
+        var transform = TRANSFORMS[step.transform]
+        if (!transform)
+            throw new Error('Unknown migration transform: ' + step.transform)
+
+        transaction.objectStore(step.store).openCursor().onsuccess = function (event) {
+            var cursor = event.target.result
+            if (!cursor)
+                return
+
+            var entry = transform(cursor.value)
+            if (entry === null)
+                cursor.delete()
+            else
+                cursor.update(entry)
+            cursor.continue()
+        }
+    },
+
+    rebuild_store: function (db, transaction, step) {
+        if (db.objectStoreNames.contains(step.store))
+            db.deleteObjectStore(step.store)
+        db.createObjectStore(step.store, { keyPath: step.key_path })
+    },
 }
 
-// returns -1 if the cache is full otherwise returns the dbSize
-function cached(dbName, limit) {
+// Resolves with the `CacheMigrationReport` of the upgrade, see `src/types.rs`.
+function upgrade(db_name, migrations, version) {
     return new Promise((resolve, reject) => {
-        let db = open_db(dbName)
-        if (!db) {
-            console.error('Error opening IndexedDB database')
-            return resolve(null);
-        }
-
-        var size = 0;
-        db.onsuccess = function (event) {
-            var db = event.target.result;
-            var transaction = db.transaction('static', 'readwrite');
-            var store = transaction.objectStore('static');
-            var cursor = store.index('body').openCursor();
-            cursor.onsuccess = function (event) {
-                var cursor = event.target.result
-                if (!cursor)
-                    return resolve(size / 1024 / 1024);
+        let request;
+        try {
+            request = globalThis.indexedDB.open(db_name, version)
+        } catch (e) {
+            return reject(e)
+        }
 
-                var storedObject = cursor.value;
-                size += storedObject.body.length;
+        var from = null
+        var applied = []
+        request.onupgradeneeded = function (event) {
+            var db = request.result
+            var transaction = request.transaction
+            from = event.oldVersion
 
-                // if the cache is full lets return -1; short-circuit
-                if (limit !== -1 && limit < (size / 1024 / 1024)) {
-                    console.log("Cache size is: ", (size / 1024 / 1024))
-                    return resolve(-1)
-                }
+            try {
+                for (const migration of migrations) {
+                    if (migration.version <= event.oldVersion || migration.version > version)
+                        continue
 
-                cursor.continue();
-            }.bind(this);
-            cursor.onerror = function (err) {
-                reject("error in " + dbName + ": " + err);
+                    applied.push({ version: migration.version, description: migration.description })
+                    for (const step of migration.steps)
+                        STEPS[step.op](db, transaction, step)
+                }
+            } catch (e) {
+                // rolls back every step of the upgrade, the database stays at its previous version
+                transaction.abort()
+                reject(e)
             }
         }
-    });
-};
 
-// Interacts with the IndexedDB method to clear expired cache
-export function clear_expired_cache(db_name, db_cache) {
-    let db = open_db(db_name, db_cache)
-    if (!db)
-        return
+        request.onsuccess = function () {
+            request.result.close()
+            // without an upgrade the database was already at `version`
+            resolve({ database: db_name, from: from === null ? version : from, to: version, migrations: applied, rebuilt: false })
+        }
 
-    db.onsuccess = function (event) {
-        var db = event.target.result
-        var transaction = db.transaction('static', 'readwrite')
-        var store = transaction.objectStore('static')
-        var index = store.index('_exp')
-        var bound = IDBKeyRange.upperBound(Date.now())
+        request.onerror = function (event) {
+            reject(event.target.error)
+        }
 
-        index.openCursor(bound).onsuccess = function (event) {
-            var cursor = event.target.result
-            if (cursor) {
-                store.delete(cursor.primaryKey)
-                cursor.continue()
-            }
+        request.onblocked = function () {
+            console.log('Upgrade of ' + db_name + ' is blocked by an open connection in another tab, waiting...')
         }
-    }
+    })
 }
 
-// Interacts with the IndexedDB method transact with the cache
-export async function serve_static(db_name, body, asset_size_limit, file_type, url, _exp) {
-    // check if we have reached out caching limit
-    const dbSize = await cached(db_name, asset_size_limit);
-    if (Math.floor(dbSize) < 0) {
-        console.log(`Storage limit ${asset_size_limit} MB reached, not caching asset...`)
-        const blob = new Blob([body], {
-            type: file_type
-        });
+function delete_db(db_name) {
+    return new Promise((resolve, reject) => {
+        var request = globalThis.indexedDB.deleteDatabase(db_name)
+        request.onsuccess = function () {
+            resolve(null)
+        }
+        request.onerror = function (event) {
+            reject(event.target.error)
+        }
+    })
+}
+
+// Deletes a database the cache no longer uses. Resolves once the deletion is done or blocked; a connection left open in
+// another tab only delays it, the database is deleted as soon as that connection closes.
+export function delete_unused_db(db_name) {
+    return new Promise((resolve) => {
+        var request = globalThis.indexedDB.deleteDatabase(db_name)
+        request.onsuccess = function () {
+            resolve(true)
+        }
+        request.onerror = function (event) {
+            console.error('Error deleting IndexedDB database ' + db_name + ': ', event.target.error)
+            resolve(false)
+        }
+        request.onblocked = function () {
+            resolve(false)
+        }
+    })
+}
+
+// Brings the database up to `version` by applying the pending migrations. If the upgrade fails the cache is wiped and
+// rebuilt from the first migration; losing cached assets is preferable to a cache that can't be opened.
+export async function run_migrations(db_name, migrations, version) {
+    try {
+        return await upgrade(db_name, migrations, version)
+    } catch (e) {
+        console.error('Migrating ' + db_name + ' failed, rebuilding the cache: ', e)
 
-        return URL.createObjectURL(blob)
+        await delete_db(db_name)
+        const report = await upgrade(db_name, migrations, version)
+        report.from = null
+        report.rebuilt = true
+        return report
     }
+}
 
-    let db = open_db(db_name, body)
-    if (!db)
-        return
+// Quota errors are surfaced as a plain string so they can be told apart from the other failures on the Rust side.
+function storage_error(error) {
+    if (error && error.name === 'QuotaExceededError')
+        return 'QuotaExceededError'
+    return error
+}
 
-    db.onsuccess = function (event) {
+// Opens the database and runs `run` with a transaction on the `static` store. The connection is closed once the
+// transaction settles, and as soon as an `upgrade` in another tab asks for it so the upgrade is not blocked.
+function with_transaction(db_name, mode, reject, run) {
+    let request = open_db(db_name)
+    if (!request)
+        return reject('Error opening IndexedDB database: ' + db_name)
+
+    request.onsuccess = function (event) {
         var db = event.target.result
-        var transaction = db.transaction(['static'], 'readwrite')
-        var store = transaction.objectStore('static')
+        db.onversionchange = function () {
+            db.close()
+        }
 
+        var transaction
         try {
-            store.put(
-                {
-                    url: url,
-                    body: body,
-                    _type: file_type,
-                    _exp: Date.now() + _exp
-                },
-            )
-        } catch (error) {
-            console.log(error)
+            transaction = db.transaction(['static'], mode)
+        } catch (e) {
+            // the store is missing until the migrations ran, or the connection was closed for an upgrade
+            db.close()
+            return reject(e)
         }
-    }
 
-    const blob = new Blob([body], {
-        type: file_type
-    });
+        transaction.addEventListener('complete', () => db.close())
+        transaction.addEventListener('abort', () => db.close())
+        transaction.addEventListener('error', () => db.close())
+        try {
+            run(transaction)
+        } catch (e) {
+            transaction.abort()
+            reject(e)
+        }
+    }
 
-    return URL.createObjectURL(blob)
+    request.onerror = function (event) {
+        console.log('Error opening database: ', event.target.error)
+        reject(event.target.error)
+    }
 }
 
-export function check_if_exists(db_name, url) {
+// Runs `operation` against the `static` store and resolves with the result of the request it returns, once the
+// transaction has committed.
+function transact(db_name, mode, operation) {
     return new Promise((resolve, reject) => {
-        let db = open_db(db_name);
-        if (!db)
-            return resolve(null);
+        with_transaction(db_name, mode, reject, function (transaction) {
+            var request = operation(transaction.objectStore('static'))
 
-        db.onsuccess = function (event) {
-            var db = event.target.result;
-            var transaction = db.transaction(['static'], 'readonly');
-            var store = transaction.objectStore('static');
-            var index = store.index('url');
-            var request = index.get(url);
+            transaction.oncomplete = function () {
+                resolve(request.result)
+            }
+            // quota errors abort the transaction after the request itself succeeded
+            transaction.onabort = function () {
+                reject(storage_error(transaction.error))
+            }
+            request.onerror = function (event) {
+                reject(storage_error(event.target.error))
+            }
+        })
+    })
+}
+
+// Walks the cursor `open` opens on the `static` store, calling `visit` with each cursor until it returns `false`.
+// Resolves once the transaction has committed.
+function walk(db_name, mode, open, visit) {
+    return new Promise((resolve, reject) => {
+        with_transaction(db_name, mode, reject, function (transaction) {
+            var request = open(transaction.objectStore('static'))
 
             request.onsuccess = function (event) {
-                if (request.result && request.result.body) {
-                    const blob = new Blob([request.result.body], { type: request.result._type });
-                    resolve(URL.createObjectURL(blob));
-                    return;
-                } else {
-                    console.log('Asset not found in cache');
-                    resolve(null);
-                }
-            };
+                var cursor = event.target.result
+                if (!cursor)
+                    return
 
+                if (visit(cursor) !== false)
+                    cursor.continue()
+            }
             request.onerror = function (event) {
-                console.log('Error retrieving asset from cache: ', event.target.error);
-                reject(event.target.error);
-            };
-        };
-
-        db.onerror = function (event) {
-            console.log('Error opening database: ', event.target.error);
-            reject(event.target.error);
-        };
-    });
+                reject(event.target.error)
+            }
+            transaction.oncomplete = function () {
+                resolve(null)
+            }
+            transaction.onabort = function () {
+                reject(transaction.error)
+            }
+        })
+    })
+}
+
+// Interacts with the IndexedDB method to clear expired cache, resolves with the number of bytes freed
+export async function clear_expired_cache(db_name, now) {
+    let freed = 0
+    await walk(db_name, 'readwrite', store => store.index('_exp').openCursor(IDBKeyRange.upperBound(now)), function (cursor) {
+        freed += cursor.value.size || 0
+        cursor.delete()
+    })
+
+    return freed
+}
+
+// Drops the entries of every session but `session`, they were cached through a tunnel that has been replaced
+export function clear_other_sessions(db_name, session) {
+    return walk(db_name, 'readwrite', store => store.openCursor(), function (cursor) {
+        if (cursor.value.session !== session)
+            cursor.delete()
+    })
+}
+
+// Drops every entry in the database, other databases are not affected
+export function clear_cache(db_name) {
+    return new Promise((resolve, reject) => {
+        let request = open_db(db_name)
+        if (!request)
+            return resolve(null)
+
+        request.onsuccess = function (event) {
+            var db = event.target.result
+            db.onversionchange = function () {
+                db.close()
+            }
+            if (!db.objectStoreNames.contains('static')) {
+                db.close()
+                return resolve(null)
+            }
+
+            var transaction
+            try {
+                transaction = db.transaction('static', 'readwrite')
+                transaction.objectStore('static').clear()
+            } catch (e) {
+                db.close()
+                return reject(e)
+            }
+
+            transaction.oncomplete = function () {
+                db.close()
+                resolve(null)
+            }
+            transaction.onabort = function () {
+                db.close()
+                reject(transaction.error)
+            }
+        }
+
+        request.onerror = function (event) {
+            reject(event.target.error)
+        }
+    })
+}
+
+export function get_asset(db_name, key) {
+    return transact(db_name, 'readonly', store => store.get(key))
+}
+
+export function put_asset(db_name, key, session, url, body, file_type, _exp, _used) {
+    return transact(db_name, 'readwrite', store => store.put({
+        key: key,
+        session: session,
+        url: url,
+        body: body,
+        size: body.byteLength,
+        _type: file_type,
+        _exp: _exp,
+        _used: _used
+    }))
+}
+
+export function delete_asset(db_name, key) {
+    return transact(db_name, 'readwrite', store => store.delete(key))
+}
+
+export function touch_asset(db_name, key, now) {
+    return transact(db_name, 'readwrite', store => {
+        var request = store.openCursor(key)
+        request.onsuccess = function () {
+            var cursor = request.result
+            if (!cursor)
+                return
+
+            var entry = cursor.value
+            entry._used = now
+            cursor.update(entry)
+        }
+        return request
+    })
+}
+
+// Drops the least recently used entries until `bytes` bytes are freed or the store is empty, resolves with the number
+// of bytes freed. Only the `size` and `_used` indexes are read, the bodies are not loaded.
+export async function evict_lru(db_name, bytes) {
+    const sizes = new Map()
+    await walk(db_name, 'readonly', store => store.index('size').openKeyCursor(), function (cursor) {
+        sizes.set(cursor.primaryKey, cursor.key)
+    })
+
+    let freed = 0
+    await walk(db_name, 'readwrite', store => store.index('_used').openKeyCursor(), function (cursor) {
+        if (freed >= bytes)
+            return false
+
+        cursor.source.objectStore.delete(cursor.primaryKey)
+        freed += sizes.get(cursor.primaryKey) || 0
+    })
+
+    return freed
 }
 
+// returns the size of the cached bodies in bytes, as recorded when they were written. Only the `size` index is read,
+// the bodies are not loaded.
+export async function cache_size(db_name) {
+    let size = 0
+    await walk(db_name, 'readonly', store => store.index('size').openKeyCursor(), function (cursor) {
+        size += cursor.key
+    })
+
+    return size
+}
+
+// resolves with the `{ quota, usage }` estimate of the origin, in bytes
 export function get_storage_estimate() {
     return new Promise((resolve, reject) => {
         navigator.storage.estimate().then(estimate => {
-            const storage_estimate = Math.floor(estimate.quota / 1024 / 1024)
-            resolve(storage_estimate);
+            resolve({ quota: estimate.quota, usage: estimate.usage });
         }).catch(error => {
             console.error('Error getting storage estimate: ', error);
             reject(error);
         });
     });
 }
+
+// resolves with whether the origin's storage is persisted, it is then exempt from eviction under storage pressure
+export async function request_persistent_storage() {
+    if (!navigator.storage || !navigator.storage.persist)
+        return false
+
+    if (await navigator.storage.persisted())
+        return true
+
+    return await navigator.storage.persist()
+}
@@ -0,0 +1,175 @@
+// Feeds a MediaSource with byte ranges of a media asset fetched through the encrypted tunnel. Given the media element,
+// a seek past the buffered media restarts the download near the target and the media played long ago is dropped.
+
+// Media played this long ago is removed from the buffer, browsers refuse appends once it is full. The value is in
+// seconds.
+const BUFFER_BEHIND = 30
+
+// `Content-Range: bytes 0-1023/4096`, the total is `null` if the provider does not know it (`*`)
+function total_from_content_range(header) {
+    if (!header)
+        return null
+
+    const total = header.split('/')[1]
+    return total && total !== '*' ? Number(total) : null
+}
+
+// Runs `operation` on the source buffer and waits for it to finish, the listener of the other outcome is removed.
+function update(source_buffer, operation) {
+    return new Promise((resolve, reject) => {
+        const on_update_end = () => {
+            source_buffer.removeEventListener('error', on_error)
+            resolve()
+        }
+        const on_error = () => {
+            source_buffer.removeEventListener('updateend', on_update_end)
+            reject(new Error('failed to update the media buffer'))
+        }
+
+        source_buffer.addEventListener('updateend', on_update_end, { once: true })
+        source_buffer.addEventListener('error', on_error, { once: true })
+        try {
+            operation()
+        } catch (error) {
+            source_buffer.removeEventListener('updateend', on_update_end)
+            source_buffer.removeEventListener('error', on_error)
+            reject(error)
+        }
+    })
+}
+
+function is_buffered(source_buffer, time) {
+    const buffered = source_buffer.buffered
+    for (let i = 0; i < buffered.length; i++) {
+        if (buffered.start(i) <= time && time < buffered.end(i))
+            return true
+    }
+
+    return false
+}
+
+// After a seek the download starts in the middle of a media segment, the parser picks up again at the next one: a
+// WebM Cluster, or the `moof` box of a fragmented MP4 which is preceded by its 4 byte size. Returns -1 if the chunk
+// holds no segment start.
+function segment_start(chunk, mime_type) {
+    const mp4 = mime_type.includes('mp4')
+    const pattern = mp4 ? [0x6d, 0x6f, 0x6f, 0x66] : [0x1f, 0x43, 0xb6, 0x75]
+    const offset = mp4 ? 4 : 0
+
+    for (let i = offset; i + pattern.length <= chunk.byteLength; i++) {
+        if (pattern.every((byte, j) => chunk[i + j] === byte))
+            return i - offset
+    }
+
+    return -1
+}
+
+async function trim(source_buffer, media) {
+    const buffered = source_buffer.buffered
+    const behind = media.currentTime - BUFFER_BEHIND
+    if (buffered.length === 0 || buffered.start(0) >= behind)
+        return
+
+    await update(source_buffer, () => source_buffer.remove(buffered.start(0), behind))
+}
+
+// Downloads from `state.offset` until the end of the asset, or until a seek bumps `state.generation`.
+async function download(media_source, source_buffer, mime_type, fetch_chunk, chunk_size, media, state) {
+    const generation = state.generation
+    const superseded = () => generation !== state.generation
+
+    while (!superseded()) {
+        const offset = state.offset
+        const response = await fetch_chunk(offset, offset + chunk_size - 1)
+        if (superseded() || response.status === 416)
+            return // the previous chunk ended exactly at the end of the asset
+
+        if (!response.ok)
+            throw new Error('failed to fetch the media chunk, status: ' + response.status)
+
+        const body = new Uint8Array(await response.arrayBuffer())
+        if (superseded())
+            return
+
+        // the provider ignored the range and sent the whole asset
+        const whole = response.status === 200
+        state.offset = whole ? body.byteLength : offset + body.byteLength
+        state.total = whole ? body.byteLength : total_from_content_range(response.headers.get('content-range')) ?? state.total
+
+        let chunk = body
+        if (state.resync && !whole) {
+            const start = segment_start(body, mime_type)
+            chunk = body.subarray(start < 0 ? body.byteLength : start)
+            if (start >= 0) {
+                state.resync = false
+                // the parser still expects the rest of the segment appended before the seek
+                if (media_source.readyState === 'open')
+                    source_buffer.abort()
+            }
+        }
+
+        if (chunk.byteLength > 0) {
+            if (media)
+                await trim(source_buffer, media)
+            if (superseded())
+                return
+
+            await update(source_buffer, () => source_buffer.appendBuffer(chunk))
+        }
+
+        if (whole || body.byteLength < chunk_size || (state.total !== null && state.offset >= state.total))
+            return
+    }
+}
+
+async function feed(media_source, mime_type, fetch_chunk, chunk_size, media) {
+    const source_buffer = media_source.addSourceBuffer(mime_type)
+    const state = { offset: 0, total: null, generation: 0, resync: false, wake: null }
+
+    if (media) {
+        media.addEventListener('seeking', () => {
+            const duration = media_source.duration
+            if (is_buffered(source_buffer, media.currentTime) || state.total === null || !(duration > 0) || !Number.isFinite(duration))
+                return
+
+            // the byte position is estimated from the share of the duration, the download resyncs on the next segment
+            state.offset = Math.floor(state.total * Math.min(media.currentTime / duration, 1))
+            state.generation++
+            state.resync = true
+            if (state.wake)
+                state.wake()
+        })
+    }
+
+    while (true) {
+        const generation = state.generation
+        await download(media_source, source_buffer, mime_type, fetch_chunk, chunk_size, media, state)
+        if (generation !== state.generation)
+            continue
+
+        if (media_source.readyState === 'open')
+            media_source.endOfStream()
+        if (!media)
+            return
+
+        // the ended stream is reopened by the next append, once a seek lands outside the buffered media
+        await new Promise(resolve => state.wake = resolve)
+        state.wake = null
+    }
+}
+
+export function create_media_source_url(mime_type, fetch_chunk, chunk_size, media) {
+    if (!MediaSource.isTypeSupported(mime_type))
+        throw new Error('unsupported media type: ' + mime_type)
+
+    const media_source = new MediaSource()
+    media_source.addEventListener('sourceopen', () => {
+        feed(media_source, mime_type, fetch_chunk, chunk_size, media).catch(error => {
+            console.error('Error streaming media asset: ', error)
+            if (media_source.readyState === 'open')
+                media_source.endOfStream('network')
+        })
+    }, { once: true })
+
+    return URL.createObjectURL(media_source)
+}
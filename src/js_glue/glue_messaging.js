@@ -0,0 +1,73 @@
+// The messages the tab group and the worker bridge post: request options and responses are turned into
+// structured-cloneable values on one side and back on the other.
+
+// Responses with these statuses can't carry a body.
+const NULL_BODY_STATUSES = [101, 204, 205, 304]
+
+export function error_message(error) {
+    return String(error && error.message || error)
+}
+
+// Only structured-cloneable options can be posted: `Headers` are turned into a plain object, the signal is left out
+// and a FormData body is posted as its entries, `restored_options` rebuilds it so the interceptor on the other side
+// encodes it. With a `transfer` list a binary body is copied and the copy transferred, the caller's buffer is left
+// attached.
+export function cloneable_options(options, transfer) {
+    if (!options)
+        return options
+
+    const cloneable = Object.assign({}, options)
+    delete cloneable.signal
+    if (options.headers !== undefined)
+        cloneable.headers = Object.fromEntries(new Headers(options.headers))
+
+    const body = options.body
+    if (body instanceof FormData) {
+        delete cloneable.body
+        // the filenames are posted along, in case the Files are cloned as Blobs
+        cloneable.formEntries = [...body].map(([name, value]) => typeof value === 'string' ? [name, value] : [name, value, value.name])
+    } else if (transfer && body instanceof ArrayBuffer) {
+        cloneable.body = body.slice(0)
+    } else if (transfer && ArrayBuffer.isView(body)) {
+        cloneable.body = new Uint8Array(body.buffer.slice(body.byteOffset, body.byteOffset + body.byteLength))
+    }
+
+    if (transfer && cloneable.body instanceof ArrayBuffer)
+        transfer.push(cloneable.body)
+    else if (transfer && cloneable.body instanceof Uint8Array)
+        transfer.push(cloneable.body.buffer)
+
+    return cloneable
+}
+
+// The options posted by `cloneable_options`, with their FormData body.
+export function restored_options(options) {
+    if (!options || !options.formEntries)
+        return options
+
+    const restored = Object.assign({}, options)
+    delete restored.formEntries
+    restored.body = new FormData()
+    for (const [name, value, filename] of options.formEntries) {
+        if (filename === undefined)
+            restored.body.append(name, value)
+        else
+            restored.body.append(name, value, filename)
+    }
+
+    return restored
+}
+
+// The response as a plain object, rebuilt by `response_from`. With a `transfer` list its body is transferred.
+export async function cloneable_response(response, transfer) {
+    const body = await response.arrayBuffer()
+    if (transfer)
+        transfer.push(body)
+
+    return { status: response.status, statusText: response.statusText, headers: [...response.headers], body: body }
+}
+
+export function response_from(value) {
+    const body = NULL_BODY_STATUSES.includes(value.status) ? null : value.body
+    return new Response(body, { status: value.status, statusText: value.statusText, headers: value.headers })
+}
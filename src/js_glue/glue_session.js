@@ -0,0 +1,94 @@
+// Persists the tunnel sessions across page reloads. The sessions hold the tunnel's private and symmetric keys, so they
+// are encrypted with an AES-GCM key that is generated non-extractable: it is stored as a CryptoKey in IndexedDB and its
+// raw bytes can never be read back, not even by scripts of the origin.
+
+const SESSION_DB = '_layer8session'
+const SESSION_DB_VERSION = 1
+const SESSIONS_STORE = 'sessions'
+const KEYS_STORE = 'keys'
+const WRAPPING_KEY = 'wrapping_key'
+
+function open_session_db() {
+    return new Promise((resolve, reject) => {
+        const request = globalThis.indexedDB.open(SESSION_DB, SESSION_DB_VERSION)
+        request.onupgradeneeded = function (event) {
+            const db = event.target.result
+            if (!db.objectStoreNames.contains(SESSIONS_STORE))
+                db.createObjectStore(SESSIONS_STORE, { keyPath: 'provider' })
+            if (!db.objectStoreNames.contains(KEYS_STORE))
+                db.createObjectStore(KEYS_STORE)
+        }
+        request.onsuccess = event => resolve(event.target.result)
+        request.onerror = event => reject('Error opening the session database: ' + event.target.error)
+    })
+}
+
+function request_to_promise(request) {
+    return new Promise((resolve, reject) => {
+        request.onsuccess = event => resolve(event.target.result)
+        request.onerror = event => reject(event.target.error)
+    })
+}
+
+async function session_op(store_name, mode, op) {
+    const db = await open_session_db()
+    try {
+        return await request_to_promise(op(db.transaction(store_name, mode).objectStore(store_name)))
+    } finally {
+        db.close()
+    }
+}
+
+async function wrapping_key() {
+    const key = await session_op(KEYS_STORE, 'readonly', store => store.get(WRAPPING_KEY))
+    if (key)
+        return key
+
+    const generated = await crypto.subtle.generateKey({ name: 'AES-GCM', length: 256 }, false, ['encrypt', 'decrypt'])
+    // `add` fails if another tab stored its key in the meantime, that key wins
+    try {
+        await session_op(KEYS_STORE, 'readwrite', store => store.add(generated, WRAPPING_KEY))
+        return generated
+    } catch (e) {
+        return await session_op(KEYS_STORE, 'readonly', store => store.get(WRAPPING_KEY))
+    }
+}
+
+export async function save_session(provider, session, expires_at) {
+    const key = await wrapping_key()
+    const iv = crypto.getRandomValues(new Uint8Array(12))
+    const data = await crypto.subtle.encrypt({ name: 'AES-GCM', iv: iv }, key, session)
+
+    await session_op(SESSIONS_STORE, 'readwrite', store => store.put({
+        provider: provider,
+        iv: iv,
+        data: data,
+        expires_at: expires_at,
+    }))
+}
+
+// Resolves with the decrypted session, or `null` if there is none or it expired.
+export async function load_session(provider, now) {
+    const entry = await session_op(SESSIONS_STORE, 'readonly', store => store.get(provider))
+    if (!entry)
+        return null
+
+    if (entry.expires_at <= now) {
+        await delete_session(provider)
+        return null
+    }
+
+    try {
+        const key = await wrapping_key()
+        const data = await crypto.subtle.decrypt({ name: 'AES-GCM', iv: entry.iv }, key, entry.data)
+        return new Uint8Array(data)
+    } catch (e) {
+        // the wrapping key was replaced, the session can't be recovered
+        await delete_session(provider)
+        return null
+    }
+}
+
+export async function delete_session(provider) {
+    await session_op(SESSIONS_STORE, 'readwrite', store => store.delete(provider))
+}
@@ -0,0 +1,7 @@
+// The Socket.IO client helpers that can't be expressed with wasm-bindgen.
+
+// Wraps `callback`, called with the array of the arguments, into a variadic function. The acks the server asks for
+// are called with any number of arguments.
+export function collect_args(callback) {
+    return (...args) => callback(args)
+}
@@ -0,0 +1,184 @@
+// Shares one tunnel per provider between the tabs of the origin. The tab holding the provider's Web Lock is the leader:
+// it owns the tunnel and serves the requests the other tabs post on the provider's BroadcastChannel. The lock is
+// released when the leader tab closes, the next tab waiting for it takes over.
+
+import { cloneable_options, cloneable_response, error_message, response_from, restored_options } from './glue_messaging.js'
+
+const CHANNEL_PREFIX = 'layer8::tunnel::'
+const LOCK_PREFIX = 'layer8::leader::'
+
+// Requests posted while no tab leads are lost, the follower gives up on them after this long.
+const REQUEST_TIMEOUT = 30 * 1000
+
+// A tab that failed to take over gets back in line after this long, doubled with each failure up to the maximum. The
+// values are in milliseconds.
+const TAKEOVER_RETRY_DELAY = 1000
+const MAX_TAKEOVER_RETRY_DELAY = 30 * 1000
+
+class TabGroup {
+    constructor(provider, on_leader, serve) {
+        this.provider = provider
+        this.on_leader = on_leader
+        this.serve = serve
+        this.tab_id = crypto.randomUUID()
+        this.leader = false
+        this.next_id = 0
+        this.pending = new Map()
+
+        this.channel = new BroadcastChannel(CHANNEL_PREFIX + provider)
+        this.channel.onmessage = event => this.on_message(event.data)
+    }
+
+    isLeader() {
+        return this.leader
+    }
+
+    // Resolves once this tab either leads or follows. A follower keeps waiting for the lock to take over.
+    async join() {
+        if (!globalThis.navigator || !navigator.locks)
+            throw new Error('sharing the tunnel between tabs requires the Web Locks API')
+
+        if (!await this.lead({ ifAvailable: true }))
+            this.follow(TAKEOVER_RETRY_DELAY)
+    }
+
+    // Waits in line for the lock. If establishing the tunnel fails on takeover the requests waiting for a leader are
+    // failed, in every tab, and this tab gets back in line.
+    follow(retry_delay) {
+        this.lead({}).catch(error => {
+            console.error('Error taking over the tunnel: ', error)
+            const message = 'the tab taking over the tunnel failed: ' + error_message(error)
+            this.fail_pending(message)
+            this.channel.postMessage({ type: 'takeover-failed', from: this.tab_id, error: message })
+
+            setTimeout(() => this.follow(Math.min(retry_delay * 2, MAX_TAKEOVER_RETRY_DELAY)), retry_delay)
+        })
+    }
+
+    fail_pending(message) {
+        for (const id of [...this.pending.keys()])
+            this.settle(id, new Error(message))
+    }
+
+    lead(options) {
+        return new Promise((resolve, reject) => {
+            navigator.locks.request(LOCK_PREFIX + this.provider, options, async lock => {
+                if (!lock)
+                    return resolve(false)
+
+                try {
+                    await this.on_leader()
+                } catch (error) {
+                    // the lock is released for the next tab in line
+                    return reject(error)
+                }
+
+                this.leader = true
+                this.channel.postMessage({ type: 'leader', from: this.tab_id })
+                resolve(true)
+
+                // the lock is held for as long as the tab lives
+                await new Promise(() => { })
+            }).catch(reject)
+        })
+    }
+
+    async forward(kind, url, options) {
+        // the signal can't be posted, an abort rejects the request here while the leader still completes it
+        const signal = options && options.signal
+        if (signal)
+            signal.throwIfAborted()
+
+        const id = this.tab_id + ':' + this.next_id++
+        const message = { type: 'request', id: id, from: this.tab_id, kind: kind, url: url, options: cloneable_options(options) }
+
+        const value = await new Promise((resolve, reject) => {
+            const timer = setTimeout(() => this.settle(id, new Error('no tab served the request in time')), REQUEST_TIMEOUT)
+            this.pending.set(id, { resolve: resolve, reject: reject, timer: timer, accepted: false })
+            if (signal)
+                signal.addEventListener('abort', () => this.settle(id, signal.reason), { once: true })
+            this.channel.postMessage(message)
+        })
+
+        if (kind === 'static')
+            return URL.createObjectURL(value)
+
+        return response_from(value)
+    }
+
+    settle(id, error, value) {
+        const pending = this.pending.get(id)
+        if (!pending)
+            return
+
+        this.pending.delete(id)
+        clearTimeout(pending.timer)
+        if (error)
+            pending.reject(error)
+        else
+            pending.resolve(value)
+    }
+
+    on_message(message) {
+        switch (message.type) {
+            case 'request':
+                if (this.leader)
+                    this.respond(message)
+                break
+
+            case 'accepted':
+                if (message.to === this.tab_id && this.pending.has(message.id))
+                    this.pending.get(message.id).accepted = true
+                break
+
+            case 'response':
+                if (message.to === this.tab_id)
+                    this.settle(message.id, message.error ? new Error(message.error) : null, message.value)
+                break
+
+            case 'leader':
+                // the requests posted to the previous leader are lost, the ones it didn't pick up can be posted again
+                for (const [id, pending] of [...this.pending]) {
+                    const error = new Error(pending.accepted ? 'the leader tab changed while serving the request' : 'the leader tab changed')
+                    error.unserved = !pending.accepted
+                    this.settle(id, error)
+                }
+                break
+
+            case 'takeover-failed':
+                // no tab leads until the next one in line has taken over
+                this.fail_pending(message.error)
+                break
+        }
+    }
+
+    async respond(message) {
+        // from now on the request may reach the backend, the follower doesn't post it again
+        this.channel.postMessage({ type: 'accepted', id: message.id, to: message.from })
+
+        const response = { type: 'response', id: message.id, to: message.from }
+        try {
+            const result = await this.serve(message.kind, message.url, restored_options(message.options))
+            if (message.kind === 'static') {
+                // object URLs are bound to the leader's document, the asset itself is handed over
+                try {
+                    response.value = await (await fetch(result)).blob()
+                } finally {
+                    URL.revokeObjectURL(result)
+                }
+            } else {
+                response.value = await cloneable_response(result)
+            }
+        } catch (error) {
+            response.error = error_message(error)
+        }
+
+        this.channel.postMessage(response)
+    }
+}
+
+export async function join_tab_group(provider, on_leader, serve) {
+    const group = new TabGroup(provider, on_leader, serve)
+    await group.join()
+    return group
+}
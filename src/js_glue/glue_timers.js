@@ -0,0 +1,5 @@
+// The timers used by the interceptor, `setTimeout` is available in the window and in the workers alike.
+
+export function sleep(ms) {
+    return new Promise(resolve => setTimeout(resolve, ms))
+}
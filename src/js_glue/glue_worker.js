@@ -0,0 +1,105 @@
+// The message-passing bridge between the main thread and a dedicated worker running the interceptor. The main thread
+// posts `layer8::call` messages and the worker answers each with a `layer8::reply`. Buffers are transferred both ways
+// instead of being copied, the request bodies are copied first so the caller's buffers stay usable.
+
+import { cloneable_options, cloneable_response, error_message, response_from, restored_options } from './glue_messaging.js'
+
+const CALL = 'layer8::call'
+const REPLY = 'layer8::reply'
+
+// Worker side: `dispatch(op, args)` runs the operation against the interceptor of this worker.
+export function serve_worker(dispatch) {
+    self.addEventListener('message', async event => {
+        const message = event.data
+        if (!message || message.type !== CALL)
+            return
+
+        const reply = { type: REPLY, id: message.id }
+        const transfer = []
+        try {
+            const args = message.op === 'fetch' ? [message.args[0], message.args[1], restored_options(message.args[2])] : message.args
+            const result = await dispatch(message.op, args)
+            reply.value = await transferable_result(message.op, result, transfer)
+        } catch (error) {
+            reply.error = error_message(error)
+        }
+
+        self.postMessage(reply, transfer)
+    })
+}
+
+async function transferable_result(op, result, transfer) {
+    switch (op) {
+        case 'fetch':
+        case 'fetchRange':
+            return await cloneable_response(result, transfer)
+
+        case 'static': {
+            // object URLs are bound to the worker, the asset itself is handed over
+            try {
+                return await (await fetch(result)).blob()
+            } finally {
+                URL.revokeObjectURL(result)
+            }
+        }
+
+        default:
+            return result
+    }
+}
+
+// Main thread side.
+export class WorkerBridge {
+    constructor(worker) {
+        this.worker = worker
+        this.next_id = 0
+        this.pending = new Map()
+        this.worker.addEventListener('message', event => this.on_message(event.data))
+    }
+
+    call(op, args, transfer) {
+        const id = this.next_id++
+        return new Promise((resolve, reject) => {
+            this.pending.set(id, { resolve: resolve, reject: reject })
+            this.worker.postMessage({ type: CALL, id: id, op: op, args: args }, transfer || [])
+        })
+    }
+
+    on_message(message) {
+        const pending = message && message.type === REPLY && this.pending.get(message.id)
+        if (!pending)
+            return
+
+        this.pending.delete(message.id)
+        if ('error' in message)
+            pending.reject(new Error(message.error))
+        else
+            pending.resolve(message.value)
+    }
+
+    async initEncryptedTunnel(config) {
+        return await this.call('initEncryptedTunnel', [config])
+    }
+
+    async fetch(provider, url, options) {
+        const transfer = []
+        const value = await this.call('fetch', [provider, url, cloneable_options(options, transfer)], transfer)
+        return response_from(value)
+    }
+
+    async fetchRange(provider, url, start, end) {
+        return response_from(await this.call('fetchRange', [provider, url, start, end]))
+    }
+
+    async static(provider, url) {
+        return URL.createObjectURL(await this.call('static', [provider, url]))
+    }
+
+    async clearCache(provider) {
+        await this.call('clearCache', [provider])
+    }
+}
+
+export function connect_worker(worker) {
+    return new WorkerBridge(worker)
+}
@@ -1,8 +1,6 @@
-use js_sys::Object;
+use js_sys::{Function, Object, Uint8Array};
 use wasm_bindgen::prelude::*;
 
-use crate::types::DbCache;
-
 /// This block imports Javascript functions that are provided by the JS Runtime.
 #[wasm_bindgen]
 extern "C" {
@@ -22,42 +20,215 @@ extern "C" {
 
     #[wasm_bindgen(js_namespace = Function, js_name = toString)]
     pub fn to_string(func: &JsValue) -> String;
+
+    /// `RegExp(pattern, flags)`, the `SyntaxError` of an invalid pattern is returned where `js_sys::RegExp::new` throws.
+    #[wasm_bindgen(js_name = RegExp, catch)]
+    pub fn new_regexp(pattern: &str, flags: &str) -> Result<js_sys::RegExp, JsValue>;
 }
 
 /// This block imports JavaScript functionality that is not mapped by the wasm-bindgen tool.
 #[wasm_bindgen(module = "/src/js_glue/glue_indexed_db.js")]
 extern "C" {
-    /// This operation clears the cache of a specific database.
-    pub fn clear_expired_cache(db_name: &str, db_cache: DbCache);
+    /// This operation clears the expired entries of a specific database, resolving with the number of bytes freed.
+    #[wasm_bindgen(catch)]
+    pub async fn clear_expired_cache(db_name: &str, now: f64) -> Result<JsValue, JsValue>;
+
+    /// This operation drops all the entries of a specific database.
+    #[wasm_bindgen(catch)]
+    pub async fn clear_cache(db_name: &str) -> Result<JsValue, JsValue>;
+
+    /// This operation drops the entries of every session but `session` in a specific database.
     #[wasm_bindgen(catch)]
-    pub async fn serve_static(
+    pub async fn clear_other_sessions(db_name: &str, session: &str) -> Result<JsValue, JsValue>;
+
+    /// This operation retrieves an entry from the cache, `null` if it does not exist.
+    #[wasm_bindgen(js_name = get_asset, catch)]
+    pub async fn idb_get_asset(db_name: &str, key: &str) -> Result<JsValue, JsValue>;
+
+    #[allow(clippy::too_many_arguments)]
+    #[wasm_bindgen(js_name = put_asset, catch)]
+    pub async fn idb_put_asset(
         db_name: &str,
-        body: &[u8],
-        asset_size_limit: u32,
-        file_type: &str,
+        key: &str,
+        session: &str,
         url: &str,
-        exp_in_seconds: i32,
+        body: Uint8Array,
+        file_type: &str,
+        exp: f64,
+        used: f64,
     ) -> Result<JsValue, JsValue>;
 
-    /// This operation checks if an asset exists in the cache, if it does, it returns the asset.
-    #[wasm_bindgen(js_name = check_if_exists, catch)]
-    pub async fn check_if_asset_exists(db_name: &str, url: &str) -> Result<JsValue, JsValue>;
+    #[wasm_bindgen(js_name = delete_asset, catch)]
+    pub async fn idb_delete_asset(db_name: &str, key: &str) -> Result<JsValue, JsValue>;
 
-    /// This operation retrieves the storage estimate of the cache.
+    /// This operation records that an entry was used at `now`.
+    #[wasm_bindgen(js_name = touch_asset, catch)]
+    pub async fn idb_touch_asset(db_name: &str, key: &str, now: f64) -> Result<JsValue, JsValue>;
+
+    /// This operation drops the least recently used entries until `bytes` bytes are freed, resolving with the number
+    /// of bytes freed.
+    #[wasm_bindgen(js_name = evict_lru, catch)]
+    pub async fn idb_evict_lru(db_name: &str, bytes: f64) -> Result<JsValue, JsValue>;
+
+    /// This operation retrieves the size of the cached assets in bytes.
+    #[wasm_bindgen(js_name = cache_size, catch)]
+    pub async fn idb_cache_size(db_name: &str) -> Result<JsValue, JsValue>;
+
+    /// This operation applies the pending schema migrations to a specific database, resolving with the
+    /// `CacheMigrationReport`.
+    #[wasm_bindgen(catch)]
+    pub async fn run_migrations(db_name: &str, migrations: JsValue, version: u32) -> Result<JsValue, JsValue>;
+
+    /// This operation deletes a database that is no longer used, resolving with whether it is gone already.
+    #[wasm_bindgen(catch)]
+    pub async fn delete_unused_db(db_name: &str) -> Result<JsValue, JsValue>;
+
+    /// This operation retrieves the `{ quota, usage }` storage estimate of the origin, in bytes.
     #[wasm_bindgen(catch)]
     pub async fn get_storage_estimate() -> Result<JsValue, JsValue>;
+
+    /// This operation asks the browser to persist the origin's storage, resolving with whether it is persisted.
+    #[wasm_bindgen(catch)]
+    pub async fn request_persistent_storage() -> Result<JsValue, JsValue>;
+}
+
+/// This block imports the Cache Storage API operations.
+#[wasm_bindgen(module = "/src/js_glue/glue_cache_storage.js")]
+extern "C" {
+    /// This operation retrieves an entry from the cache, `null` if it does not exist.
+    #[wasm_bindgen(catch)]
+    pub async fn cs_get_asset(cache_name: &str, key: &str) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(catch)]
+    pub async fn cs_put_asset(cache_name: &str, key: &str, body: Uint8Array, file_type: &str, exp: f64, used: f64) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(catch)]
+    pub async fn cs_delete_asset(cache_name: &str, key: &str) -> Result<JsValue, JsValue>;
+
+    /// This operation records that an entry was used at `now`.
+    #[wasm_bindgen(catch)]
+    pub async fn cs_touch_asset(cache_name: &str, key: &str, now: f64) -> Result<JsValue, JsValue>;
+
+    /// This operation drops the least recently used entries until `bytes` bytes are freed, resolving with the number
+    /// of bytes freed.
+    #[wasm_bindgen(catch)]
+    pub async fn cs_evict_lru(cache_name: &str, bytes: f64) -> Result<JsValue, JsValue>;
+
+    /// This operation retrieves the size of the cached assets in bytes.
+    #[wasm_bindgen(catch)]
+    pub async fn cs_cache_size(cache_name: &str) -> Result<JsValue, JsValue>;
+
+    /// This operation clears the entries that have expired by `now`, resolving with the number of bytes freed.
+    #[wasm_bindgen(catch)]
+    pub async fn cs_evict(cache_name: &str, now: f64) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(catch)]
+    pub async fn cs_clear(cache_name: &str) -> Result<JsValue, JsValue>;
+
+    /// This operation clears the entries whose key does not start with `prefix`.
+    #[wasm_bindgen(catch)]
+    pub async fn cs_retain_prefix(cache_name: &str, prefix: &str) -> Result<JsValue, JsValue>;
+}
+
+/// This block imports the storage of the persisted tunnel sessions.
+#[wasm_bindgen(module = "/src/js_glue/glue_session.js")]
+extern "C" {
+    /// This operation encrypts and stores the session of a provider until `expires_at`.
+    #[wasm_bindgen(catch)]
+    pub async fn save_session(provider: &str, session: Uint8Array, expires_at: f64) -> Result<JsValue, JsValue>;
+
+    /// This operation retrieves the decrypted session of a provider, `null` if there is none or it expired by `now`.
+    #[wasm_bindgen(catch)]
+    pub async fn load_session(provider: &str, now: f64) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(catch)]
+    pub async fn delete_session(provider: &str) -> Result<JsValue, JsValue>;
 }
 
-#[cfg(not(feature = "formdata_polyfill_js_test"))]
-#[wasm_bindgen(module = "/src/js_glue/formdata_polyfill.ts")]
+/// This block imports the helpers shared by the tab group and the worker bridge to post requests and responses.
+#[wasm_bindgen(module = "/src/js_glue/glue_messaging.js")]
 extern "C" {
-    #[wasm_bindgen(catch, js_name = parseFormDataToArray)]
-    pub async fn parse_form_data_to_array(form_data: web_sys::FormData, boundary: String) -> Result<JsValue, JsValue>;
+    /// This operation returns the message of an `Error`, or the value as a string.
+    pub fn error_message(error: &JsValue) -> String;
 }
 
-#[cfg(feature = "formdata_polyfill_js_test")]
-pub async fn parse_form_data_to_array(_: web_sys::FormData, _: String) -> Result<JsValue, JsValue> {
-    unimplemented!("FormData polyfill is not available in this build. Please disable the feature flag 'formdata_polyfill_js_test' to use it.");
+/// This block imports the coordination of the tabs sharing a tunnel.
+#[wasm_bindgen(module = "/src/js_glue/glue_tab_share.js")]
+extern "C" {
+    #[derive(Debug, Clone)]
+    pub type TabGroup;
+
+    /// This operation joins the tab group of a provider. `on_leader()` is awaited once this tab leads the group,
+    /// `serve(kind, url, options)` serves the requests of the other tabs while it does.
+    #[wasm_bindgen(catch)]
+    pub async fn join_tab_group(provider: &str, on_leader: Function, serve: Function) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(method, js_name = isLeader)]
+    pub fn is_leader(this: &TabGroup) -> bool;
+
+    /// This operation forwards a request to the leader tab, resolving with a `Response` or an object URL.
+    #[wasm_bindgen(method, catch)]
+    pub async fn forward(this: &TabGroup, kind: &str, url: &str, options: &JsValue) -> Result<JsValue, JsValue>;
+}
+
+/// This block imports the message-passing bridge between the main thread and a worker running the interceptor.
+#[wasm_bindgen(module = "/src/js_glue/glue_worker.js")]
+extern "C" {
+    /// This operation answers the calls of the main thread with `dispatch(op, args)`, it is called in the worker.
+    pub fn serve_worker(dispatch: Function);
+
+    #[derive(Debug, Clone)]
+    pub type WorkerBridge;
+
+    pub fn connect_worker(worker: &web_sys::Worker) -> WorkerBridge;
+
+    #[wasm_bindgen(method, catch)]
+    pub async fn call(this: &WorkerBridge, op: &str, args: &js_sys::Array) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(method, catch, js_name = initEncryptedTunnel)]
+    pub async fn init_encrypted_tunnel(this: &WorkerBridge, config: &Object) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(method, catch)]
+    pub async fn fetch(this: &WorkerBridge, provider: &str, url: &str, options: &JsValue) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(method, catch, js_name = fetchRange)]
+    pub async fn fetch_range(this: &WorkerBridge, provider: &str, url: &str, start: f64, end: Option<f64>) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(method, catch, js_name = "static")]
+    pub async fn get_static(this: &WorkerBridge, provider: &str, url: &str) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(method, catch, js_name = clearCache)]
+    pub async fn clear_cache(this: &WorkerBridge, provider: &str) -> Result<JsValue, JsValue>;
+}
+
+/// This block imports the timers.
+#[wasm_bindgen(module = "/src/js_glue/glue_timers.js")]
+extern "C" {
+    /// This operation resolves after `ms` milliseconds.
+    #[wasm_bindgen(catch)]
+    pub async fn sleep(ms: u32) -> Result<JsValue, JsValue>;
+}
+
+/// This block imports the helpers of the Socket.IO client.
+#[cfg(feature = "websocket")]
+#[wasm_bindgen(module = "/src/js_glue/glue_socket_io.js")]
+extern "C" {
+    /// This operation turns a function taking the array of its arguments into a variadic function.
+    pub fn collect_args(callback: &Function) -> Function;
+}
+
+/// This block imports the `MediaSource` plumbing used to stream media assets.
+#[wasm_bindgen(module = "/src/js_glue/glue_media_source.js")]
+extern "C" {
+    /// This operation creates a `MediaSource` fed by `fetch_chunk(start, end)` and returns its object URL. With the
+    /// `media` element playing it, seeks and the buffer behind playback are handled.
+    #[wasm_bindgen(catch)]
+    pub fn create_media_source_url(
+        mime_type: &str,
+        fetch_chunk: Function,
+        chunk_size: u32,
+        media: Option<web_sys::HtmlMediaElement>,
+    ) -> Result<String, JsValue>;
 }
 
 #[macro_export]
@@ -0,0 +1,91 @@
+//! Pinning of the tunnel's ECDH keys. The proxy's key returned by `/init-tunnel` is only accepted if its JWK thumbprint
+//! ([RFC 7638](https://www.rfc-editor.org/rfc/rfc7638)) is one of `InitConfig.proxyPublicKeys`, the provider's key if it
+//! is one of `InitConfig.providerKeyThumbprints`.
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+/// Computes the SHA-256 JWK thumbprint of `jwk`, base64url encoded without padding.
+pub(crate) fn jwk_thumbprint(jwk: &Map<String, Value>) -> Result<String, String> {
+    let kty = jwk.get("kty").and_then(Value::as_str).ok_or("expected the JWK to have a `kty` member")?;
+
+    // the required members of each key type, in lexicographic order
+    let members: &[&str] = match kty {
+        "EC" => &["crv", "kty", "x", "y"],
+        "OKP" => &["crv", "kty", "x"],
+        "RSA" => &["e", "kty", "n"],
+        _ => return Err(format!("unsupported JWK key type: {}", kty)),
+    };
+
+    // the members are inserted in lexicographic order and serde_json writes no whitespace, as the RFC requires
+    let mut canonical = Map::new();
+    for member in members {
+        let value = jwk
+            .get(*member)
+            .and_then(Value::as_str)
+            .ok_or(format!("expected the JWK to have a `{}` member", member))?;
+        canonical.insert(member.to_string(), Value::String(value.to_string()));
+    }
+
+    let canonical = serde_json::to_vec(&canonical).map_err(|e| e.to_string())?;
+    Ok(URL_SAFE_NO_PAD.encode(Sha256::digest(&canonical)))
+}
+
+/// Fails unless the thumbprint of `jwk` is one of `pins`. No pins means the key is not pinned. `owner` and `option` name
+/// the key's owner and the option holding the pins in errors.
+pub(crate) fn verify_pinned_key(jwk: &Map<String, Value>, pins: &[String], owner: &str, option: &str) -> Result<(), String> {
+    if pins.is_empty() {
+        return Ok(());
+    }
+
+    let thumbprint = jwk_thumbprint(jwk)?;
+    if pins.iter().any(|pin| pin.trim_end_matches('=') == thumbprint) {
+        return Ok(());
+    }
+
+    Err(format!(
+        "the {}'s public key (thumbprint {}) does not match any of `{}`, refusing the tunnel",
+        owner, thumbprint, option
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jwk(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn thumbprint_matches_rfc_7638_example() {
+        // https://www.rfc-editor.org/rfc/rfc7638#section-3.1
+        let key = jwk(serde_json::json!({
+            "kty": "RSA",
+            "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+            "e": "AQAB",
+            "alg": "RS256",
+            "kid": "2011-04-29"
+        }));
+
+        assert_eq!(jwk_thumbprint(&key).unwrap(), "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs");
+    }
+
+    #[test]
+    fn mismatching_keys_are_refused() {
+        let key = jwk(serde_json::json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU",
+            "y": "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0",
+            "use": "ecdh"
+        }));
+        let thumbprint = jwk_thumbprint(&key).unwrap();
+
+        let verify = |pins: &[String]| verify_pinned_key(&key, pins, "proxy", "InitConfig.proxyPublicKeys");
+        assert!(verify(&[]).is_ok());
+        assert!(verify(&["other".to_string(), thumbprint]).is_ok());
+        assert!(verify(&["other".to_string()]).is_err());
+    }
+}
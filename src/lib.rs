@@ -1,14 +1,26 @@
 pub mod js;
 #[cfg(feature = "websocket")]
 pub mod websocket;
+pub mod worker;
 
+pub(crate) mod cache;
+pub(crate) mod encoding;
+pub(crate) mod end_to_end;
 pub(crate) mod js_glue;
+pub(crate) mod key_pinning;
+pub(crate) mod multipart;
 pub(crate) mod network_state;
+pub(crate) mod session;
+pub(crate) mod static_paths;
+pub(crate) mod tab_share;
 mod types;
 
+#[cfg(test)]
+wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
 pub(crate) mod js_imports_prelude {
     use crate::js_glue;
-    pub use js_glue::js_imports::{clear_expired_cache, console_error, console_log, object_entries, serve_static};
+    pub use js_glue::js_imports::{console_error, console_log, object_entries};
 
     #[cfg(debug_assertions)]
     pub use js_glue::js_imports::{console_error_, console_log_};
@@ -0,0 +1,188 @@
+//! The `multipart/form-data` encoding of `FormData` request bodies, following the encoding algorithm of the HTML
+//! standard: <https://html.spec.whatwg.org/multipage/form-control-infrastructure.html#multipart-form-data>.
+
+use js_sys::{Array, Reflect, Uint8Array};
+use uuid::Uuid;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{File, FormData, ReadableStreamDefaultReader};
+
+/// The content type of File entries that don't provide one.
+const DEFAULT_FILE_TYPE: &str = "application/octet-stream";
+
+/// An entry of the form, the files are only read while the body is written.
+enum Entry {
+    Text { name: String, value: String },
+    File { name: String, file: File },
+}
+
+/// Generates a boundary for a form body, it can't appear in the escaped names nor, in practice, in the file contents.
+pub(crate) fn boundary() -> String {
+    format!("----------------------------layer8{}", Uuid::new_v4().simple())
+}
+
+/// The content type of a form body delimited by `boundary`.
+pub(crate) fn content_type(boundary: &str) -> String {
+    format!("multipart/form-data; boundary={}", boundary)
+}
+
+/// Encodes the entries of `form`. The body is buffered whole since it is encrypted as one message, the files are
+/// read into it chunk by chunk so it is allocated once for the whole form.
+pub(crate) async fn encode(form: &FormData, boundary: &str) -> Result<Vec<u8>, JsValue> {
+    let entries = entries(form)?;
+
+    let size = entries.iter().fold(closing_delimiter(boundary).len(), |size, entry| {
+        size + match entry {
+            Entry::Text { name, value } => text_part(boundary, name, value).len(),
+            Entry::File { name, file } => file_part_headers(boundary, name, &file.name(), &file.type_()).len() + file.size() as usize + 2,
+        }
+    });
+
+    let mut body = Vec::with_capacity(size);
+    for entry in entries {
+        match entry {
+            Entry::Text { name, value } => body.extend_from_slice(text_part(boundary, &name, &value).as_bytes()),
+            Entry::File { name, file } => {
+                body.extend_from_slice(file_part_headers(boundary, &name, &file.name(), &file.type_()).as_bytes());
+                read_into(&file, &mut body).await?;
+                body.extend_from_slice(b"\r\n");
+            }
+        }
+    }
+
+    body.extend_from_slice(closing_delimiter(boundary).as_bytes());
+    Ok(body)
+}
+
+fn entries(form: &FormData) -> Result<Vec<Entry>, JsValue> {
+    let iter = js_sys::try_iter(form)?.ok_or(JsValue::from_str("expected the FormData to be iterable"))?;
+
+    let mut entries = Vec::new();
+    for entry in iter {
+        // [name, value] item array
+        let entry = Array::from(&entry?);
+        let name = entry.get(0).as_string().unwrap_or_default();
+        let value = entry.get(1);
+
+        // a Blob appended to a FormData is turned into a File named "blob"
+        match value.dyn_into::<File>() {
+            Ok(file) => entries.push(Entry::File { name, file }),
+            Err(value) => entries.push(Entry::Text {
+                name,
+                value: value.as_string().unwrap_or_default(),
+            }),
+        }
+    }
+
+    Ok(entries)
+}
+
+// Appends the contents of the file, chunk by chunk, to `body`.
+async fn read_into(file: &File, body: &mut Vec<u8>) -> Result<(), JsValue> {
+    let reader = file.stream().get_reader().unchecked_into::<ReadableStreamDefaultReader>();
+
+    loop {
+        let chunk = JsFuture::from(reader.read()).await?;
+        if Reflect::get(&chunk, &"done".into())?.as_bool().unwrap_or(true) {
+            return Ok(());
+        }
+
+        let value = Reflect::get(&chunk, &"value".into())?;
+        body.extend_from_slice(&value.dyn_into::<Uint8Array>()?.to_vec());
+    }
+}
+
+fn text_part(boundary: &str, name: &str, value: &str) -> String {
+    format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+        boundary,
+        escape(&normalize_line_breaks(name)),
+        normalize_line_breaks(value)
+    )
+}
+
+fn file_part_headers(boundary: &str, name: &str, filename: &str, file_type: &str) -> String {
+    let file_type = match file_type {
+        "" => DEFAULT_FILE_TYPE,
+        file_type => file_type,
+    };
+
+    format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+        boundary,
+        escape(&normalize_line_breaks(name)),
+        escape(filename),
+        file_type
+    )
+}
+
+fn closing_delimiter(boundary: &str) -> String {
+    format!("--{}--\r\n", boundary)
+}
+
+// The names and filenames are sent as UTF-8, only the characters that would end the quoted string or the header are
+// percent-encoded, as browsers do.
+fn escape(value: &str) -> String {
+    value.replace('\n', "%0A").replace('\r', "%0D").replace('"', "%22")
+}
+
+// The names and values of text entries have their line breaks converted to CRLF.
+fn normalize_line_breaks(value: &str) -> String {
+    value.replace("\r\n", "\n").replace('\r', "\n").replace('\n', "\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::*;
+    use web_sys::{Blob, BlobPropertyBag};
+
+    use super::*;
+
+    #[test]
+    fn text_part_normalizes_line_breaks() {
+        assert_eq!(
+            text_part("b", "field", "line\rone\nline two\r\n"),
+            "--b\r\nContent-Disposition: form-data; name=\"field\"\r\n\r\nline\r\none\r\nline two\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn names_are_escaped() {
+        assert_eq!(
+            text_part("b", "a \"quoted\"\nname", ""),
+            "--b\r\nContent-Disposition: form-data; name=\"a %22quoted%22%0D%0Aname\"\r\n\r\n\r\n"
+        );
+
+        assert_eq!(
+            file_part_headers("b", "upload", "résumé \"v2\"\r.pdf", "application/pdf"),
+            "--b\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"résumé %22v2%22%0D.pdf\"\r\n\
+             Content-Type: application/pdf\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn file_type_defaults_to_octet_stream() {
+        assert!(file_part_headers("b", "upload", "blob", "").ends_with("Content-Type: application/octet-stream\r\n\r\n"));
+    }
+
+    #[wasm_bindgen_test]
+    async fn form_data_is_encoded() {
+        let options = BlobPropertyBag::new();
+        options.set_type("text/plain");
+        let file = Blob::new_with_u8_array_sequence_and_options(&Array::of1(&Uint8Array::from(&b"layer8"[..])), &options)
+            .expect("failed to create the Blob");
+
+        let form = FormData::new().expect("failed to create the FormData");
+        form.append_with_str("greeting", "hello\nworld").unwrap();
+        form.append_with_blob_and_filename("upload", &file, "notes \"final\".txt").unwrap();
+
+        let body = encode(&form, "boundary").await.expect("failed to encode the FormData");
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "--boundary\r\nContent-Disposition: form-data; name=\"greeting\"\r\n\r\nhello\r\nworld\r\n\
+             --boundary\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"notes %22final%22.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\nlayer8\r\n\
+             --boundary--\r\n"
+        );
+    }
+}
@@ -2,43 +2,63 @@ use std::collections::HashMap;
 
 use js_sys::{ArrayBuffer, Object, Uint8Array};
 use layer8_primitives::{
-    compression::decompress_data_gzip,
     crypto::{self, Jwk, generate_key_pair, jwk_from_map},
     types::{self, Request, new_client},
 };
 use reqwest::header::HeaderValue;
 use url::Url;
-use uuid::Uuid;
-use wasm_bindgen::{JsCast, JsError, JsValue, UnwrapThrowExt, prelude::wasm_bindgen};
-use web_sys::{Blob, FileReaderSync, FormData, Response, ResponseInit};
+use wasm_bindgen::{
+    JsCast, JsError, JsValue, UnwrapThrowExt,
+    prelude::{Closure, wasm_bindgen},
+};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Blob, BlobPropertyBag, FormData, Response, ResponseInit};
 
 use crate::{
-    js::{INDEXED_DB_CACHE, INDEXED_DB_CACHE_TTL},
-    js_glue::js_imports::check_if_asset_exists,
+    cache::{self, AssetCache, CacheBackend, CachedAsset},
+    encoding::{self, RequestCompression},
+    end_to_end::{E2E_HEADER, EndToEnd, OpenedBody, PROVIDER_KEY_PATH, PROXY_ERROR_HEADER, UNVERIFIED_HEADER},
+    js::cache_db_name,
     js_imports_prelude::*,
-    types::CACHE_STORAGE_LIMIT,
+    key_pinning::verify_pinned_key,
+    multipart,
+    session::{self, PersistedSession},
+    static_paths::find_rule,
+    tab_share,
+    types::InitConfig,
 };
 use crate::{
-    js::{PROVIDER_REGISTER, get_base_url},
-    js_glue::js_imports::parse_form_data_to_array,
+    js::{CACHE_MIGRATIONS, PROVIDER_REGISTER, get_base_url},
+    js_glue::js_imports::create_media_source_url,
 };
 
+/// The default size of the byte ranges media assets are streamed in, 1MB.
+pub(crate) const MEDIA_CHUNK_SIZE: u32 = 1024 * 1024;
+
+/// The namespace the cached assets of a provider are keyed on: the provider's origin and the tunnel session.
+fn cache_namespace(base_url: &str, client_uuid: &str) -> String {
+    format!("{}#{}", base_url, client_uuid)
+}
+
 #[derive(Debug, Default, Clone)]
 pub(crate) struct NetworkState {
     // These environment values are essential for the tunnel to work
     pub client_uuid: String,
     pub symmetric_key: Jwk,
     pub provider_session: String,
-    pub static_paths: Vec<String>,
+    // The configuration the tunnel was initialized with, reused when the tunnel is re-established.
+    pub config: InitConfig,
+    // The backend that holds this provider's cached assets.
+    pub cache: CacheBackend,
+    // The session the cached assets are keyed on, see `cache_namespace`.
+    pub cache_namespace: String,
 
     pub proxy_url: String,
     pub client: Option<types::Client>,
     pub public_key_jwk: Jwk,
     pub private_key_jwk: Jwk,
-    // TODO:
-    // user_proxy_jwt
-    // provider_public_key
-    // ephemeral_client_key_pair
+    // The provider's verified key and the ephemeral key pair, present when the end-to-end encryption is configured.
+    pub end_to_end: Option<EndToEnd>,
 }
 
 /// This is the object that the JS API interacts with. It is a marker for the ProviderRegistry to identify which
@@ -49,6 +69,9 @@ pub struct NetworkStateHandler(pub(crate) String);
 #[wasm_bindgen]
 impl NetworkStateHandler {
     /// This function is an override of the fetch function. It's arguments are a URL and an options object.
+    ///
+    /// Besides the fetch options, `compress: boolean | RequestCompression` overrides `InitConfig.requestCompression`
+    /// for this request.
     pub async fn fetch(&self, url: String, options: JsValue) -> Result<Response, JsError> {
         let base_url = get_base_url(&url);
         if base_url.ne(&self.0) {
@@ -58,10 +81,17 @@ impl NetworkStateHandler {
             )));
         }
 
+        // a tab following in a shared tunnel forwards the request to the leader tab
+        if let Some(res) = tab_share::forward(&self.0, "fetch", &url, &options).await {
+            return res?
+                .dyn_into::<Response>()
+                .map_err(|_| JsError::new("expected the leader tab to respond with a Response"));
+        }
+
         let mut network_state = PROVIDER_REGISTER
             .with_borrow(|map| map.get(&self.0).cloned())
             .expect_throw("we expect the NetworkState to be present since the handler exists");
-        let proxy_url = network_state.proxy_url.clone();
+        let config = network_state.config.clone();
 
         let mut err_cache = JsError::new("");
         for _ in 1..=3 {
@@ -74,7 +104,7 @@ impl NetworkStateHandler {
                     }
 
                     err_cache = err;
-                    network_state = NetworkState::new(&url, &proxy_url).await.map_err(|e| JsError::new(e.as_str()))?;
+                    network_state = NetworkState::new(&url, &config).await.map_err(|e| JsError::new(e.as_str()))?;
                 }
             }
         }
@@ -94,10 +124,16 @@ impl NetworkStateHandler {
             )));
         }
 
+        if let Some(res) = tab_share::forward(&self.0, "static", &url, &JsValue::UNDEFINED).await {
+            return res?
+                .as_string()
+                .ok_or(JsError::new("expected the leader tab to respond with an object URL"));
+        }
+
         let mut network_state = PROVIDER_REGISTER
             .with_borrow(|map| map.get(&self.0).cloned())
             .expect_throw("we expect the NetworkState to be present since the handler exists");
-        let proxy_url = network_state.proxy_url.clone();
+        let config = network_state.config.clone();
         let mut err_cache = JsError::new("");
 
         for _ in 1..=3 {
@@ -110,19 +146,157 @@ impl NetworkStateHandler {
                     }
 
                     err_cache = err;
-                    network_state = NetworkState::new(&url, &proxy_url).await.map_err(|e| JsError::new(e.as_str()))?;
+                    network_state = NetworkState::new(&url, &config).await.map_err(|e| JsError::new(e.as_str()))?;
                 }
             }
         }
 
         Err(err_cache)
     }
+
+    /// This function fetches the `[start, end]` byte range of the resource, `end` is inclusive. If `end` is not provided
+    /// the range extends to the end of the resource.
+    ///
+    /// The response is the provider's, a `206 Partial Content` with a `Content-Range` header when the range is honoured.
+    /// Partial responses are not cached.
+    #[wasm_bindgen(js_name = fetchRange)]
+    pub async fn fetch_range(&self, url: String, start: f64, end: Option<f64>) -> Result<Response, JsError> {
+        // NaN fails every comparison, only finite bounds make a range
+        if !start.is_finite() || start < 0.0 || end.is_some_and(|end| !end.is_finite() || end < start) {
+            return Err(JsError::new(&format!("invalid byte range: {}-{:?}", start, end)));
+        }
+
+        let range = match end {
+            Some(end) => format!("bytes={}-{}", start as u64, end as u64),
+            None => format!("bytes={}-", start as u64),
+        };
+
+        let options = js_sys::Object::new();
+        let headers = js_sys::Object::new();
+        js_sys::Reflect::set(&headers, &"Range".into(), &range.into()).expect_throw("expected the headers object to be writable");
+        js_sys::Reflect::set(&options, &"method".into(), &"GET".into()).expect_throw("expected the options object to be writable");
+        js_sys::Reflect::set(&options, &"headers".into(), &headers).expect_throw("expected the options object to be writable");
+
+        self.fetch(url, options.into()).await
+    }
+
+    /// This function streams a media asset into a `MediaSource`, returning its object URL to be used as the `src` of
+    /// a `<video>` or `<audio>` element.
+    ///
+    /// The asset is fetched in `chunkSize` byte ranges (1MB by default) and appended as they arrive, so playback starts
+    /// with the first chunk instead of after the whole file is downloaded. The `mimeType` must be supported by
+    /// `MediaSource.isTypeSupported`, e.g. `video/webm; codecs="vp9, opus"`.
+    ///
+    /// Pass the `media` element the URL is played by to seek: a seek outside the buffered media restarts the download
+    /// at the estimated byte position of the target, for WebM and fragmented MP4 assets. The media played more than 30
+    /// seconds ago is then removed from the buffer as well.
+    #[wasm_bindgen(js_name = streamStatic)]
+    pub fn stream_static(
+        &self,
+        url: String,
+        mime_type: String,
+        chunk_size: Option<u32>,
+        media: Option<web_sys::HtmlMediaElement>,
+    ) -> Result<String, JsError> {
+        let base_url = get_base_url(&url);
+        if base_url.ne(&self.0) {
+            return Err(JsError::new(&format!(
+                "the NetworkStateHandler is for `{}` but we're calling the url `{}` instead",
+                self.0, base_url
+            )));
+        }
+
+        let provider = self.0.clone();
+        let fetch_chunk = Closure::wrap(Box::new(move |start: f64, end: f64| -> js_sys::Promise {
+            let handler = NetworkStateHandler(provider.clone());
+            let url = url.clone();
+            wasm_bindgen_futures::future_to_promise(async move {
+                handler.fetch_range(url, start, Some(end)).await.map(JsValue::from).map_err(JsValue::from)
+            })
+        }) as Box<dyn FnMut(f64, f64) -> js_sys::Promise>);
+
+        create_media_source_url(
+            &mime_type,
+            fetch_chunk.into_js_value().unchecked_into(),
+            chunk_size.unwrap_or(MEDIA_CHUNK_SIZE),
+            media,
+        )
+        .map_err(|e| JsError::new(&format!("failed to create a MediaSource: {:?}", e)))
+    }
+
+    /// This function clears the cached static assets of this provider. The caches of other providers are left untouched.
+    #[wasm_bindgen(js_name = clearCache)]
+    pub async fn clear_cache(&self) -> Result<(), JsError> {
+        let cache = PROVIDER_REGISTER
+            .with_borrow(|map| map.get(&self.0).map(|val| val.cache.clone()))
+            .expect_throw("we expect the NetworkState to be present since the handler exists");
+
+        cache.clear().await.map_err(|e| JsError::new(&e))
+    }
+
+    /// The `CacheMigrationReport` of the IndexedDB cache migrations run by `initEncryptedTunnel`, `null` with the other
+    /// cache backends.
+    #[wasm_bindgen(getter, js_name = cacheMigration)]
+    pub fn cache_migration(&self) -> JsValue {
+        CACHE_MIGRATIONS.with_borrow(|map| map.get(&self.0).cloned()).unwrap_or(JsValue::NULL)
+    }
 }
 
 impl NetworkState {
-    /// This operation initializes a new NetworkState. It updates
-    pub(crate) async fn new(provider_url: &str, proxy_url: &str) -> Result<Self, String> {
-        let mut network_state = NetworkState::default();
+    /// This operation restores the persisted session of the provider and registers it to the PROVIDER_REGISTER. It is
+    /// `None` if sessions are not persisted or none can be restored.
+    pub(crate) async fn restore(provider_url: &str, config: &InitConfig) -> Option<Self> {
+        if !config.persist_session {
+            return None;
+        }
+
+        let base_url = get_base_url(provider_url);
+        let session = match session::load(&base_url, js_sys::Date::now()).await {
+            Ok(val) => val?,
+            Err(_e) => {
+                console_error!(&format!("Failed to restore the session of provider: {}. Error: {}", base_url, _e));
+                return None;
+            }
+        };
+
+        let mut network_state = match NetworkState::with_client(provider_url, config) {
+            Ok(val) => val,
+            Err(_e) => {
+                console_error!(&format!("Failed to restore the session of provider: {}. Error: {}", base_url, _e));
+                return None;
+            }
+        };
+
+        // a session established under other settings is dropped, the tunnel is established anew
+        if !session.matches(&network_state.proxy_url, config) {
+            console_log!(&format!("The persisted session of provider {} does not match the config", base_url));
+            if let Err(_e) = session::forget(&base_url).await {
+                console_error!(&format!("Failed to drop the session of provider: {}. Error: {}", base_url, _e));
+            }
+            return None;
+        }
+
+        network_state.cache_namespace = cache_namespace(&base_url, &session.client_uuid);
+        network_state.client_uuid = session.client_uuid;
+        network_state.provider_session = session.provider_session;
+        network_state.symmetric_key = session.symmetric_key;
+        network_state.public_key_jwk = session.public_key_jwk;
+        network_state.private_key_jwk = session.private_key_jwk;
+        network_state.end_to_end = session.end_to_end;
+        console_log!(&format!("Encrypted tunnel restored with provider: {}", base_url));
+
+        PROVIDER_REGISTER.with_borrow_mut(|map| map.insert(base_url, network_state.clone()));
+        Some(network_state)
+    }
+
+    // A NetworkState with the client to the proxy, the tunnel is yet to be established.
+    pub(crate) fn with_client(provider_url: &str, config: &InitConfig) -> Result<Self, String> {
+        let mut network_state = NetworkState {
+            config: config.clone(),
+            cache: CacheBackend::new(config.cache_backend, &cache_db_name(provider_url)),
+            ..Default::default()
+        };
+        let proxy_url = &config.proxy;
 
         // Adding the client and the proxy url to the network_state
         {
@@ -138,6 +312,13 @@ impl NetworkState {
             network_state.client = Some(new_client(proxy_proxy).map_err(|e| e.to_string())?);
         }
 
+        Ok(network_state)
+    }
+
+    /// This operation initializes a new NetworkState and registers it to the PROVIDER_REGISTER.
+    pub(crate) async fn new(provider_url: &str, config: &InitConfig) -> Result<Self, String> {
+        let mut network_state = NetworkState::with_client(provider_url, config)?;
+
         // Create client_uuid and generate pub&priv key pair, add values to the network state
         let base_url = get_base_url(provider_url);
         {
@@ -199,22 +380,124 @@ impl NetworkState {
             .expect_throw("we expect the data type of tje jwt to be a string")
             .to_string();
 
+        // a substituted key would let whoever sits on the proxy hop derive the shared secret
+        verify_pinned_key(&proxy_data, &config.proxy_public_keys, "proxy", "InitConfig.proxyPublicKeys")?;
         network_state.symmetric_key = network_state.private_key_jwk.get_ecdh_shared_secret(&jwk_from_map(proxy_data)?)?;
         console_log!(&format!("Encrypted tunnel established with provider: {}", base_url));
 
+        // the bodies are encrypted once more with a key only the provider can derive
+        if config.provider_key.is_enabled() {
+            let provider_key = match config.provider_key.public_key.clone() {
+                Some(key) => key,
+                None => network_state.fetch_provider_key(&base_url).await?,
+            };
+
+            config.provider_key.verify(&provider_key)?;
+            network_state.end_to_end = Some(EndToEnd::new(provider_key)?);
+            console_log!(&format!("End-to-end encryption established with provider: {}", base_url));
+        }
+
+        // the assets cached through the tunnel this one replaces can no longer be looked up
+        network_state.cache_namespace = cache_namespace(&base_url, &network_state.client_uuid);
+        if let Err(_e) = network_state.cache.retain_session(&network_state.cache_namespace).await {
+            console_error!(&format!("Failed to purge the replaced cache of provider: {}. Error: {}", base_url, _e));
+        }
+
+        // failing to persist the session should not fail the tunnel, the next page load establishes a new one
+        if config.persist_session {
+            if let Err(_e) = session::save(&base_url, &network_state.persisted_session()).await {
+                console_error!(&format!("Failed to persist the session of provider: {}. Error: {}", base_url, _e));
+            }
+        }
+
         // update the network state to the PROVIDER_REGISTER cache
         PROVIDER_REGISTER.with_borrow_mut(|map| map.insert(base_url, network_state.clone()));
         Ok(network_state)
     }
 
+    fn persisted_session(&self) -> PersistedSession {
+        PersistedSession {
+            proxy_url: self.proxy_url.clone(),
+            client_uuid: self.client_uuid.clone(),
+            provider_session: self.provider_session.clone(),
+            symmetric_key: self.symmetric_key.clone(),
+            public_key_jwk: self.public_key_jwk.clone(),
+            private_key_jwk: self.private_key_jwk.clone(),
+            end_to_end: self.end_to_end.clone(),
+            proxy_public_keys: self.config.proxy_public_keys.clone(),
+            provider_key_thumbprints: self.config.provider_key.thumbprints.clone(),
+            provider_public_key: self.config.provider_key.public_key.clone(),
+        }
+    }
+
+    // The provider's key is fetched through the tunnel, the proxy can substitute it so it is only used once verified.
+    async fn fetch_provider_key(&self, base_url: &str) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+        let req_metadata = types::RequestMetadata {
+            method: "GET".to_string(),
+            headers: HashMap::from([
+                ("content-type".to_string(), "application/json".to_string()),
+                ("layer8-empty-body".to_string(), "true".to_string()),
+            ]),
+            url_path: Some(format!("{}{}", base_url, PROVIDER_KEY_PATH)),
+        };
+
+        let res = self
+            .client
+            .clone()
+            .expect_throw("we expect the client to be present")
+            .r#do(
+                (&Request::default(), &req_metadata),
+                &self.symmetric_key,
+                base_url,
+                false,
+                &self.provider_session,
+                &self.client_uuid,
+            )
+            .await
+            .map_err(|(status, e)| format!("failed to fetch the provider's public key, status {}: {}", status, e))?;
+
+        serde_json::from_slice(&res.body).map_err(|e| format!("expected the provider's public key to be a JWK: {}", e))
+    }
+
     async fn fetch(&self, url: String, options: JsValue) -> Result<Response, (i16, JsError)> {
-        let (js_body, mut req_metadata) = retrieve_body_and_req_metadata(&url, options).map_err(|e| (-1, e))?;
-        let req = generate_req_from_js_body(js_body, &mut req_metadata).await.map_err(|e| (-1, e))?;
+        let (js_body, mut req_metadata, compression) =
+            retrieve_body_and_req_metadata(&url, options, &self.config.request_compression).map_err(|e| (-1, e))?;
+        let mut req = generate_req_from_js_body(js_body, &mut req_metadata).await.map_err(|e| (-1, e))?;
+
+        // the body is compressed before it is encrypted, a body the caller already encoded is left alone
+        if !req_metadata.headers.keys().any(|k| k.trim().eq_ignore_ascii_case("Content-Encoding")) {
+            let content_type = req_metadata
+                .headers
+                .iter()
+                .find(|(k, _)| k.trim().eq_ignore_ascii_case("Content-Type"))
+                .map(|(_, v)| v.as_str())
+                .unwrap_or_default();
+
+            if let Some(compressed) = compression.compress(&req.body, content_type).map_err(|e| (-1, JsError::new(&e)))? {
+                console_log!(&format!("Request body compressed from {} to {} bytes", req.body.len(), compressed.len()));
+                req.body = compressed;
+                req_metadata
+                    .headers
+                    .insert("Content-Encoding".to_string(), compression.encoding.as_str().to_string());
+            }
+        }
 
         if req.body.is_empty() {
             req_metadata.headers.insert("layer8-empty-body".to_string(), "true".to_string());
         }
 
+        if let Some(end_to_end) = &self.end_to_end {
+            req.body = end_to_end
+                .seal(&req.body, &mut req_metadata.headers)
+                .map_err(|e| (-1, JsError::new(&e)))?;
+        }
+
+        // the response body is decoded here, the proxy is told which codings we can undo
+        req_metadata.headers.retain(|k, _| !k.trim().eq_ignore_ascii_case("Accept-Encoding"));
+        req_metadata
+            .headers
+            .insert("Accept-Encoding".to_string(), encoding::ACCEPT_ENCODING.to_string());
+
         let base_url = get_base_url(&url);
         req_metadata.url_path = Some(url.clone());
         let res = match self
@@ -240,19 +523,46 @@ impl NetworkState {
             }
         };
 
+        let content_encoding = res
+            .headers
+            .iter()
+            .find(|(k, _)| k.trim().eq_ignore_ascii_case("Content-Encoding"))
+            .map(|(_, v)| v.clone());
+        let has_header = |name: &str| res.headers.iter().any(|(k, _)| k.trim().eq_ignore_ascii_case(name));
+        let (sealed, proxy_error) = (has_header(E2E_HEADER), has_header(PROXY_ERROR_HEADER));
+        let opened = self
+            .open_body(res.body, sealed, proxy_error, res.status)
+            .map_err(|e| (-1, JsError::new(&e)))?;
+        let mut body = encoding::decode(opened.body, content_encoding.as_deref())
+            .map_err(|e| (-1, JsError::new(&format!("Error occurred decoding the response: {}", e))))?;
+
         let response_init = ResponseInit::new();
         let headers = web_sys::Headers::new().expect_throw("expected headers to be created");
         for (key, value) in res.headers.iter() {
+            // the body is handed over decrypted and decoded, these headers no longer describe it. Whether it is verified
+            // is only for us to say.
+            if ["Content-Encoding", "Content-Length", E2E_HEADER, UNVERIFIED_HEADER]
+                .iter()
+                .any(|header| key.trim().eq_ignore_ascii_case(header))
+            {
+                continue;
+            }
+
             headers
                 .append(key, value)
                 .expect_throw("expected headers to be appended to the web_sys::Headers object");
         }
 
+        if !opened.verified {
+            headers
+                .set(UNVERIFIED_HEADER, "true")
+                .expect_throw("expected headers to be set on the web_sys::Headers object");
+        }
+
         response_init.set_headers(&headers);
         response_init.set_status(res.status);
         response_init.set_status_text(&res.status_text);
 
-        let mut body = res.body;
         let response = match Response::new_with_opt_u8_array_and_init(Some(&mut body), &response_init) {
             Ok(val) => val,
             Err(e) => {
@@ -269,58 +579,54 @@ impl NetworkState {
             return Err((-1, JsError::new("Invalid url provided to fetch call")));
         }
 
-        match check_if_asset_exists(INDEXED_DB_CACHE, &url).await {
-            Ok(val) => {
-                if let Some(val) = val.as_string() {
-                    if !val.is_empty() {
-                        // if file is in cache, short-circuit
-                        return Ok(val);
-                    }
+        let parsed_url = Url::parse(&url).map_err(|e| (-1, JsError::new(&format!("url provided is invalid, {}", e))))?;
+
+        // assets not matched by any rule are cached with the tunnel defaults
+        let rule = find_rule(&self.config.static_paths, &parsed_url);
+        let cacheable = rule.is_none_or(|rule| rule.cacheable);
+
+        if cacheable {
+            match cache::lookup(&self.cache, &self.cache_namespace, &url, js_sys::Date::now()).await {
+                Ok(Some(asset)) => {
+                    // if file is in cache, short-circuit
+                    return create_object_url(&asset.body, &asset.content_type).map_err(|e| (-1, e));
                 }
-            }
-            Err(e) => {
-                console_log!(&format!("IndexDB error {:?}", e));
-                return Err((
-                    -1,
-                    JsError::new(&format!(
-                        "error interacting with IndexDB: {}",
-                        e.as_string().unwrap_or(format!("error unwrappable: {:?}", e))
-                    )),
-                ));
-            }
-        };
+                Ok(None) => {}
+                Err(e) => {
+                    console_log!(&format!("Cache error {:?}", e));
+                    return Err((-1, JsError::new(&e)));
+                }
+            };
+        }
 
         let base_url = get_base_url(&url);
-        let mut assets_glob_url = base_url.clone();
-        for static_path in self.static_paths.iter() {
-            if url.contains(static_path) {
-                assets_glob_url.push_str(static_path);
-                break;
-            }
-        }
 
         console_log!(&format!("Request URL: {}", base_url));
 
-        let req_metadata = types::RequestMetadata {
+        let mut req_metadata = types::RequestMetadata {
             method: "GET".to_string(),
             headers: HashMap::from([
                 ("content-type".to_string(), "application/json".to_string()),
                 ("layer8-empty-body".to_string(), "true".to_string()),
+                ("Accept-Encoding".to_string(), encoding::ACCEPT_ENCODING.to_string()),
             ]),
-            url_path: Some(
-                Url::parse(&url.clone())
-                    .map_err(|e| (-1, JsError::new(&format!("url provided is invalid, {}", e))))?
-                    .to_string(),
-            ),
+            url_path: Some(parsed_url.to_string()),
         };
 
+        let mut req = Request::default();
+        if let Some(end_to_end) = &self.end_to_end {
+            req.body = end_to_end
+                .seal(&req.body, &mut req_metadata.headers)
+                .map_err(|e| (-1, JsError::new(&e)))?;
+        }
+
         let res = {
             let res = self
                 .client
                 .clone()
                 .expect_throw("we expect the client to be present")
                 .r#do(
-                    (&Request::default(), &req_metadata),
+                    (&req, &req_metadata),
                     &self.symmetric_key,
                     &base_url,
                     true,
@@ -354,46 +660,102 @@ impl NetworkState {
             }
         };
 
-        // decompress the file if we compressed it
-        let body = match decompress_data_gzip(&res.body) {
-            Ok(val) => {
-                console_log!("File decompressed successfully");
-                val
-            }
-            Err(e) => {
-                if e.eq("invalid gzip header") {
-                    res.body
-                } else {
-                    return Err((-1, JsError::new(&format!("Error occurred decompressing file: {}", e))));
-                }
-            }
+        let content_encoding = res
+            .headers
+            .iter()
+            .find(|(k, _)| k.trim().eq_ignore_ascii_case("Content-Encoding"))
+            .map(|(_, v)| v.clone());
+        let has_header = |name: &str| res.headers.iter().any(|(k, _)| k.trim().eq_ignore_ascii_case(name));
+        let (sealed, proxy_error) = (has_header(E2E_HEADER), has_header(PROXY_ERROR_HEADER));
+        let opened = self
+            .open_body(res.body, sealed, proxy_error, res.status)
+            .map_err(|e| (-1, JsError::new(&e)))?;
+
+        // an asset is served as is, an error body the provider did not seal can't be told apart from a forged one
+        if !opened.verified {
+            return Err((
+                res.status as i16,
+                JsError::new(&format!("the proxy failed to serve {}, status {}", url, res.status)),
+            ));
+        }
+
+        let body = encoding::decode(opened.body, content_encoding.as_deref())
+            .map_err(|e| (-1, JsError::new(&format!("Error occurred decompressing file: {}", e))))?;
+
+        let object_url = create_object_url(&body, &file_type).map_err(|e| (-1, e))?;
+
+        if !rule.is_none_or(|rule| rule.caches(body.len() as u64)) {
+            console_log!(&format!("Static path rule excludes {} from the cache", url));
+            return Ok(object_url);
+        }
+
+        let ttl = rule.and_then(|rule| rule.ttl).unwrap_or(self.config.cache_ttl);
+        let now = js_sys::Date::now();
+        let asset = CachedAsset {
+            body,
+            content_type: file_type,
+            expires_at: now + ttl,
+            last_used: now,
+        };
+
+        let headroom = match self.cache.uses_storage_quota() {
+            true => cache::storage_estimate().await.map(|estimate| estimate.headroom()),
+            false => None,
         };
 
-        let object_url = match serve_static(
-            INDEXED_DB_CACHE,
-            &body,
-            CACHE_STORAGE_LIMIT.with(|v| v.get()),
-            &file_type,
+        // failing to cache the asset should not fail the request
+        match cache::store(
+            &self.cache,
+            self.config.cache_asset_limit,
+            headroom,
+            &self.cache_namespace,
             &url,
-            INDEXED_DB_CACHE_TTL,
+            asset,
+            now,
         )
         .await
         {
-            Ok(val) => val.as_string().expect_throw("expected object url to be a string").to_string(),
-            Err(e) => {
-                return Err((
-                    -1,
-                    JsError::new(&format!(
-                        "Error occurred interacting with IndexDB: {}",
-                        e.as_string().expect_throw("error unwrappable")
-                    )),
+            Ok(true) => {}
+            Ok(false) => {
+                console_log!(&format!(
+                    "Storage limit {} bytes reached, not caching asset...",
+                    self.config.cache_asset_limit
                 ));
             }
-        };
+            Err(_e) => {
+                console_error!(&format!("Failed to cache asset: {}", _e));
+            }
+        }
 
         console_log!(&format!("Object URL: {:?}", object_url));
         Ok(object_url)
     }
+
+    // Undoes the end-to-end encryption of a response body, if it is configured. `sealed` and `proxy_error` are whether
+    // the response carries the `E2E_HEADER` and `PROXY_ERROR_HEADER` headers.
+    fn open_body(&self, body: Vec<u8>, sealed: bool, proxy_error: bool, status: u16) -> Result<OpenedBody, String> {
+        match &self.end_to_end {
+            Some(end_to_end) => end_to_end.open(body, sealed, proxy_error, status),
+            None => Ok(OpenedBody { body, verified: true }),
+        }
+    }
+}
+
+/// Reads the bytes of a Blob, or a File. Unlike `FileReaderSync` it works on the main thread as well as in workers.
+pub(crate) async fn read_blob(blob: &Blob) -> Result<Vec<u8>, JsValue> {
+    let buffer = JsFuture::from(blob.array_buffer()).await?;
+    Ok(Uint8Array::new(&buffer).to_vec())
+}
+
+fn create_object_url(body: &[u8], content_type: &str) -> Result<String, JsError> {
+    let parts = js_sys::Array::of1(&Uint8Array::from(body));
+    let options = BlobPropertyBag::new();
+    options.set_type(content_type);
+
+    let blob = Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+        .map_err(|e| JsError::new(&format!("failed to create a Blob for the asset: {:?}", e)))?;
+
+    web_sys::Url::create_object_url_with_blob(&blob).map_err(|e| JsError::new(&format!("failed to create an object URL: {:?}", e)))
 }
 
 async fn generate_req_from_js_body(js_body: JsValue, req_metadata: &mut types::RequestMetadata) -> Result<types::Request, JsError> {
@@ -413,11 +775,9 @@ async fn generate_req_from_js_body(js_body: JsValue, req_metadata: &mut types::R
         }
 
         x if x.is_instance_of::<Blob>() => {
-            let reader = FileReaderSync::new().expect_throw("failed to create FileReaderSync");
-            let array = reader
-                .read_as_array_buffer(&x.dyn_into::<Blob>().expect_throw("check asserted, js_body is an instance of Blob"))
+            req.body = read_blob(x.unchecked_ref::<Blob>())
+                .await
                 .map_err(|e| JsError::new(&e.as_string().unwrap_or("failed to read a Blob instance".to_string())))?;
-            req.body = Uint8Array::new(&array).to_vec()
         }
 
         x if x.is_instance_of::<ArrayBuffer>() => req.body = Uint8Array::new(&x.dyn_into::<ArrayBuffer>().unwrap_throw()).to_vec(),
@@ -426,20 +786,17 @@ async fn generate_req_from_js_body(js_body: JsValue, req_metadata: &mut types::R
 
         x if x.is_instance_of::<FormData>() => {
             console_log!("FormData detected");
-            let boundary = format!("---------------------------{}", Uuid::new_v4());
-
-            // we expect it to be Uint8Array
-            let val = parse_form_data_to_array(x.dyn_into::<FormData>().unwrap_throw(), boundary.clone())
+            let boundary = multipart::boundary();
+            req.body = multipart::encode(x.unchecked_ref::<FormData>(), &boundary)
                 .await
-                .map_err(|e| JsError::new(&format!("failed to parse FormData: {:?}", e)))?
-                .dyn_into::<Uint8Array>()
-                .map_err(|e| JsError::new(&format!("failed to convert FormData to Uint8Array: {:?}", e)))?;
+                .map_err(|e| JsError::new(&format!("failed to encode the FormData: {:?}", e)))?;
 
-            console_log!(&format!("Form body length: {}", val.length()));
-            req.body = val.to_vec();
+            console_log!(&format!("Form body length: {}", req.body.len()));
+            // the boundary is ours, any content type set by the caller is replaced
+            req_metadata.headers.retain(|k, _| !k.trim().eq_ignore_ascii_case("Content-Type"));
             req_metadata
                 .headers
-                .insert("Content-Type".to_string(), format!("multipart/form-data; boundary={}", boundary));
+                .insert("Content-Type".to_string(), multipart::content_type(&boundary));
         }
 
         _ => {
@@ -452,7 +809,11 @@ async fn generate_req_from_js_body(js_body: JsValue, req_metadata: &mut types::R
     Ok(req)
 }
 
-fn retrieve_body_and_req_metadata(url: &str, options: JsValue) -> Result<(JsValue, types::RequestMetadata), JsError> {
+fn retrieve_body_and_req_metadata(
+    url: &str,
+    options: JsValue,
+    compression: &RequestCompression,
+) -> Result<(JsValue, types::RequestMetadata, RequestCompression), JsError> {
     let mut req_metadata = types::RequestMetadata {
         method: "GET".to_string(),
         url_path: Some(url.to_string()),
@@ -460,8 +821,9 @@ fn retrieve_body_and_req_metadata(url: &str, options: JsValue) -> Result<(JsValu
     };
 
     let mut js_body = JsValue::null();
+    let mut compression = compression.clone();
     if options.is_null() || options.is_undefined() {
-        return Ok((js_body, req_metadata));
+        return Ok((js_body, req_metadata, compression));
     }
 
     let options = Object::from(options);
@@ -497,18 +859,41 @@ fn retrieve_body_and_req_metadata(url: &str, options: JsValue) -> Result<(JsValu
             });
         }
 
+        // not part of the fetch API, it overrides `InitConfig.requestCompression` for this request
+        if key.as_str() == "compress" {
+            compression = RequestCompression::from_js(&value, &compression, "compress")?;
+        }
+
         if key.as_str() == "body" {
             js_body = value;
-            if !js_body.is_null() && !js_body.is_undefined() && js_body.is_instance_of::<FormData>() {
-                req_metadata.headers.insert("Content-Type".to_string(), "multipart/form-data".to_string());
-            }
         }
     }
 
-    // if content type is not provided, we default to "application/json"
-    if !req_metadata.headers.iter().any(|(k, _)| k.trim().eq_ignore_ascii_case("Content-Type")) {
+    // if content type is not provided, we default to "application/json"; a FormData body gets its own, with the
+    // boundary, once it is encoded
+    if !js_body.is_instance_of::<FormData>() && !req_metadata.headers.iter().any(|(k, _)| k.trim().eq_ignore_ascii_case("Content-Type")) {
         req_metadata.headers.insert("Content-Type".to_string(), "application/json".to_string());
     }
 
-    Ok((js_body, req_metadata))
+    Ok((js_body, req_metadata, compression))
+}
+
+#[cfg(test)]
+mod tests {
+    use js_sys::Array;
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    async fn blob_is_read() {
+        let blob = Blob::new_with_u8_array_sequence(&Array::of1(&Uint8Array::from(&b"layer8"[..]))).expect("failed to create the Blob");
+        assert_eq!(read_blob(&blob).await.expect("failed to read the Blob"), b"layer8");
+    }
+
+    #[wasm_bindgen_test]
+    async fn empty_blob_is_read() {
+        let blob = Blob::new().expect("failed to create the Blob");
+        assert!(read_blob(&blob).await.expect("failed to read the Blob").is_empty());
+    }
 }
@@ -0,0 +1,144 @@
+//! Opt-in persistence of the tunnel sessions, see `InitConfig.persistSession`. A persisted session is restored by
+//! `initEncryptedTunnel` instead of running the `/init-tunnel` handshake again, for as long as its up-JWT is valid.
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use js_sys::Uint8Array;
+use layer8_primitives::crypto::Jwk;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::end_to_end::EndToEnd;
+use crate::js_glue::js_imports::{delete_session, load_session, save_session};
+use crate::types::InitConfig;
+
+/// Sessions are dropped this long before their up-JWT expires, so a restored tunnel is not refused right away. The
+/// value is in milliseconds.
+const SESSION_EXPIRY_MARGIN: f64 = 30.0 * 1000.0;
+
+/// The parts of a `NetworkState` that make up the tunnel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct PersistedSession {
+    // The proxy the session was established with, a session is not restored for another proxy.
+    pub(crate) proxy_url: String,
+    pub(crate) client_uuid: String,
+    pub(crate) provider_session: String,
+    pub(crate) symmetric_key: Jwk,
+    pub(crate) public_key_jwk: Jwk,
+    pub(crate) private_key_jwk: Jwk,
+    pub(crate) end_to_end: Option<EndToEnd>,
+    // The pins the keys were verified against, a session is not restored once they change.
+    #[serde(default)]
+    pub(crate) proxy_public_keys: Vec<String>,
+    #[serde(default)]
+    pub(crate) provider_key_thumbprints: Vec<String>,
+    #[serde(default)]
+    pub(crate) provider_public_key: Option<Map<String, Value>>,
+}
+
+impl PersistedSession {
+    /// Whether the session was established with the proxy at `proxy_url` under the key settings of `config`.
+    pub(crate) fn matches(&self, proxy_url: &str, config: &InitConfig) -> bool {
+        // the pins are sets, their order in the config does not matter
+        let same_pins = |a: &[String], b: &[String]| {
+            let (mut a, mut b) = (a.to_vec(), b.to_vec());
+            a.sort();
+            a.dedup();
+            b.sort();
+            b.dedup();
+            a == b
+        };
+
+        self.proxy_url == proxy_url
+            && self.end_to_end.is_some() == config.provider_key.is_enabled()
+            && same_pins(&self.proxy_public_keys, &config.proxy_public_keys)
+            && same_pins(&self.provider_key_thumbprints, &config.provider_key.thumbprints)
+            && self.provider_public_key == config.provider_key.public_key
+    }
+}
+
+/// Stores the session of `provider`, it is encrypted with the non-extractable key in `glue_session.js`.
+pub(crate) async fn save(provider: &str, session: &PersistedSession) -> Result<(), String> {
+    let expires_at = jwt_expiry(&session.provider_session).ok_or("expected the up-JWT to carry an expiry")?;
+    let data = serde_json::to_vec(session).map_err(|e| e.to_string())?;
+
+    save_session(provider, Uint8Array::from(data.as_slice()), expires_at - SESSION_EXPIRY_MARGIN)
+        .await
+        .map_err(session_error)?;
+    Ok(())
+}
+
+/// Retrieves the session of `provider`, `None` if there is none or it expired by `now`.
+pub(crate) async fn load(provider: &str, now: f64) -> Result<Option<PersistedSession>, String> {
+    let data = load_session(provider, now).await.map_err(session_error)?;
+    if data.is_null() || data.is_undefined() {
+        return Ok(None);
+    }
+
+    let data = Uint8Array::new(&data).to_vec();
+    serde_json::from_slice(&data)
+        .map(Some)
+        .map_err(|e| format!("failed to decode the persisted session: {}", e))
+}
+
+pub(crate) async fn forget(provider: &str) -> Result<(), String> {
+    delete_session(provider).await.map_err(session_error)?;
+    Ok(())
+}
+
+/// Returns the `exp` claim of `jwt` in milliseconds. The signature is not checked, the proxy does that.
+pub(crate) fn jwt_expiry(jwt: &str) -> Option<f64> {
+    let claims = URL_SAFE_NO_PAD.decode(jwt.split('.').nth(1)?.trim_end_matches('=')).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&claims).ok()?;
+    claims.get("exp")?.as_f64().map(|exp| exp * 1000.0)
+}
+
+fn session_error(e: wasm_bindgen::JsValue) -> String {
+    format!(
+        "error interacting with the session store: {}",
+        e.as_string().unwrap_or(format!("error unwrappable: {:?}", e))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_are_bound_to_the_pins() {
+        let mut config = InitConfig {
+            proxy_public_keys: vec!["a".to_string(), "b".to_string()],
+            ..Default::default()
+        };
+        let session = PersistedSession {
+            proxy_url: "https://proxy/".to_string(),
+            client_uuid: String::new(),
+            provider_session: String::new(),
+            symmetric_key: Jwk::default(),
+            public_key_jwk: Jwk::default(),
+            private_key_jwk: Jwk::default(),
+            end_to_end: None,
+            proxy_public_keys: vec!["b".to_string(), "a".to_string()],
+            provider_key_thumbprints: Vec::new(),
+            provider_public_key: None,
+        };
+        assert!(session.matches("https://proxy/", &config));
+        assert!(!session.matches("https://other-proxy/", &config));
+
+        config.proxy_public_keys.push("c".to_string());
+        assert!(!session.matches("https://proxy/", &config));
+
+        config.proxy_public_keys.pop();
+        config.provider_key.thumbprints.push("d".to_string());
+        assert!(!session.matches("https://proxy/", &config));
+    }
+
+    #[test]
+    fn reads_the_expiry_of_the_up_jwt() {
+        let claims = URL_SAFE_NO_PAD.encode(r#"{"sub":"client","exp":1700000000}"#);
+        assert_eq!(jwt_expiry(&format!("e30.{}.c2ln", claims)), Some(1_700_000_000_000.0));
+
+        let claims = URL_SAFE_NO_PAD.encode(r#"{"sub":"client"}"#);
+        assert_eq!(jwt_expiry(&format!("e30.{}.c2ln", claims)), None);
+        assert_eq!(jwt_expiry("not a jwt"), None);
+    }
+}
@@ -0,0 +1,223 @@
+use wasm_bindgen::prelude::*;
+
+use crate::js_glue::js_imports::new_regexp;
+use crate::js_imports_prelude::*;
+
+/// A rule of `InitConfig.staticPaths`. Rules are matched against the path of the asset URL, the first matching rule
+/// applies.
+///
+/// A rule is either a string, matched as a path prefix from the root (`"static"` is read as `"/static"`), or an object:
+/// ```js
+/// export interface StaticPathRule {
+///    // Exactly one of `prefix`, `glob` or `regex` is expected.
+///    prefix: string | undefined;
+///    // `*` matches within a path segment, `**` across segments and `?` a single character, e.g. `/assets/**/*.png`.
+///    glob: string | undefined;
+///    // A JavaScript regular expression source, e.g. `^/media/.+\.(mp4|webm)$`.
+///    regex: string | undefined;
+///    // The time-to-live of the matched assets in the cache, in seconds. Defaults to `InitConfig.cacheTtl`.
+///    ttl: number | undefined;
+///    // Whether the matched assets are cached. Defaults to `true`.
+///    cacheable: boolean | undefined;
+///    // The size above which the matched assets are not cached, in bytes.
+///    maxSize: number | undefined;
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub(crate) struct StaticPathRule {
+    pub(crate) matcher: PathMatcher,
+    // The value is in milliseconds.
+    pub(crate) ttl: Option<f64>,
+    pub(crate) cacheable: bool,
+    pub(crate) max_size: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum PathMatcher {
+    Prefix(String),
+    Glob(String),
+    Regex(js_sys::RegExp),
+}
+
+impl StaticPathRule {
+    pub(crate) fn prefix(prefix: String) -> Self {
+        StaticPathRule {
+            matcher: PathMatcher::Prefix(prefix),
+            ttl: None,
+            cacheable: true,
+            max_size: None,
+        }
+    }
+
+    /// The rule of a string `staticPaths` entry, or of the deprecated `staticPath`. They used to match anywhere in the
+    /// URL, a path without its leading `/` is now matched from the root.
+    pub(crate) fn from_path(path: String) -> Self {
+        match path.starts_with('/') {
+            true => StaticPathRule::prefix(path),
+            false => StaticPathRule::prefix(format!("/{}", path)),
+        }
+    }
+
+    pub(crate) fn from_js(val: &JsValue) -> Result<Self, JsError> {
+        if let Some(path) = val.as_string() {
+            return Ok(StaticPathRule::from_path(path));
+        }
+
+        if !val.is_object() {
+            return Err(JsError::new("expected `InitConfig.staticPaths` value to be a string or an object"));
+        }
+
+        let mut matcher = None;
+        let mut rule = StaticPathRule::prefix(String::new());
+        for entry in object_entries(val.unchecked_ref()).iter() {
+            let entry = js_sys::Array::from(&entry); // [key, value] result from Object.entries
+            let key = entry.get(0).as_string().ok_or(JsError::new("expected object key to be a string"))?;
+            let value = entry.get(1);
+
+            match key.as_str() {
+                "prefix" | "glob" | "regex" => {
+                    if matcher.is_some() {
+                        return Err(JsError::new("expected only one of `prefix`, `glob` or `regex` in a `staticPaths` rule"));
+                    }
+
+                    let pattern = value
+                        .as_string()
+                        .ok_or(JsError::new(&format!("expected `staticPaths` rule `{}` value to be a string", key)))?;
+
+                    matcher = Some(match key.as_str() {
+                        "prefix" => PathMatcher::Prefix(pattern),
+                        "glob" => PathMatcher::Glob(pattern),
+                        _ => PathMatcher::Regex(new_regexp(&pattern, "").map_err(|e| {
+                            let message = e.dyn_ref::<js_sys::Error>().map(|e| String::from(e.message()));
+                            JsError::new(&format!(
+                                "expected `staticPaths` rule `regex` value to be a valid regular expression: {}",
+                                message.unwrap_or(pattern)
+                            ))
+                        })?),
+                    });
+                }
+
+                "ttl" => {
+                    let ttl = value
+                        .as_f64()
+                        .filter(|ttl| *ttl >= 0.0 && ttl.is_finite())
+                        .ok_or(JsError::new("expected `staticPaths` rule `ttl` value to be a finite non-negative number"))?;
+                    rule.ttl = Some(ttl * 1000.0);
+                }
+
+                "cacheable" => {
+                    rule.cacheable = value
+                        .as_bool()
+                        .ok_or(JsError::new("expected `staticPaths` rule `cacheable` value to be a boolean"))?;
+                }
+
+                "maxSize" => {
+                    let max_size = value
+                        .as_f64()
+                        .filter(|max_size| *max_size >= 0.0)
+                        .ok_or(JsError::new("expected `staticPaths` rule `maxSize` value to be a non-negative number"))?;
+                    rule.max_size = Some(max_size as u64);
+                }
+
+                _ => {
+                    // we rather pipe the issues now than have them silently ignored
+                    return Err(JsError::new(&format!("unexpected key in `staticPaths` rule: {}", key)));
+                }
+            }
+        }
+
+        rule.matcher = matcher.ok_or(JsError::new("expected one of `prefix`, `glob` or `regex` in a `staticPaths` rule"))?;
+        Ok(rule)
+    }
+
+    pub(crate) fn matches(&self, path: &str) -> bool {
+        match &self.matcher {
+            PathMatcher::Prefix(prefix) => path.starts_with(prefix.as_str()),
+            PathMatcher::Glob(glob) => glob_matches(glob.as_bytes(), path.as_bytes()),
+            PathMatcher::Regex(regex) => regex.test(path),
+        }
+    }
+
+    /// Whether an asset of `size` bytes matched by this rule can be cached.
+    pub(crate) fn caches(&self, size: u64) -> bool {
+        self.cacheable && self.max_size.is_none_or(|max_size| size <= max_size)
+    }
+}
+
+/// Returns the first rule matching the path of `url`.
+pub(crate) fn find_rule<'a>(rules: &'a [StaticPathRule], url: &url::Url) -> Option<&'a StaticPathRule> {
+    rules.iter().find(|rule| rule.matches(url.path()))
+}
+
+// `*` and `?` don't match across path segments, `**` does.
+fn glob_matches(glob: &[u8], path: &[u8]) -> bool {
+    // the outcome of each (glob, path) suffix pair is kept, runs of stars would otherwise backtrack exponentially
+    let mut memo = vec![None; (glob.len() + 1) * (path.len() + 1)];
+    glob_matches_from(glob, path, 0, 0, &mut memo)
+}
+
+fn glob_matches_from(glob: &[u8], path: &[u8], g: usize, p: usize, memo: &mut [Option<bool>]) -> bool {
+    let index = g * (path.len() + 1) + p;
+    if let Some(val) = memo[index] {
+        return val;
+    }
+
+    let val = match &glob[g..] {
+        [] => p == path.len(),
+        [b'*', b'*', rest @ ..] => {
+            // `/**/` also matches a single `/`
+            let after_slash = g + 2 + usize::from(rest.first() == Some(&b'/'));
+            (p..=path.len()).any(|i| glob_matches_from(glob, path, g + 2, i, memo) || glob_matches_from(glob, path, after_slash, i, memo))
+        }
+        [b'*', ..] => {
+            let segment_end = path[p..].iter().position(|c| *c == b'/').map_or(path.len(), |i| p + i);
+            (p..=segment_end).any(|i| glob_matches_from(glob, path, g + 1, i, memo))
+        }
+        [b'?', ..] => matches!(path.get(p), Some(c) if *c != b'/') && glob_matches_from(glob, path, g + 1, p + 1, memo),
+        [c, ..] => path.get(p) == Some(c) && glob_matches_from(glob, path, g + 1, p + 1, memo),
+    };
+
+    memo[index] = Some(val);
+    val
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StaticPathRule, glob_matches};
+
+    fn matches(glob: &str, path: &str) -> bool {
+        glob_matches(glob.as_bytes(), path.as_bytes())
+    }
+
+    #[test]
+    fn paths_without_a_leading_slash_match_from_the_root() {
+        let rule = StaticPathRule::from_path("static".to_string());
+        assert!(rule.matches("/static/app.js"));
+        assert!(!rule.matches("/app/static/app.js"));
+        assert!(StaticPathRule::from_path("/static".to_string()).matches("/static/app.js"));
+    }
+
+    #[test]
+    fn single_star_stays_within_a_segment() {
+        assert!(matches("/assets/*.png", "/assets/logo.png"));
+        assert!(!matches("/assets/*.png", "/assets/icons/logo.png"));
+        assert!(matches("/assets/?.js", "/assets/a.js"));
+        assert!(!matches("/assets/?.js", "/assets/ab.js"));
+    }
+
+    #[test]
+    fn double_star_crosses_segments() {
+        assert!(matches("/assets/**/*.png", "/assets/logo.png"));
+        assert!(matches("/assets/**/*.png", "/assets/icons/small/logo.png"));
+        assert!(matches("/media/**", "/media/videos/intro.mp4"));
+        assert!(!matches("/media/**", "/assets/intro.mp4"));
+    }
+
+    #[test]
+    fn runs_of_stars_do_not_backtrack_exponentially() {
+        let glob = "/**".repeat(16) + "/*x";
+        let path = "/a".repeat(64) + "/b";
+        assert!(!matches(&glob, &path));
+        assert!(matches(&glob, &(path + "x")));
+    }
+}
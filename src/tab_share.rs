@@ -0,0 +1,105 @@
+//! Sharing of one tunnel per provider between the tabs of the origin, see `InitConfig.shareTunnel`. The leader tab owns
+//! the provider's `NetworkState`, the other tabs forward their requests to it. The election and the failover are run by
+//! `glue_tab_share.js`.
+
+use std::{cell::RefCell, collections::HashMap};
+
+use js_sys::{Promise, Reflect};
+use wasm_bindgen::{JsCast, JsError, JsValue, prelude::Closure};
+use wasm_bindgen_futures::future_to_promise;
+
+use crate::{
+    js::PROVIDER_REGISTER,
+    js_glue::js_imports::{TabGroup, error_message, join_tab_group},
+    js_imports_prelude::*,
+    network_state::{NetworkState, NetworkStateHandler},
+    types::InitConfig,
+};
+
+thread_local! {
+    // The tab groups this tab joined, keyed by provider.
+    static TAB_GROUPS: RefCell<HashMap<String, TabGroup>> = RefCell::new(HashMap::new());
+}
+
+/// Joins the tab group of `provider`. If no other tab leads it this tab establishes the tunnel before returning,
+/// otherwise it follows and takes over once the leader tab closes.
+pub(crate) async fn join(provider: &str, config: &InitConfig) -> Result<(), String> {
+    if TAB_GROUPS.with_borrow(|groups| groups.contains_key(provider)) {
+        return Ok(());
+    }
+
+    // followers don't establish a tunnel, the NetworkState still holds the config and the cache for the handlers
+    if PROVIDER_REGISTER.with_borrow(|map| !map.contains_key(provider)) {
+        let network_state = NetworkState::with_client(provider, config)?;
+        PROVIDER_REGISTER.with_borrow_mut(|map| map.insert(provider.to_string(), network_state));
+    }
+
+    let on_leader = {
+        let (provider, config) = (provider.to_string(), config.clone());
+        Closure::<dyn FnMut() -> Promise>::new(move || {
+            let (provider, config) = (provider.clone(), config.clone());
+            future_to_promise(async move {
+                console_log!(&format!("This tab leads the tunnel with provider: {}", provider));
+                if NetworkState::restore(&provider, &config).await.is_none() {
+                    NetworkState::new(&provider, &config).await.map_err(|e| JsValue::from_str(&e))?;
+                }
+
+                Ok(JsValue::UNDEFINED)
+            })
+        })
+    };
+
+    let serve = {
+        let provider = provider.to_string();
+        Closure::<dyn FnMut(String, String, JsValue) -> Promise>::new(move |kind: String, url: String, options: JsValue| {
+            let handler = NetworkStateHandler(provider.clone());
+            future_to_promise(async move {
+                let res = match kind.as_str() {
+                    "static" => handler.get_static(url).await.map(JsValue::from),
+                    _ => handler.fetch(url, options).await.map(JsValue::from),
+                };
+
+                res.map_err(JsValue::from)
+            })
+        })
+    };
+
+    let group = join_tab_group(
+        provider,
+        on_leader.into_js_value().unchecked_into(),
+        serve.into_js_value().unchecked_into(),
+    )
+    .await
+    .map_err(|e| format!("failed to join the tab group: {:?}", e))?;
+
+    TAB_GROUPS.with_borrow_mut(|groups| groups.insert(provider.to_string(), group.unchecked_into()));
+    Ok(())
+}
+
+/// Forwards the request to the leader tab if this tab follows in the group of `provider`, `None` if it does not.
+/// `kind` is either "fetch" or "static". A request is posted again only if the leader changed before picking it up, the
+/// errors of the leader are returned as is.
+pub(crate) async fn forward(provider: &str, kind: &str, url: &str, options: &JsValue) -> Option<Result<JsValue, JsError>> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        // the leader can change between the attempts, this tab included
+        let group = TAB_GROUPS.with_borrow(|groups| groups.get(provider).filter(|group| !group.is_leader()).cloned())?;
+
+        let err = match group.forward(kind, url, options).await {
+            Ok(val) => return Some(Ok(val)),
+            Err(e) => e,
+        };
+
+        // once a leader picked the request up it may have reached the backend
+        let unserved = Reflect::get(&err, &"unserved".into()).is_ok_and(|unserved| unserved.is_truthy());
+        if !unserved || attempt == 3 {
+            return Some(Err(JsError::new(&format!(
+                "failed to forward the request to the leader tab: {}",
+                error_message(&err)
+            ))));
+        }
+
+        console_error!(&format!("Failed to forward the request to the leader tab: {}", error_message(&err)));
+    }
+}
@@ -1,15 +1,14 @@
-use std::cell::Cell;
-
-use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
-use crate::js_glue::js_imports::{self, get_storage_estimate};
+use crate::cache::{CacheBackendKind, storage_estimate};
+use crate::encoding::RequestCompression;
+use crate::end_to_end::ProviderKeyConfig;
+use crate::js::INDEXED_DB_CACHE_TTL;
+use crate::js_glue::js_imports;
+use crate::static_paths::StaticPathRule;
 
-// These statics are declared here to avoid import cycles if we coupled them with the rest in `./js.rs`.
-thread_local! {
-    /// We are using a default asset size limit ot 50MB. This value can be overridden by the initialization config.
-    pub(crate) static CACHE_STORAGE_LIMIT: Cell<u32> = const { Cell::new(50) };
-}
+/// We are using a default asset size limit ot 50MB. This value can be overridden by the initialization config.
+pub(crate) const DEFAULT_CACHE_STORAGE_LIMIT: u64 = 50 * 1024 * 1024;
 
 /// This type represents the configuration object that is passed to the `init` function.
 ///
@@ -22,17 +21,86 @@ thread_local! {
 ///    proxy:      string;
 ///    // Deprecated: `staticPath` is used for backwards compatibility, use `staticPaths` instead.
 ///    staticPath:  string | undefined;
-///    // The list of paths to serve static assets from.
-///    staticPaths: string[] | undefined;
-///    // The maximum size of assets to cache. The value is in MB.
+///    // The rules of the paths to serve static assets from, matched against the URL path. See [`StaticPathRule`].
+///    // The strings, and `staticPath`, are path prefixes from the root: they no longer match anywhere in the URL, and
+///    // `"static"` is read as `"/static"`.
+///    staticPaths: (string | StaticPathRule)[] | undefined;
+///    // The maximum size of assets to cache for this provider. The value is in MB.
 ///    cacheAssetLimit: number | undefined;
+///    // The time-to-live of the cached assets for this provider. The value is in seconds.
+///    cacheTtl: number | undefined;
+///    // The backend to cache the assets in. Defaults to "indexedDB".
+///    cacheBackend: "indexedDB" | "cacheStorage" | "memory" | undefined;
+///    // Asks the browser to persist the origin's storage so cached assets are not evicted under storage pressure.
+///    persistStorage: boolean | undefined;
+///    // Compresses request bodies before they are encrypted, off by default. See `RequestCompression`.
+///    requestCompression: boolean | RequestCompression | undefined;
+///    // The RFC 7638 SHA-256 thumbprints (base64url) of the proxy's accepted ECDH keys. The tunnel is refused if the
+///    // proxy presents any other key. No value means the key is not pinned.
+///    proxyPublicKeys: string[] | undefined;
+///    // The provider's long-term ECDH public key. Setting it, or `providerKeyThumbprints`, encrypts the bodies end-to-end
+///    // with the provider so the proxy cannot read them. The headers, status and URL are not covered. Error responses
+///    // the proxy generated itself are passed on with an `x-layer8-e2e-unverified: true` header, their body is not the
+///    // provider's; other unsealed responses are refused.
+///    providerPublicKey: JsonWebKey | undefined;
+///    // The RFC 7638 SHA-256 thumbprints (base64url) of the provider's accepted keys. Without `providerPublicKey` the key
+///    // is fetched from the provider's `/.well-known/layer8/provider-key` and verified against them.
+///    providerKeyThumbprints: string[] | undefined;
+///    // Keeps the tunnel session in IndexedDB, encrypted with a non-extractable key, and restores it on the next page
+///    // load while its up-JWT is valid. Defaults to `false`. The cached assets are scoped to the tunnel session, they
+///    // only outlive the page load when it is persisted.
+///    persistSession: boolean | undefined;
+///    // Shares one tunnel per provider between the tabs of the origin. The leader tab establishes it and serves the
+///    // requests of the other tabs, another tab takes over when it closes. Requires the Web Locks API.
+///    shareTunnel: boolean | undefined;
+/// }
+///
+/// // The schema migrations applied to the IndexedDB cache by `initEncryptedTunnel`, see `cacheMigration`.
+/// export interface CacheMigrationReport {
+///    database: string;
+///    // The version the database was migrated from, `null` when the cache was rebuilt after a failed migration.
+///    from: number | null;
+///    to: number;
+///    // The migrations that were applied, empty when the schema was up to date.
+///    migrations: { version: number; description: string }[];
+///    rebuilt: boolean;
 /// }
 /// ```
-#[derive(Default)]
+#[derive(Debug, Clone)]
 pub(crate) struct InitConfig {
     pub(crate) proxy: String,
-    pub(crate) static_paths: Vec<String>,
+    pub(crate) static_paths: Vec<StaticPathRule>,
     pub(crate) provider: String,
+    // The value is in bytes.
+    pub(crate) cache_asset_limit: u64,
+    // The value is in milliseconds.
+    pub(crate) cache_ttl: f64,
+    pub(crate) cache_backend: CacheBackendKind,
+    pub(crate) persist_storage: bool,
+    pub(crate) request_compression: RequestCompression,
+    pub(crate) proxy_public_keys: Vec<String>,
+    pub(crate) provider_key: ProviderKeyConfig,
+    pub(crate) persist_session: bool,
+    pub(crate) share_tunnel: bool,
+}
+
+impl Default for InitConfig {
+    fn default() -> Self {
+        InitConfig {
+            proxy: String::new(),
+            static_paths: Vec::new(),
+            provider: String::new(),
+            cache_asset_limit: DEFAULT_CACHE_STORAGE_LIMIT,
+            cache_ttl: INDEXED_DB_CACHE_TTL,
+            cache_backend: CacheBackendKind::default(),
+            persist_storage: false,
+            request_compression: RequestCompression::default(),
+            proxy_public_keys: Vec::new(),
+            provider_key: ProviderKeyConfig::default(),
+            persist_session: false,
+            share_tunnel: false,
+        }
+    }
 }
 
 impl InitConfig {
@@ -62,44 +130,126 @@ impl InitConfig {
                         .get(1)
                         .as_string()
                         .ok_or(JsError::new("expected `InitConfig.staticPath` value to be a string"))?;
-                    init_config.static_paths.push(path);
+                    init_config.static_paths.push(StaticPathRule::from_path(path));
                 }
 
                 "staticPaths" => {
-                    // paths is a list of strings
+                    // paths is a list of rules, see [`StaticPathRule`]
                     if !val.get(1).is_instance_of::<js_sys::Array>() {
                         return Err(JsError::new("expected `InitConfig.staticPaths` value to be an array"));
                     }
 
                     for path in js_sys::Array::from(&val.get(1)).iter() {
-                        let value = path
-                            .as_string()
-                            .ok_or(JsError::new("expected `InitConfig.staticPaths` value to be a string"))?;
-                        init_config.static_paths.push(value);
+                        init_config.static_paths.push(StaticPathRule::from_js(&path)?);
                     }
                 }
 
                 "cacheAssetLimit" => {
-                    // if we can't get the storage estimate, we rely on the default value
-                    let estimate = get_storage_estimate().await;
-                    if let Ok(estimate) = estimate {
-                        let mut val = val
-                            .get(1)
-                            .as_f64()
-                            .ok_or(JsError::new("expected `InitConfig.cacheAssetLimit` value to be a number"))?;
+                    let val = val
+                        .get(1)
+                        .as_f64()
+                        .ok_or(JsError::new("expected `InitConfig.cacheAssetLimit` value to be a number"))?;
 
-                        let estimate = estimate.as_f64().expect_throw("expected storage estimate to be a number");
+                    if val < 0.0 {
+                        return Err(JsError::new("expected `InitConfig.cacheAssetLimit` value to be a positive number"));
+                    }
 
-                        if val > estimate {
+                    let mut limit = (val * 1024.0 * 1024.0) as u64;
+
+                    // if we can't get the storage estimate, the limit is applied as is
+                    if let Some(estimate) = storage_estimate().await {
+                        if limit > estimate.quota {
                             // we are going with half the estimate
                             // estimates are usually [very large]<https://developer.mozilla.org/en-US/play?id=qHEOFcbSol%2Bevp8cXcV4AHeiMNC9eg1hPfouaBm%2Fdv3CX6MmH3pAqbE018v9o2C0XOIUTTJe%2BTlzxxbC>
-                            val = estimate * 0.5;
+                            limit = estimate.quota / 2;
                         }
+                    }
+
+                    init_config.cache_asset_limit = limit;
+                }
+
+                "persistStorage" => {
+                    init_config.persist_storage = val
+                        .get(1)
+                        .as_bool()
+                        .ok_or(JsError::new("expected `InitConfig.persistStorage` value to be a boolean"))?;
+                }
+
+                "cacheTtl" => {
+                    let val = val
+                        .get(1)
+                        .as_f64()
+                        .ok_or(JsError::new("expected `InitConfig.cacheTtl` value to be a number"))?;
+
+                    if val <= 0.0 || !val.is_finite() {
+                        return Err(JsError::new("expected `InitConfig.cacheTtl` value to be a finite positive number"));
+                    }
+
+                    // the value is provided in seconds, we keep it in milliseconds
+                    init_config.cache_ttl = val * 1000.0;
+                }
+
+                "cacheBackend" => {
+                    let backend = val
+                        .get(1)
+                        .as_string()
+                        .ok_or(JsError::new("expected `InitConfig.cacheBackend` value to be a string"))?;
+
+                    init_config.cache_backend = CacheBackendKind::parse(&backend).ok_or(JsError::new(&format!(
+                        "expected `InitConfig.cacheBackend` to be one of \"indexedDB\", \"cacheStorage\" or \"memory\", got: {}",
+                        backend
+                    )))?;
+                }
+
+                "requestCompression" => {
+                    init_config.request_compression =
+                        RequestCompression::from_js(&val.get(1), &RequestCompression::default(), "InitConfig.requestCompression")?;
+                }
+
+                "proxyPublicKeys" => {
+                    if !val.get(1).is_instance_of::<js_sys::Array>() {
+                        return Err(JsError::new("expected `InitConfig.proxyPublicKeys` value to be an array"));
+                    }
+
+                    for key in js_sys::Array::from(&val.get(1)).iter() {
+                        let value = key
+                            .as_string()
+                            .ok_or(JsError::new("expected `InitConfig.proxyPublicKeys` value to be a string"))?;
+                        init_config.proxy_public_keys.push(value);
+                    }
+                }
+
+                "providerPublicKey" => {
+                    init_config.provider_key.public_key = Some(ProviderKeyConfig::parse_public_key(&val.get(1))?);
+                }
+
+                "providerKeyThumbprints" => {
+                    if !val.get(1).is_instance_of::<js_sys::Array>() {
+                        return Err(JsError::new("expected `InitConfig.providerKeyThumbprints` value to be an array"));
+                    }
 
-                        CACHE_STORAGE_LIMIT.with(|limit| limit.set(val as u32));
+                    for thumbprint in js_sys::Array::from(&val.get(1)).iter() {
+                        let value = thumbprint
+                            .as_string()
+                            .ok_or(JsError::new("expected `InitConfig.providerKeyThumbprints` value to be a string"))?;
+                        init_config.provider_key.thumbprints.push(value);
                     }
                 }
 
+                "persistSession" => {
+                    init_config.persist_session = val
+                        .get(1)
+                        .as_bool()
+                        .ok_or(JsError::new("expected `InitConfig.persistSession` value to be a boolean"))?;
+                }
+
+                "shareTunnel" => {
+                    init_config.share_tunnel = val
+                        .get(1)
+                        .as_bool()
+                        .ok_or(JsError::new("expected `InitConfig.shareTunnel` value to be a boolean"))?;
+                }
+
                 _ => {
                     // we rather pipe the issues now than have them silently ignored
                     return Err(JsError::new(&format!(
@@ -113,26 +263,3 @@ impl InitConfig {
         Ok(init_config)
     }
 }
-
-#[derive(Clone)]
-#[wasm_bindgen(getter_with_clone)]
-pub struct DbCache {
-    pub store: String,
-    pub key_path: String,
-    pub indexes: Indexes,
-}
-
-#[wasm_bindgen(getter_with_clone)]
-#[derive(Clone, Serialize, Deserialize)]
-pub struct Indexes {
-    pub url: Uniqueness,
-    pub _exp: Uniqueness,
-    pub body: Uniqueness,
-    pub _type: Uniqueness,
-}
-
-#[wasm_bindgen]
-#[derive(Clone, Serialize, Deserialize)]
-pub struct Uniqueness {
-    pub unique: bool,
-}
@@ -1,46 +1,99 @@
 use base64::{Engine as _, engine::general_purpose::URL_SAFE as base64_enc_dec};
-use js_sys::{ArrayBuffer, Function, Uint8Array};
+use js_sys::{ArrayBuffer, Function, Promise, Uint8Array};
 use serde_json::json;
-use std::{cell::RefCell, collections::HashMap};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    rc::Rc,
+};
 use tokio::sync::oneshot;
 use uuid::Uuid;
 use wasm_bindgen::prelude::*;
-use web_sys::{BinaryType, Blob, Event, FileReaderSync, MessageEvent, MessageEventInit, WebSocket as BrowserWebSocket};
+use wasm_bindgen_futures::{JsFuture, future_to_promise};
+use web_sys::{
+    BinaryType, Blob, CloseEvent, CloseEventInit, CustomEvent, CustomEventInit, Event, EventTarget, MessageEvent, MessageEventInit,
+    WebSocket as BrowserWebSocket,
+};
 
 use layer8_primitives::{
     crypto::{self, KeyUse, generate_key_pair, jwk_from_map},
     types::{Layer8Envelope, WebSocketPayload},
 };
 
-use crate::{js::get_base_url, js_imports_prelude::*};
+use crate::{js_glue::js_imports::sleep, js_imports_prelude::*, network_state::read_blob};
 
 thread_local! {
-    // This static variable will help us keep track of our websocket wrapper.
+    // This static variable will help us keep track of our websocket wrappers, keyed by connection id.
     static LAYER8_SOCKETS: RefCell<HashMap<String, WasmWebSocket>> = RefCell::new(HashMap::new());
-    static WS_PUB_JWK_ECDH:  RefCell<Option<crypto::Jwk>> = const { RefCell::new(None) };
-    static WS_PRIVATE_JWK_ECDH: RefCell<Option<crypto::Jwk>> = const { RefCell::new(None) };
-    static WS_USER_SYMMETRIC_KEY: RefCell<Option<crypto::Jwk> >= const { RefCell::new(None) };
-    static WS_UP_JWT: RefCell<String> = const { RefCell::new(String::new()) };
-    static WS_ENCRYPTED_TUNNEL_FLAG: RefCell<bool> = const { RefCell::new(false) };
-    static WS_UUID: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+    // The sends queued behind a Blob being read, per socket: their count and the promise of the last one.
+    static BLOB_SENDS: RefCell<HashMap<String, (u32, Promise)>> = RefCell::new(HashMap::new());
+    // The frames sent while a connection is being opened, keyed by the id it will have once it is.
+    static CONNECTING: RefCell<HashMap<String, SendQueue>> = RefCell::new(HashMap::new());
 }
 
 /// The configuration object for the WebSocket.
 #[wasm_bindgen(getter_with_clone)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct InitConfig {
     pub url: String,
     pub proxy: String,
     pub reconnect: bool,
+    /// The delay before the first reconnection attempt in milliseconds, it doubles with each failed attempt.
+    pub reconnect_delay: u32,
+    /// The cap of the delay between two reconnection attempts in milliseconds.
+    pub max_reconnect_delay: u32,
+    /// The number of reconnection attempts before the socket is closed, unlimited if not set.
+    pub max_reconnect_attempts: Option<u32>,
+    /// The number of frames `send` queues while the connection is being opened or restored.
+    pub send_queue_size: u32,
+    #[wasm_bindgen(skip)]
+    pub send_queue_policy: QueuePolicy,
+    /// The delay between two heartbeat pings in milliseconds, the heartbeat is off if not set.
+    pub heartbeat_interval: Option<u32>,
+    /// The delay after which a ping without its pong drops the connection, in milliseconds.
+    pub heartbeat_timeout: u32,
+    /// The delay after which a handshake with the proxy fails, in milliseconds.
+    pub handshake_timeout: u32,
     pub protocols: Option<Vec<String>>,
 }
 
+/// What `send` does with a frame once the send queue is full, see `InitConfig.sendQueuePolicy`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// The oldest queued frame is dropped to make room.
+    #[default]
+    DropOldest,
+    /// `send` throws.
+    Reject,
+    /// `send` returns a promise, the frame is queued once there is room and the promise resolves once it is sent.
+    Block,
+}
+
+impl QueuePolicy {
+    fn parse(val: &str) -> Option<Self> {
+        match val {
+            "drop-oldest" => Some(QueuePolicy::DropOldest),
+            "reject" => Some(QueuePolicy::Reject),
+            "block" => Some(QueuePolicy::Block),
+            _ => None,
+        }
+    }
+}
+
 impl Default for InitConfig {
     fn default() -> Self {
         InitConfig {
             url: String::new(),
             proxy: String::new(),
             reconnect: true,
+            reconnect_delay: 1000,
+            max_reconnect_delay: 30_000,
+            max_reconnect_attempts: None,
+            send_queue_size: 64,
+            send_queue_policy: QueuePolicy::default(),
+            heartbeat_interval: None,
+            heartbeat_timeout: 10_000,
+            handshake_timeout: 10_000,
             protocols: None,
         }
     }
@@ -67,6 +120,54 @@ impl InitConfig {
                         .ok_or(JsError::new("expected `InitConfig.proxy` value to be a string"))?;
                 }
 
+                "reconnect" => {
+                    init_config.reconnect = val
+                        .get(1)
+                        .as_bool()
+                        .ok_or(JsError::new("expected `InitConfig.reconnect` value to be a boolean"))?;
+                }
+
+                "reconnectDelay" => {
+                    init_config.reconnect_delay = non_negative_integer(&val.get(1), "reconnectDelay")?;
+                }
+
+                "maxReconnectDelay" => {
+                    init_config.max_reconnect_delay = non_negative_integer(&val.get(1), "maxReconnectDelay")?;
+                }
+
+                "maxReconnectAttempts" => {
+                    init_config.max_reconnect_attempts = Some(non_negative_integer(&val.get(1), "maxReconnectAttempts")?);
+                }
+
+                "sendQueueSize" => {
+                    init_config.send_queue_size = non_negative_integer(&val.get(1), "sendQueueSize")?;
+                    if init_config.send_queue_size == 0 {
+                        return Err(JsError::new("expected `InitConfig.sendQueueSize` value to be at least 1"));
+                    }
+                }
+
+                "sendQueuePolicy" => {
+                    init_config.send_queue_policy = val.get(1).as_string().as_deref().and_then(QueuePolicy::parse).ok_or(JsError::new(
+                        "expected `InitConfig.sendQueuePolicy` value to be one of \"drop-oldest\", \"reject\" or \"block\"",
+                    ))?;
+                }
+
+                "heartbeatInterval" => {
+                    let interval = non_negative_integer(&val.get(1), "heartbeatInterval")?;
+                    if interval == 0 {
+                        return Err(JsError::new("expected `InitConfig.heartbeatInterval` value to be at least 1"));
+                    }
+                    init_config.heartbeat_interval = Some(interval);
+                }
+
+                "heartbeatTimeout" => {
+                    init_config.heartbeat_timeout = non_negative_integer(&val.get(1), "heartbeatTimeout")?;
+                }
+
+                "handshakeTimeout" => {
+                    init_config.handshake_timeout = non_negative_integer(&val.get(1), "handshakeTimeout")?;
+                }
+
                 "protocols" => {
                     if val.get(1).is_instance_of::<js_sys::Array>() {
                         let protocols = js_sys::Array::from(&val.get(1));
@@ -89,6 +190,10 @@ impl InitConfig {
                     } else {
                         return Err(JsError::new("expected `InitConfig.protocols` value to be a string or an array"));
                     }
+
+                    if let Some(protocols) = &init_config.protocols {
+                        validate_protocols(protocols).map_err(|e| JsError::new(&format!("invalid `InitConfig.protocols`: {}", e)))?;
+                    }
                 }
 
                 _ => {
@@ -105,11 +210,141 @@ impl InitConfig {
     }
 }
 
+// The protocols are checked as the browser's WebSocket constructor does: they must be distinct tokens.
+fn validate_protocols(protocols: &[String]) -> Result<(), String> {
+    for (index, protocol) in protocols.iter().enumerate() {
+        // the token characters of RFC 7230
+        let is_token = !protocol.is_empty() && protocol.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+        if !is_token {
+            return Err(format!("{:?} is not a valid subprotocol", protocol));
+        }
+
+        if protocols[..index].contains(protocol) {
+            return Err(format!("{:?} is requested more than once", protocol));
+        }
+    }
+
+    Ok(())
+}
+
+// The subprotocol the backend selected, it must be one of the requested ones if any were.
+fn negotiated_protocol(requested: Option<&[String]>, selected: Option<&str>) -> Result<String, String> {
+    match (requested, selected) {
+        (None | Some([]), _) => Ok(String::new()),
+        (Some(requested), Some(selected)) if requested.iter().any(|protocol| protocol == selected) => Ok(selected.to_string()),
+        (Some(requested), _) => Err(format!(
+            "the backend accepted none of the requested subprotocols: {}",
+            requested.join(", ")
+        )),
+    }
+}
+
+// Reads a non-negative integer option of the `InitConfig`.
+fn non_negative_integer(val: &JsValue, option: &str) -> Result<u32, JsError> {
+    val.as_f64()
+        .filter(|v| *v >= 0.0 && v.fract() == 0.0 && *v <= u32::MAX as f64)
+        .map(|v| v as u32)
+        .ok_or(JsError::new(&format!(
+            "expected `InitConfig.{}` value to be a non-negative integer",
+            option
+        )))
+}
+
 // The WebSocket input-output stream using the browser's WebSocket API.
 #[derive(Debug)]
 struct WasmWebSocket {
-    // This is the actual WebSocket object.
+    // This is the actual WebSocket object, it is replaced when the connection is restored.
     socket: BrowserWebSocket,
+    // the key material of the socket's handshake, replaced along with the socket
+    session: Session,
+    config: InitConfig,
+    // The listeners are on this target, not on the browser socket: they outlive the browser sockets the connection
+    // goes through and only see the events of the backend connection.
+    target: EventTarget,
+    // the event handler properties, `onmessage` and co, keyed by event type; each is a listener of the target
+    handlers: HashMap<&'static str, Function>,
+    // set while the connection is being restored
+    reconnecting: bool,
+    // set once `close` is called, the connection is not restored after
+    closing: bool,
+    // the frames sent while the connection is being restored
+    queue: SendQueue,
+    // how binary frames are delivered, as with the browser's WebSocket
+    binary_type: BinaryType,
+    // the nonce of the heartbeat ping waiting for its pong and when it was sent
+    ping: Option<(u64, f64)>,
+    // the round-trip time of the last heartbeat in milliseconds
+    latency: Option<f64>,
+}
+
+// The outcome of the ECDH handshake with the proxy, each socket has its own.
+#[derive(Debug, Clone)]
+struct Session {
+    symmetric_key: crypto::Jwk,
+    #[allow(dead_code)] // the proxy identifies the socket by its client uuid for now
+    up_jwt: String,
+    client_uuid: String,
+    // the subprotocol and the extensions the backend negotiated
+    protocol: String,
+    extensions: String,
+}
+
+/// The handshake metadata keys of the subprotocols: the ones requested, then the one the backend selected, and the
+/// extensions it negotiated.
+const REQUESTED_PROTOCOLS: &str = "x-ws-protocols";
+const SELECTED_PROTOCOL: &str = "x-ws-protocol";
+const NEGOTIATED_EXTENSIONS: &str = "x-ws-extensions";
+
+/// The envelope metadata key of the frame type, the payload is encrypted so the type of the frame it came in is lost.
+const FRAME_TYPE: &str = "x-frame-type";
+
+// The type of a frame, it decides how the payload is delivered to the `message` listeners.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameType {
+    // delivered as a string
+    Text,
+    // delivered as an ArrayBuffer or a Blob, according to `binaryType`
+    Binary,
+    // the heartbeat frames, they are not delivered; the payload of a ping is its nonce, the pong echoes it
+    Ping,
+    Pong,
+}
+
+impl FrameType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FrameType::Text => "text",
+            FrameType::Binary => "binary",
+            FrameType::Ping => "ping",
+            FrameType::Pong => "pong",
+        }
+    }
+
+    // Frames from peers that don't set the type are text frames.
+    fn from_metadata(metadata: &serde_json::Value) -> Self {
+        match metadata.get(FRAME_TYPE).and_then(|v| v.as_str()) {
+            Some("binary") => FrameType::Binary,
+            Some("ping") => FrameType::Ping,
+            Some("pong") => FrameType::Pong,
+            _ => FrameType::Text,
+        }
+    }
+}
+
+// The decrypted payload of a frame and its type.
+#[derive(Debug)]
+struct Frame {
+    data: Vec<u8>,
+    frame_type: FrameType,
+}
+
+// A frame waiting for the connection to be opened or restored. It is encrypted when it is flushed, the new handshake
+// derives a new symmetric key.
+#[derive(Debug)]
+struct QueuedFrame {
+    frame: Frame,
+    // the resolve and reject functions of the promise returned by `send`, see `QueuePolicy::Block`
+    blocked: Option<(Function, Function)>,
 }
 
 impl Drop for WasmWebSocket {
@@ -118,32 +353,187 @@ impl Drop for WasmWebSocket {
     }
 }
 
+// The frames sent while the connection is being opened or restored, they are sent in order once it is.
+#[derive(Debug)]
+struct SendQueue {
+    frames: VecDeque<QueuedFrame>,
+    // the frames `send` blocks on while the queue is full, they are queued in order as room is made
+    blocked: VecDeque<QueuedFrame>,
+    size: usize,
+    policy: QueuePolicy,
+}
+
+impl SendQueue {
+    fn new(config: &InitConfig) -> Self {
+        SendQueue {
+            frames: VecDeque::new(),
+            blocked: VecDeque::new(),
+            size: config.send_queue_size as usize,
+            policy: config.send_queue_policy,
+        }
+    }
+
+    // Queues a frame, `InitConfig.sendQueuePolicy` applies once the queue is full.
+    fn push(&mut self, frame: Frame) -> Result<Option<Promise>, JsValue> {
+        if self.frames.len() < self.size {
+            self.frames.push_back(QueuedFrame { frame, blocked: None });
+            return Ok(None);
+        }
+
+        match self.policy {
+            QueuePolicy::DropOldest => {
+                console_error!("The send queue is full, dropping the oldest frame");
+                self.frames.pop_front();
+                self.frames.push_back(QueuedFrame { frame, blocked: None });
+                Ok(None)
+            }
+            QueuePolicy::Reject => Err(JsValue::from_str("the send queue is full")),
+            QueuePolicy::Block => {
+                let mut blocked = None;
+                let promise = Promise::new(&mut |resolve, reject| blocked = Some((resolve, reject)));
+                self.blocked.push_back(QueuedFrame { frame, blocked });
+                Ok(Some(promise))
+            }
+        }
+    }
+
+    // The next frame to send, the first blocked frame takes the room it makes.
+    fn pop(&mut self) -> Option<QueuedFrame> {
+        let next = self.frames.pop_front()?;
+        if let Some(blocked) = self.blocked.pop_front() {
+            self.frames.push_back(blocked);
+        }
+
+        Some(next)
+    }
+
+    // Drops the queued frames, the connection won't be opened or restored.
+    fn discard(&mut self) {
+        for frame in self.frames.drain(..).chain(self.blocked.drain(..)) {
+            if let Some((_, reject)) = frame.blocked {
+                _ = reject.call1(&JsValue::UNDEFINED, &JsValue::from_str("the socket closed"));
+            }
+        }
+    }
+}
+
+impl Drop for SendQueue {
+    fn drop(&mut self) {
+        self.discard();
+    }
+}
+
 /// This is a reference to the WebSocket object.
 /// The implementation does not support SharedArrayBuffers.
 ///
 /// We would want to name it as `WebSocket`, please look: <https://github.com/rustwasm/wasm-bindgen/issues/2798>
 #[wasm_bindgen(js_name = L8WebSocket)]
 #[derive(Debug, Default)]
-struct WasmWebSocketRef(String);
+pub struct WasmWebSocketRef(RefCell<String>);
+
+impl WasmWebSocketRef {
+    // A reference to the connection with the provided id.
+    pub(crate) fn of(id: String) -> Self {
+        WasmWebSocketRef(RefCell::new(id))
+    }
+
+    // The id of the connection, it changes when `init` opens a new one.
+    pub(crate) fn id(&self) -> String {
+        self.0.borrow().clone()
+    }
+}
 
 impl WasmWebSocket {
-    async fn init_(options: js_sys::Object) -> Result<WasmWebSocketRef, JsValue> {
+    // Opens a connection for `options.url` and points `socket` at it, each connection has its own id. The connection
+    // `socket` points at is kept if it is still live and for the same URL. The frames sent while the new connection is
+    // being opened are queued.
+    async fn init_(socket: &WasmWebSocketRef, options: js_sys::Object) -> Result<(), JsValue> {
         let options = InitConfig::new(options)?;
-        let rebuilt_url = get_base_url(&options.url);
+        url::Url::parse(&options.url).map_err(|e| JsError::new(&format!("expected `InitConfig.url` to be a valid URL: {}", e)))?;
 
-        // if already present & in open state, return the existing socket ref
-        if let Some(val) = LAYER8_SOCKETS.with_borrow_mut(|val| match val.get(&rebuilt_url) {
-            Some(x) if x.socket.ready_state() <= BrowserWebSocket::OPEN => Some(rebuilt_url.clone()),
-            _ => None,
+        let current = socket.id();
+        if CONNECTING.with_borrow(|queues| queues.contains_key(&current)) {
+            return Err(JsError::new("the socket is being opened, `init` must resolve first").into());
+        }
+
+        // if already present & in open state, keep the existing socket
+        if LAYER8_SOCKETS.with_borrow(|val| match val.get(&current) {
+            Some(x) => x.config.url == options.url && (x.reconnecting || x.socket.ready_state() <= BrowserWebSocket::OPEN),
+            None => false,
         }) {
-            return Ok(WasmWebSocketRef(val));
+            return Ok(());
         }
 
-        let (private_jwk_ecdh, pub_jwk_ecdh) = generate_key_pair(KeyUse::Ecdh)?;
-        WS_PRIVATE_JWK_ECDH.with_borrow_mut(|v| {
-            v.replace(private_jwk_ecdh.clone());
+        // the frames sent from now on wait for the handshake
+        let id = Uuid::new_v4().to_string();
+        CONNECTING.with_borrow_mut(|queues| queues.insert(id.clone(), SendQueue::new(&options)));
+        socket.0.replace(id.clone());
+
+        let handshake = Self::handshake(&options).await;
+        let queue = CONNECTING.with_borrow_mut(|queues| queues.remove(&id));
+        let (browser_socket, session, queue) = match (handshake, queue) {
+            (Ok((browser_socket, session)), Some(queue)) => (browser_socket, session, queue),
+            // `close` was called while the connection was being opened, the connection it replaces goes with it
+            (Ok((browser_socket, _)), None) => {
+                _ = browser_socket.close();
+                remove(&current);
+                return Err(JsValue::from_str("the socket was closed while being opened"));
+            }
+            (Err(e), queue) => {
+                if queue.is_some() {
+                    socket.0.replace(current);
+                }
+                return Err(e);
+            }
+        };
+        Self::attach(&id, &browser_socket);
+
+        // the connection replaced is closed without a `close` event, its listeners don't follow the L8WebSocket
+        remove(&current);
+
+        let target = EventTarget::new()?;
+        let heartbeat = options.heartbeat_interval.map(|interval| (interval, options.heartbeat_timeout));
+        LAYER8_SOCKETS.with_borrow_mut(|val| {
+            val.insert(
+                id.clone(),
+                WasmWebSocket {
+                    socket: browser_socket,
+                    session,
+                    config: options,
+                    target,
+                    handlers: HashMap::new(),
+                    reconnecting: false,
+                    closing: false,
+                    queue,
+                    binary_type: BinaryType::Blob,
+                    ping: None,
+                    latency: None,
+                },
+            );
         });
 
+        if let Some((interval, timeout)) = heartbeat {
+            wasm_bindgen_futures::spawn_local(Self::heartbeat(id.clone(), interval, timeout));
+        }
+
+        flush_queue(&id);
+
+        // the connection is open by the time `init` resolves, the listeners added right after still see the event
+        let opened = id.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            _ = sleep(0).await;
+            if let Ok(event) = Event::new("open") {
+                dispatch(&opened, &event);
+            }
+        });
+
+        Ok(())
+    }
+
+    // Connects to the proxy and makes the ECDH handshake for the backend at `options.url`.
+    async fn handshake(options: &InitConfig) -> Result<(BrowserWebSocket, Session), JsValue> {
+        let (private_jwk_ecdh, pub_jwk_ecdh) = generate_key_pair(KeyUse::Ecdh)?;
+
         let b64_pub_jwk = pub_jwk_ecdh.export_as_base64();
         console_log!(&format!("Connecting to proxy: {}", options.proxy));
 
@@ -153,61 +543,40 @@ impl WasmWebSocket {
         })?;
 
         // waiting for the connection to be ready
-        {
-            let (tx, rx) = oneshot::channel();
-            let closure = Closure::once(move |_event: Event| {
-                tx.send(())
-                    .expect_throw("Failed to send ready state; this is a bug in the code, please report it to the developers")
-            });
-
-            socket.set_onopen(Some(closure.as_ref().unchecked_ref()));
+        first_event(socket.clone(), "open", options.handshake_timeout).await.map_err(|_e| {
+            console_log!(&format!("Failed to connect to proxy: {:?}", _e));
+            _ = socket.close();
+            JsValue::from_str("Failed to connect to proxy")
+        })?;
 
-            rx.await.map_err(|_e| {
-                console_log!("Failed to connect to proxy");
-                JsValue::from_str("Failed to connect to proxy")
-            })?;
+        console_log!("Connected to proxy");
 
-            console_log!("Connected to proxy");
-            socket.set_onopen(None);
-        }
+        // fixme: should be reusable for other operations
+        let uuid = Uuid::new_v4().to_string();
 
         // let's make the initECDH handshake first
         let resp_bytes = {
-            let (tx, rx) = oneshot::channel();
-            let closure = Closure::once(move |event: MessageEvent| {
-                let data = event.data().as_string().expect_throw("expected the message frame o be a string");
-                console_log!(&format!("Received response: {}", data));
-                tx.send(data).unwrap();
+            // sending the public key, and the subprotocols for the proxy to request from the backend
+            let mut metadata = json!({
+                "backend_url": options.url,
+                "x-ecdh-init": b64_pub_jwk,
+                "x-client-uuid": &uuid,
             });
+            if let Some(protocols) = options.protocols.as_ref().filter(|protocols| !protocols.is_empty()) {
+                metadata[REQUESTED_PROTOCOLS] = json!(protocols);
+            }
 
-            console_log!("Setting onmessage");
-            socket.set_onmessage(Some(closure.as_ref().unchecked_ref()));
-
-            // fixme: should be reusable for other operations
-            let uuid = Uuid::new_v4().to_string();
-            WS_UUID.with_borrow_mut(|val| {
-                val.insert(rebuilt_url.clone(), uuid.clone());
-            });
+            let payload = Layer8Envelope::WebSocket(WebSocketPayload { payload: None, metadata });
 
-            // sending the public key
-            let payload = Layer8Envelope::WebSocket(WebSocketPayload {
-                payload: None,
-                metadata: json!({
-                    "backend_url": rebuilt_url,
-                    "x-ecdh-init": b64_pub_jwk,
-                    "x-client-uuid": uuid,
-                }),
-            });
+            let payload = serde_json::to_vec(&payload).map_err(|_e| {
+                console_log!(&format!("Failed to send public key: {:?}", _e));
+                JsValue::from_str("Failed to send public key")
+            })?;
 
+            // listening before sending, so the response can't be missed
+            let response = first_event(socket.clone(), "message", options.handshake_timeout);
             socket
-                .send_with_u8_array(
-                    &serde_json::to_vec(&payload)
-                        .map_err(|_e| {
-                            console_log!(&format!("Failed to send public key: {:?}", _e));
-                            JsValue::from_str("Failed to send public key")
-                        })
-                        .unwrap(),
-                )
+                .send_with_u8_array(&payload)
                 .inspect(|_| {
                     console_log!(&format!("Sent public key: {}", b64_pub_jwk));
                 })
@@ -219,12 +588,13 @@ impl WasmWebSocket {
             console_log!("Waiting for response");
 
             // this will be a blocking operation; we need to wait for the response
-            rx.await
-                .inspect(|_| {
-                    console_log!("Received response");
-                    socket.set_onmessage(None); // reset the onmessage callback
-                })
-                .map_err(|e| JsValue::from_str(&e.to_string()))?
+            response
+                .await
+                .inspect_err(|_| _ = socket.close())?
+                .unchecked_into::<MessageEvent>()
+                .data()
+                .as_string()
+                .ok_or(JsValue::from_str("expected the handshake response to be a string"))?
         };
 
         console_log!("Decoding response");
@@ -243,7 +613,7 @@ impl WasmWebSocket {
                 Layer8Envelope::WebSocket(payload) => {
                     // we only care about the metadata to make sure the tunnel is secure
                     serde_json::from_value::<serde_json::Map<String, serde_json::Value>>(payload.metadata)
-                        .expect_throw("we expect a json object as the metadata")
+                        .map_err(|_| JsValue::from_str("we expect a json object as the metadata"))?
                 }
                 _ => {
                     return Err(JsValue::from_str("we expect a websocket response"));
@@ -251,17 +621,356 @@ impl WasmWebSocket {
             }
         };
 
-        WS_UP_JWT.set(proxy_data.remove("up-JWT").ok_or("up_jwt not found")?.as_str().unwrap().to_string());
+        let up_jwt = proxy_data.remove("up-JWT").ok_or("up_jwt not found")?;
+        let up_jwt = up_jwt.as_str().ok_or("expected up_jwt to be a string")?.to_string();
+
+        let selected = proxy_data.remove(SELECTED_PROTOCOL);
+        let protocol = negotiated_protocol(options.protocols.as_deref(), selected.as_ref().and_then(|v| v.as_str())).map_err(|e| {
+            _ = socket.close();
+            JsValue::from(JsError::new(&e))
+        })?;
+        let extensions = proxy_data
+            .remove(NEGOTIATED_EXTENSIONS)
+            .and_then(|v| v.as_str().map(str::to_string))
+            .unwrap_or_default();
 
         let shared_key = private_jwk_ecdh.get_ecdh_shared_secret(&jwk_from_map(proxy_data)?)?;
 
-        WS_USER_SYMMETRIC_KEY.set(Some(shared_key.clone()));
-        WS_ENCRYPTED_TUNNEL_FLAG.set(true);
-        LAYER8_SOCKETS.with_borrow_mut(|val| {
-            val.insert(rebuilt_url.clone(), WasmWebSocket { socket });
+        Ok((
+            socket,
+            Session {
+                symmetric_key: shared_key,
+                up_jwt,
+                client_uuid: uuid,
+                protocol,
+                extensions,
+            },
+        ))
+    }
+
+    // Relays the events of the browser socket to the listeners of the connection with the provided id.
+    fn attach(id: &str, socket: &BrowserWebSocket) {
+        socket.set_onmessage(Some(&preprocess_on_message(id)));
+
+        let errored = id.to_string();
+        let on_error = Closure::<dyn FnMut(Event)>::new(move |_event: Event| {
+            console_error!(&format!("The socket {} errored: {:?}", errored, _event));
+            if let Ok(event) = Event::new("error") {
+                dispatch(&errored, &event);
+            }
         });
+        socket.set_onerror(Some(on_error.into_js_value().unchecked_ref()));
 
-        Ok(WasmWebSocketRef(rebuilt_url))
+        let (id, watched) = (id.to_string(), socket.clone());
+        let on_close = Closure::<dyn FnMut(CloseEvent)>::new(move |event: CloseEvent| Self::on_socket_close(&id, &watched, event));
+        socket.set_onclose(Some(on_close.into_js_value().unchecked_ref()));
+    }
+
+    // The browser socket closed, the connection is restored unless it was closed by `close` or the reconnection is
+    // disabled.
+    fn on_socket_close(id: &str, socket: &BrowserWebSocket, event: CloseEvent) {
+        // the sockets replaced by a reconnection are not followed anymore
+        let reconnect = LAYER8_SOCKETS.with_borrow_mut(|sockets| {
+            let ws = sockets.get_mut(id).filter(|ws| ws.socket == *socket)?;
+            ws.reconnecting = ws.config.reconnect && !ws.closing;
+            Some(ws.reconnecting)
+        });
+
+        match reconnect {
+            Some(true) => wasm_bindgen_futures::spawn_local(Self::reconnect(id.to_string(), event)),
+            Some(false) => {
+                if let Ok(event) = close_event(event.code(), &event.reason(), event.was_clean()) {
+                    dispatch(id, &event);
+                }
+                remove(id);
+            }
+            None => {}
+        }
+    }
+
+    // Restores the connection with a new handshake, backing off exponentially between the attempts. The `close`
+    // event of the dropped socket is dispatched if the attempts run out.
+    async fn reconnect(id: String, dropped: CloseEvent) {
+        let Some(config) = LAYER8_SOCKETS.with_borrow(|sockets| sockets.get(&id).map(|ws| ws.config.clone())) else {
+            return;
+        };
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            if config.max_reconnect_attempts.is_some_and(|max| attempt > max) {
+                console_error!(&format!("Giving up on reconnecting the socket {} after {} attempts", id, attempt - 1));
+                LAYER8_SOCKETS.with_borrow_mut(|sockets| {
+                    if let Some(ws) = sockets.get_mut(&id) {
+                        ws.reconnecting = false;
+                        ws.queue.discard();
+                    }
+                });
+                if let Ok(event) = close_event(dropped.code(), &dropped.reason(), dropped.was_clean()) {
+                    dispatch(&id, &event);
+                }
+                remove(&id);
+                return;
+            }
+
+            let delay = backoff_delay(attempt, config.reconnect_delay, config.max_reconnect_delay, js_sys::Math::random());
+            if let Ok(event) = reconnect_event("reconnecting", attempt, Some(delay)) {
+                dispatch(&id, &event);
+            }
+
+            _ = sleep(delay).await;
+
+            // `close` was called in the meantime
+            if !LAYER8_SOCKETS.with_borrow(|sockets| sockets.get(&id).is_some_and(|ws| ws.reconnecting)) {
+                return;
+            }
+
+            let (socket, session) = match Self::handshake(&config).await {
+                Ok(handshake) => handshake,
+                Err(_e) => {
+                    console_error!(&format!("Failed to reconnect the socket {}: {:?}", id, _e));
+                    continue;
+                }
+            };
+
+            let restored = LAYER8_SOCKETS.with_borrow_mut(|sockets| match sockets.get_mut(&id) {
+                Some(ws) if ws.reconnecting => {
+                    Self::attach(&id, &socket);
+                    ws.socket = socket.clone();
+                    ws.session = session;
+                    ws.reconnecting = false;
+                    true
+                }
+                _ => false,
+            });
+
+            if !restored {
+                _ = socket.close();
+                return;
+            }
+
+            console_log!(&format!("Reconnected the socket {} after {} attempts", id, attempt));
+            flush_queue(&id);
+            if let Ok(event) = reconnect_event("reconnected", attempt, None) {
+                dispatch(&id, &event);
+            }
+
+            return;
+        }
+    }
+}
+
+impl WasmWebSocket {
+    // Pings the backend through the proxy every `interval` milliseconds. A pong missing after `timeout` milliseconds
+    // means the connection is dead even if the browser socket looks open, it is dropped as if it closed. The heartbeat
+    // stops with the connection.
+    async fn heartbeat(id: String, interval: u32, timeout: u32) {
+        let mut nonce = 0u64;
+        let mut wait = interval;
+        loop {
+            _ = sleep(wait).await;
+            wait = interval.saturating_sub(timeout);
+            nonce += 1;
+
+            let ping = LAYER8_SOCKETS.with_borrow_mut(|sockets| {
+                let ws = sockets.get_mut(&id)?;
+                if ws.closing || (!ws.reconnecting && ws.socket.ready_state() == BrowserWebSocket::CLOSED) {
+                    return None;
+                }
+
+                // the connection is being restored, the next ping goes to the new socket
+                ws.ping = (!ws.reconnecting).then(|| (nonce, js_sys::Date::now()));
+                Some(ws.ping.is_some())
+            });
+
+            match ping {
+                Some(true) => {}
+                Some(false) => {
+                    wait = interval;
+                    continue;
+                }
+                None => return,
+            }
+
+            let frame = Frame {
+                data: nonce.to_string().into_bytes(),
+                frame_type: FrameType::Ping,
+            };
+            if let Err(_e) = encrypt_and_send(&id, &frame) {
+                console_error!(&format!("Failed to send the heartbeat of the socket {}: {:?}", id, _e));
+            }
+
+            _ = sleep(timeout).await;
+
+            let dead = LAYER8_SOCKETS.with_borrow_mut(|sockets| {
+                let ws = sockets.get_mut(&id)?;
+                ws.ping.take_if(|(pending, _)| *pending == nonce)?;
+                (!ws.closing && !ws.reconnecting).then(|| ws.socket.clone())
+            });
+
+            if let Some(socket) = dead {
+                console_error!(&format!("The socket {} missed its heartbeat, dropping it", id));
+                socket.set_onclose(None);
+                _ = socket.close();
+                if let Ok(event) = close_event(1006, "heartbeat timeout", false) {
+                    Self::on_socket_close(&id, &socket, event);
+                }
+            }
+        }
+    }
+
+    // Answers a heartbeat ping, or records the round trip of the pong of the pending one.
+    fn on_heartbeat(id: &str, frame: Frame) {
+        match frame.frame_type {
+            FrameType::Ping => {
+                let pong = Frame {
+                    data: frame.data,
+                    frame_type: FrameType::Pong,
+                };
+                if let Err(_e) = encrypt_and_send(id, &pong) {
+                    console_error!(&format!("Failed to answer the heartbeat of the socket {}: {:?}", id, _e));
+                }
+            }
+            FrameType::Pong => LAYER8_SOCKETS.with_borrow_mut(|sockets| {
+                let Some(ws) = sockets.get_mut(id) else {
+                    return;
+                };
+
+                let nonce = String::from_utf8_lossy(&frame.data).parse::<u64>().ok();
+                if let Some((_, sent_at)) = ws.ping.take_if(|(pending, _)| Some(*pending) == nonce) {
+                    ws.latency = Some(js_sys::Date::now() - sent_at);
+                }
+            }),
+            _ => {}
+        }
+    }
+}
+
+// Sends the frames queued while the connection was being opened or restored, in order. The blocked frames are
+// queued as the frames ahead of them are sent.
+fn flush_queue(id: &str) {
+    while let Some(queued) = LAYER8_SOCKETS.with_borrow_mut(|sockets| sockets.get_mut(id).and_then(|ws| ws.queue.pop())) {
+        match (encrypt_and_send(id, &queued.frame), queued.blocked) {
+            (Ok(()), Some((resolve, _))) => _ = resolve.call0(&JsValue::UNDEFINED),
+            (Err(e), Some((_, reject))) => _ = reject.call1(&JsValue::UNDEFINED, &e),
+            (Err(_e), None) => console_error!(&format!("Failed to send a queued frame: {:?}", _e)),
+            (Ok(()), None) => {}
+        }
+    }
+}
+
+// Waits for the first event of `type_` on the socket, fails if the socket closes first or if `timeout` milliseconds
+// pass. The listeners are added when the function is called, no event is missed between the call and the await.
+fn first_event(socket: BrowserWebSocket, type_: &'static str, timeout: u32) -> impl Future<Output = Result<Event, JsValue>> {
+    let (tx, rx) = oneshot::channel();
+    let tx = Rc::new(RefCell::new(Some(tx)));
+    let settle = |expected: bool| {
+        let tx = tx.clone();
+        Closure::<dyn FnMut(Event)>::new(move |event: Event| {
+            if let Some(tx) = tx.borrow_mut().take() {
+                _ = tx.send(if expected {
+                    Ok(event)
+                } else {
+                    Err(JsValue::from_str("the socket closed"))
+                });
+            }
+        })
+    };
+
+    let (on_event, on_close) = (settle(true), settle(false));
+    _ = socket.add_event_listener_with_callback(type_, on_event.as_ref().unchecked_ref());
+    _ = socket.add_event_listener_with_callback("close", on_close.as_ref().unchecked_ref());
+
+    let expired = Rc::downgrade(&tx);
+    wasm_bindgen_futures::spawn_local(async move {
+        _ = sleep(timeout).await;
+        if let Some(tx) = expired.upgrade().and_then(|tx| tx.borrow_mut().take()) {
+            _ = tx.send(Err(JsValue::from_str(&format!("timed out waiting for the {} event", type_))));
+        }
+    });
+
+    async move {
+        let res = rx.await.unwrap_or(Err(JsValue::from_str("the socket was dropped")));
+        _ = socket.remove_event_listener_with_callback(type_, on_event.as_ref().unchecked_ref());
+        _ = socket.remove_event_listener_with_callback("close", on_close.as_ref().unchecked_ref());
+        res
+    }
+}
+
+// Forgets the connection with the provided id, once its final `close` event is dispatched. Dropping it closes the browser
+// socket, and the heartbeat stops with it.
+fn remove(id: &str) {
+    // the queued frames are rejected once the sockets are not borrowed anymore
+    let removed = LAYER8_SOCKETS.with_borrow_mut(|sockets| sockets.remove(id));
+    drop(removed);
+}
+
+// Dispatches the event to the listeners of the connection with the provided id.
+fn dispatch(id: &str, event: &Event) {
+    // the listeners may call back into the socket, the target is not borrowed while they run
+    let Some(target) = LAYER8_SOCKETS.with_borrow(|sockets| sockets.get(id).map(|ws| ws.target.clone())) else {
+        return;
+    };
+
+    if let Err(_err) = target.dispatch_event(event) {
+        console_error!(&format!("Failed to dispatch the {} event: {:?}", event.type_(), _err));
+    }
+}
+
+// A `close` event as the browser's WebSocket emits it.
+fn close_event(code: u16, reason: &str, was_clean: bool) -> Result<CloseEvent, JsValue> {
+    let init = CloseEventInit::new();
+    init.set_code(code);
+    init.set_reason(reason);
+    init.set_was_clean(was_clean);
+    CloseEvent::new_with_event_init_dict("close", &init)
+}
+
+// The `reconnecting` and `reconnected` events, their `detail` holds the attempt and the delay before it.
+fn reconnect_event(type_: &str, attempt: u32, delay: Option<u32>) -> Result<Event, JsValue> {
+    let detail = js_sys::Object::new();
+    js_sys::Reflect::set(&detail, &"attempt".into(), &attempt.into())?;
+    if let Some(delay) = delay {
+        js_sys::Reflect::set(&detail, &"delay".into(), &delay.into())?;
+    }
+
+    let init = CustomEventInit::new();
+    init.set_detail(&detail);
+    Ok(CustomEvent::new_with_event_init_dict(type_, &init)?.into())
+}
+
+// The delay before the reconnection `attempt`: `base` doubled with each attempt, up to `max`. Its upper half is
+// scaled by `random`, in [0, 1), so the clients dropped together don't all come back at once.
+fn backoff_delay(attempt: u32, base: u32, max: u32, random: f64) -> u32 {
+    let delay = base.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1))).min(max);
+    let half = delay / 2;
+    half + (f64::from(delay - half) * random) as u32
+}
+
+impl WasmWebSocketRef {
+    // The target the listeners of this socket are added to.
+    fn target(&self) -> Result<EventTarget, JsValue> {
+        LAYER8_SOCKETS
+            .with_borrow(|val| val.get(&self.id()).map(|val| val.target.clone()))
+            .ok_or(JsValue::from_str("Socket not found, `init` must resolve first"))
+    }
+
+    // Replaces the event handler property of `type_`, `onmessage` and co.
+    fn set_handler(&self, type_: &'static str, value: Option<Function>) {
+        LAYER8_SOCKETS.with_borrow_mut(|val| {
+            let Some(stream) = val.get_mut(&self.id()) else {
+                return;
+            };
+
+            if let Some(previous) = stream.handlers.remove(type_) {
+                _ = stream.target.remove_event_listener_with_callback(type_, &previous);
+            }
+
+            if let Some(value) = value {
+                _ = stream.target.add_event_listener_with_callback(type_, &value);
+                stream.handlers.insert(type_, value);
+            }
+        });
     }
 }
 
@@ -270,18 +979,28 @@ impl WasmWebSocket {
 impl WasmWebSocketRef {
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
     #[wasm_bindgen(getter)]
-    /// Getter for the `url` field of this object.
+    /// Getter for the `url` field of this object, the backend URL the socket was opened for.
     pub fn url(&self) -> String {
-        self.0.clone()
+        LAYER8_SOCKETS
+            .with_borrow(|val| val.get(&self.id()).map(|val| val.config.url.clone()))
+            .unwrap_or_default()
     }
 
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
     #[wasm_bindgen(getter)]
     /// Getter for the `readyState` field of this object.
+    /// A socket being reconnected is `CONNECTING`.
     pub fn ready_state(&self) -> u16 {
         LAYER8_SOCKETS
-            .with_borrow(|val| val.get(&self.0).map(|val| val.socket.ready_state()))
-            .unwrap_or_default()
+            .with_borrow(|val| {
+                val.get(&self.id()).map(|val| match val.reconnecting {
+                    true => BrowserWebSocket::CONNECTING,
+                    false => val.socket.ready_state(),
+                })
+            })
+            .or_else(|| CONNECTING.with_borrow(|queues| queues.contains_key(&self.id()).then_some(BrowserWebSocket::CONNECTING)))
+            // the connection is forgotten once closed
+            .unwrap_or(BrowserWebSocket::CLOSED)
     }
 
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
@@ -289,7 +1008,7 @@ impl WasmWebSocketRef {
     /// Getter for the `bufferedAmount` field of this object.
     pub fn buffered_amount(&self) -> u32 {
         LAYER8_SOCKETS
-            .with_borrow(|val| val.get(&self.0).map(|val| val.socket.buffered_amount()))
+            .with_borrow(|val| val.get(&self.id()).map(|val| val.socket.buffered_amount()))
             .unwrap_or_default()
     }
 
@@ -297,121 +1016,133 @@ impl WasmWebSocketRef {
     #[wasm_bindgen(getter)]
     /// Getter for the `onopen` field of this object.
     pub fn onopen(&self) -> Option<Function> {
-        LAYER8_SOCKETS
-            .with_borrow(|val| val.get(&self.0).map(|val| val.socket.onopen()))
-            .unwrap_or_default()
+        LAYER8_SOCKETS.with_borrow(|val| val.get(&self.id()).and_then(|val| val.handlers.get("open").cloned()))
     }
 
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
     #[wasm_bindgen(js_name = onopen, setter)]
     /// Setter for the `onopen` field of this object.
     pub fn set_onopen(&self, value: Option<Function>) {
-        LAYER8_SOCKETS.with_borrow_mut(|val| {
-            if let Some(stream) = val.get_mut(&self.0) {
-                stream.socket.set_onopen(value.as_ref());
-            }
-        });
+        self.set_handler("open", value);
     }
 
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
     #[wasm_bindgen(getter)]
     /// Getter for the `onerror` field of this object.
     pub fn onerror(&self) -> Option<Function> {
-        LAYER8_SOCKETS
-            .with_borrow(|val| val.get(&self.0).map(|val| val.socket.onerror()))
-            .unwrap_or_default()
+        LAYER8_SOCKETS.with_borrow(|val| val.get(&self.id()).and_then(|val| val.handlers.get("error").cloned()))
     }
 
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
     #[wasm_bindgen(js_name = onerror, setter)]
     /// Setter for the `onerror` field of this object.
+    /// An inbound frame that can't be delivered is skipped and reported with an `error` event whose `detail` holds the
+    /// `reason` (`not-a-string`, `malformed-envelope`, `unexpected-envelope`, `base64`, `decrypt` or `delivery`) and a
+    /// `message`; the connection stays open.
     pub fn set_onerror(&self, value: Option<Function>) {
-        LAYER8_SOCKETS.with_borrow_mut(|val| {
-            if let Some(stream) = val.get_mut(&self.0) {
-                stream.socket.set_onerror(value.as_ref());
-            }
-        });
+        self.set_handler("error", value);
     }
 
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
     #[wasm_bindgen(getter)]
     /// Getter for the `onclose` field of this object.
     pub fn onclose(&self) -> Option<Function> {
-        LAYER8_SOCKETS
-            .with_borrow(|val| val.get(&self.0).map(|val| val.socket.onclose()))
-            .unwrap_or_default()
+        LAYER8_SOCKETS.with_borrow(|val| val.get(&self.id()).and_then(|val| val.handlers.get("close").cloned()))
     }
 
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
     #[wasm_bindgen(js_name = onclose, setter)]
     /// Setter for the `onclose` field of this object.
+    /// It is not called when the connection drops and is being restored, see the `reconnecting` event.
     pub fn set_onclose(&self, value: Option<Function>) {
-        LAYER8_SOCKETS.with_borrow_mut(|val| {
-            if let Some(stream) = val.get_mut(&self.0) {
-                stream.socket.set_onclose(value.as_ref());
-            }
-        });
+        self.set_handler("close", value);
     }
 
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
     #[wasm_bindgen(getter)]
-    /// Getter for the `extensions` field of this object.
+    /// Getter for the `extensions` field of this object, the extensions negotiated with the backend.
     pub fn extensions(&self) -> String {
         LAYER8_SOCKETS
-            .with_borrow(|val| val.get(&self.0).map(|val| val.socket.extensions()))
+            .with_borrow(|val| val.get(&self.id()).map(|val| val.session.extensions.clone()))
             .unwrap_or_default()
     }
 
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
     #[wasm_bindgen(getter)]
-    /// Getter for the `protocol` field of this object.
+    /// Getter for the `protocol` field of this object, the subprotocol the backend selected.
     pub fn protocol(&self) -> String {
         LAYER8_SOCKETS
-            .with_borrow(|val| val.get(&self.0).map(|val| val.socket.protocol()))
+            .with_borrow(|val| val.get(&self.id()).map(|val| val.session.protocol.clone()))
             .unwrap_or_default()
     }
 
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
     #[wasm_bindgen(getter)]
-    /// Getter for the `binaryType` field of this object.
+    /// Getter for the `onmessage` field of this object.
     pub fn onmessage(&self) -> Option<Function> {
-        LAYER8_SOCKETS
-            .with_borrow(|val| val.get(&self.0).map(|val| val.socket.onmessage()))
-            .unwrap_or_default()
+        LAYER8_SOCKETS.with_borrow(|val| val.get(&self.id()).and_then(|val| val.handlers.get("message").cloned()))
     }
 
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
     #[wasm_bindgen(js_name = onmessage, setter)]
-    /// Setter for the `binaryType` field of this object.
+    /// Setter for the `onmessage` field of this object.
     pub fn set_onmessage(&self, value: Option<Function>) {
-        // self.on_receive(value);
-        LAYER8_SOCKETS.with_borrow_mut(|val| {
-            if let Some(stream) = val.get_mut(&self.0) {
-                // we need yo overwrite the on
-                stream.socket.set_onmessage(Some(&preprocess_on_message(value)));
-            }
-        });
+        self.set_handler("message", value);
     }
 
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
     #[wasm_bindgen(js_name = addEventListener)]
-    /// Sets an event listener on the WebSocket object.
-    /// It intercepts the `message` event and plugs in our custom logic to decrypt the message.
-    pub fn add_event_listener(&self, type_: &str, listener: Option<Function>) {
-        LAYER8_SOCKETS.with_borrow_mut(|val| {
-            let stream = val.get_mut(&self.0).expect_throw(&format!("Socket with url {} not found", self.0));
-            if type_.eq_ignore_ascii_case("message") {
-                stream.socket.set_onmessage(Some(&preprocess_on_message(listener)));
-                return;
+    /// Adds an event listener to the WebSocket object, with the `options` of `EventTarget.addEventListener`.
+    /// The `message` events carry the decrypted data. On top of the WebSocket events it emits `reconnecting` and
+    /// `reconnected` while the connection is restored, their `detail` holds the `attempt` and the `delay` in
+    /// milliseconds before it.
+    pub fn add_event_listener(&self, type_: &str, listener: Option<Function>, options: JsValue) -> Result<(), JsValue> {
+        let target = self.target()?;
+        let Some(listener) = listener else {
+            return Ok(());
+        };
+
+        match options.as_bool() {
+            Some(capture) => target.add_event_listener_with_callback_and_bool(type_, &listener, capture),
+            None if options.is_object() => {
+                target.add_event_listener_with_callback_and_add_event_listener_options(type_, &listener, options.unchecked_ref())
             }
+            None => target.add_event_listener_with_callback(type_, &listener),
+        }
+    }
 
-            if let Some(listener) = &listener {
-                stream
-                    .socket
-                    .add_event_listener_with_callback(type_, listener)
-                    .expect_throw(&format!("Failed to add event listener for type: {}", type_))
+    #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
+    #[wasm_bindgen(js_name = removeEventListener)]
+    /// Removes an event listener added with `addEventListener`, with the `options` of
+    /// `EventTarget.removeEventListener`.
+    pub fn remove_event_listener(&self, type_: &str, listener: Option<Function>, options: JsValue) -> Result<(), JsValue> {
+        let target = self.target()?;
+        let Some(listener) = listener else {
+            return Ok(());
+        };
+
+        match options.as_bool() {
+            Some(capture) => target.remove_event_listener_with_callback_and_bool(type_, &listener, capture),
+            None if options.is_object() => {
+                target.remove_event_listener_with_callback_and_event_listener_options(type_, &listener, options.unchecked_ref())
             }
-        });
+            None => target.remove_event_listener_with_callback(type_, &listener),
+        }
+    }
+
+    #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
+    #[wasm_bindgen(js_name = dispatchEvent)]
+    /// Dispatches the event to the listeners of the WebSocket object, it returns false if a listener canceled it.
+    pub fn dispatch_event(&self, event: &Event) -> Result<bool, JsValue> {
+        self.target()?.dispatch_event(event)
+    }
+
+    /// The round-trip time of the last heartbeat in milliseconds, see `InitConfig.heartbeatInterval`. It is undefined
+    /// until the first pong.
+    #[allow(dead_code, reason = "This is only called from JavaScript.")]
+    #[wasm_bindgen(getter)]
+    pub fn latency(&self) -> Option<f64> {
+        LAYER8_SOCKETS.with_borrow(|val| val.get(&self.id()).and_then(|val| val.latency))
     }
 
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
@@ -419,15 +1150,21 @@ impl WasmWebSocketRef {
     /// Getter for the `binaryType` field of this object.
     pub fn binary_type(&self) -> BinaryType {
         LAYER8_SOCKETS
-            .with_borrow(|val| val.get(&self.0).map(|val| val.socket.binary_type()))
-            .unwrap_or(BinaryType::Arraybuffer)
+            .with_borrow(|val| val.get(&self.id()).map(|val| val.binary_type))
+            .unwrap_or(BinaryType::Blob)
     }
 
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
     #[wasm_bindgen(js_name = binaryType, setter)]
     /// Setter for the `binaryType` field of this object.
-    /// This is a no-op since we layer8 dictates the binary type.
-    pub fn set_binary_type(&self, _: BinaryType) {}
+    /// It decides whether binary messages are delivered as a `Blob`, the default, or an `ArrayBuffer`.
+    pub fn set_binary_type(&self, value: BinaryType) {
+        LAYER8_SOCKETS.with_borrow_mut(|val| {
+            if let Some(stream) = val.get_mut(&self.id()) {
+                stream.binary_type = value;
+            }
+        });
+    }
 
     /// Constructor for the `WebSocket` object.
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
@@ -439,148 +1176,396 @@ impl WasmWebSocketRef {
     /// The options object is expected to have the following structure:
     /// ```js
     /// export interface InitConfig {
-    ///     // The URL of the service provider.
+    ///     // The URL of the service provider's endpoint, including its path and query. Each L8WebSocket opens its own
+    ///     // connection, several can be open to the same service provider.
     ///     url: string;
     ///     // The Layer8 proxy URL to connect to.
     ///     proxy: string;
-    ///     // The protocols to use for the ws connection.
+    ///     // The subprotocols requested from the backend, `init` fails if it accepts none of them. The one it selected
+    ///     // is `protocol`.
     ///     protocols?: string | string[] | undefined;
+    ///     // Restores the connection when the proxy socket drops, defaults to true.
+    ///     reconnect?: boolean;
+    ///     // The delay before the first reconnection attempt in milliseconds, defaults to 1000. It doubles with each
+    ///     // failed attempt.
+    ///     reconnectDelay?: number;
+    ///     // The cap of the delay between two reconnection attempts in milliseconds, defaults to 30000.
+    ///     maxReconnectDelay?: number;
+    ///     // The number of reconnection attempts before the socket closes, unlimited by default.
+    ///     maxReconnectAttempts?: number;
+    ///     // The number of frames queued by `send` while the connection is being opened or restored, defaults to 64.
+    ///     // They are sent in order once it is.
+    ///     sendQueueSize?: number;
+    ///     // What `send` does once the queue is full: drop the oldest frame, throw, or return a promise that resolves
+    ///     // once there was room for the frame and it is sent. Defaults to "drop-oldest".
+    ///     sendQueuePolicy?: "drop-oldest" | "reject" | "block";
+    ///     // The delay between two encrypted heartbeat pings in milliseconds, the heartbeat is off by default. The
+    ///     // round trip of the last one is `latency`.
+    ///     heartbeatInterval?: number;
+    ///     // The delay after which a ping without its pong drops the connection, in milliseconds, defaults to 10000.
+    ///     // It is then restored, or closed with the code 1006, as if the proxy socket dropped.
+    ///     heartbeatTimeout?: number;
+    ///     // The delay after which the proxy not opening the socket or not answering the key exchange fails the
+    ///     // connection attempt, in milliseconds, defaults to 10000.
+    ///     handshakeTimeout?: number;
     /// }
     /// ```
     #[allow(dead_code)]
-    pub async fn init(&mut self, options: js_sys::Object) -> Result<(), JsValue> {
-        *self = WasmWebSocket::init_(options).await?;
-        Ok(())
+    pub async fn init(&self, options: js_sys::Object) -> Result<(), JsValue> {
+        WasmWebSocket::init_(self, options).await
     }
 
-    /// close the connection
+    /// close the connection, it is not restored after
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
     pub fn close(&self, code: Option<u16>, reason: Option<String>) -> Result<(), JsValue> {
-        LAYER8_SOCKETS.with_borrow_mut(|val| {
-            if let Some(val) = val.get_mut(&self.0) {
-                match (code, reason) {
-                    (Some(code), Some(reason)) => val.socket.close_with_code_and_reason(code, &reason),
-                    (Some(code), None) => val.socket.close_with_code(code),
-                    _ => val.socket.close(),
-                }
-            } else {
-                Err(JsValue::from_str("Socket not found"))
-            }
-        })
+        // the connection being opened is given up, `init` fails
+        let connecting = CONNECTING.with_borrow_mut(|queues| queues.remove(&self.id()));
+        if connecting.is_some() {
+            return Ok(());
+        }
+
+        let (res, interrupted) = LAYER8_SOCKETS.with_borrow_mut(|val| {
+            // the connection is forgotten once closed, closing it again does nothing as with the browser's WebSocket
+            let Some(val) = val.get_mut(&self.id()) else {
+                return (Ok(()), false);
+            };
+
+            val.closing = true;
+            let interrupted = std::mem::take(&mut val.reconnecting);
+            val.queue.discard();
+            let res = match (code, reason.as_ref()) {
+                (Some(code), Some(reason)) => val.socket.close_with_code_and_reason(code, reason),
+                (Some(code), None) => val.socket.close_with_code(code),
+                _ => val.socket.close(),
+            };
+
+            (res, interrupted)
+        });
+
+        // the dropped socket already closed, it won't report this closure
+        if interrupted {
+            let dispatched = close_event(code.unwrap_or(1005), reason.as_deref().unwrap_or_default(), true).map(|event| dispatch(&self.id(), &event));
+            remove(&self.id());
+            dispatched?;
+        }
+
+        res
     }
 
     #[allow(dead_code, reason = "This is for API compatibility with the browser's WebSocket API.")]
-    pub fn send(&self, data: JsValue) -> Result<(), JsValue> {
+    /// Sends the data over the socket. Types checked: string, Blob, ArrayBuffer, Uint8Array. Strings are received as
+    /// text messages, the other types as binary messages.
+    ///
+    /// A Blob is read asynchronously, the data sent while it is being read is queued behind it so the frames keep
+    /// their order. While the connection is being opened or restored the frames are queued, see `InitConfig.sendQueueSize`.
+    ///
+    /// It returns a promise for the frames that are not handed over right away, Blobs and the frames blocked by a full
+    /// queue, that resolves once they are.
+    pub fn send(&self, data: JsValue) -> Result<Option<Promise>, JsValue> {
         console_log!(&format!("Sending data: {:?}", data));
 
-        let symmetric_key = match WS_USER_SYMMETRIC_KEY.with_borrow(|v| v.clone()) {
-            Some(v) => v,
-            None => return Err(JsValue::from_str("Symmetric key not found")),
-        };
+        let previous = BLOB_SENDS.with_borrow(|sends| sends.get(&self.id()).map(|(_, promise)| promise.clone()));
+        if previous.is_none() && !data.is_instance_of::<Blob>() {
+            return send_frame(&self.id(), frame(&data)?);
+        }
+
+        let id = self.id();
+        let previous = previous.unwrap_or_else(|| Promise::resolve(&JsValue::UNDEFINED));
+        let next = future_to_promise(async move {
+            // the previous frame reports its own failure
+            let _ = JsFuture::from(previous).await;
+
+            let res = match data.dyn_ref::<Blob>() {
+                Some(blob) => read_blob(blob).await.map(|data| Frame {
+                    data,
+                    frame_type: FrameType::Binary,
+                }),
+                None => frame(&data),
+            }
+            .and_then(|frame| send_frame(&id, frame));
+
+            // the frames behind this one wait for it to be unblocked as well
+            let res = match res {
+                Ok(Some(blocked)) => JsFuture::from(blocked).await.map(|_| ()),
+                Ok(None) => Ok(()),
+                Err(e) => Err(e),
+            };
+
+            BLOB_SENDS.with_borrow_mut(|sends| {
+                if let Some((pending, _)) = sends.get_mut(&id) {
+                    *pending -= 1;
+                    if *pending == 0 {
+                        sends.remove(&id);
+                    }
+                }
+            });
+
+            res.inspect_err(|_e| console_error!(&format!("Failed to send data: {:?}", _e)))
+                .map(|_| JsValue::UNDEFINED)
+        });
+
+        BLOB_SENDS.with_borrow_mut(|sends| {
+            let entry = sends.entry(self.id()).or_insert((0, next.clone()));
+            entry.0 += 1;
+            entry.1 = next.clone();
+        });
+
+        Ok(Some(next))
+    }
+}
+
+// The frame of a string, ArrayBuffer or Uint8Array.
+fn frame(data: &JsValue) -> Result<Frame, JsValue> {
+    let (data, frame_type) = if let Some(data) = data.as_string() {
+        (data.into_bytes(), FrameType::Text)
+    } else if data.is_instance_of::<ArrayBuffer>() {
+        (Uint8Array::new(data).to_vec(), FrameType::Binary)
+    } else if let Some(data) = data.dyn_ref::<Uint8Array>() {
+        (data.to_vec(), FrameType::Binary)
+    } else {
+        return Err(JsValue::from_str("Unsupported data type"));
+    };
+
+    Ok(Frame { data, frame_type })
+}
+
+// Sends the frame, or queues it while the connection is being opened or restored. The promise is returned for a frame blocked by
+// a full queue, see `QueuePolicy::Block`.
+fn send_frame(id: &str, frame: Frame) -> Result<Option<Promise>, JsValue> {
+    if CONNECTING.with_borrow(|queues| queues.contains_key(id)) {
+        return CONNECTING.with_borrow_mut(|queues| queues.get_mut(id).ok_or("Socket not found")?.push(frame));
+    }
+
+    let reconnecting = LAYER8_SOCKETS
+        .with_borrow(|sockets| sockets.get(id).map(|ws| ws.reconnecting))
+        .ok_or("Socket not found")?;
+
+    if !reconnecting {
+        return encrypt_and_send(id, &frame).map(|_| None);
+    }
+
+    LAYER8_SOCKETS.with_borrow_mut(|sockets| sockets.get_mut(id).ok_or("Socket not found")?.queue.push(frame))
+}
 
-        let client_uuid = WS_UUID
-            .with_borrow(|v| v.get(&get_base_url(&self.0)).cloned())
-            .ok_or(JsValue::from("could not find a client_uuid for the provided id"))?;
+// Encrypts the frame and sends it over the socket with the provided id.
+fn encrypt_and_send(id: &str, frame: &Frame) -> Result<(), JsValue> {
+    LAYER8_SOCKETS.with_borrow_mut(|v| {
+        let ws = v.get_mut(id).ok_or("Socket not found")?;
 
-        let mut ws_exchange = WebSocketPayload {
+        let encrypted = ws.session.symmetric_key.symmetric_encrypt(&frame.data)?;
+        let ws_exchange = WebSocketPayload {
             metadata: json!({
-                "backend_url": self.0.to_string(),
-                "x-client-uuid": client_uuid,
+                "backend_url": ws.config.url,
+                "x-client-uuid": ws.session.client_uuid,
+                FRAME_TYPE: frame.frame_type.as_str(),
             }),
-            payload: None,
+            payload: Some(base64_enc_dec.encode(&encrypted)),
         };
 
-        // Types checked: string, Blob, ArrayBuffer, Uint8Array
-        if data.is_string() {
-            let encrypted = symmetric_key.symmetric_encrypt(data.as_string().unwrap().as_bytes())?;
-            ws_exchange.payload = Some(base64_enc_dec.encode(&encrypted));
-        } else if data.is_instance_of::<Blob>() {
-            let data = {
-                let reader = FileReaderSync::new().expect_throw("Failed to create FileReader");
-                let array = reader.read_as_array_buffer(&data.dyn_into::<Blob>().expect("check already done; qed"))?;
-                Uint8Array::new(&array).to_vec()
-            };
-            let encrypted = symmetric_key.symmetric_encrypt(&data)?;
-            ws_exchange.payload = Some(base64_enc_dec.encode(&encrypted));
-        } else if data.is_instance_of::<ArrayBuffer>() {
-            let data = Uint8Array::new(&data.dyn_into::<ArrayBuffer>().expect("check already done; qed")).to_vec();
-            let encrypted = symmetric_key.symmetric_encrypt(&data)?;
-            ws_exchange.payload = Some(base64_enc_dec.encode(&encrypted));
-        } else if data.is_instance_of::<Uint8Array>() {
-            let data = data.dyn_into::<Uint8Array>().expect("check already done; qed").to_vec();
-            let encrypted = symmetric_key.symmetric_encrypt(&data)?;
-            ws_exchange.payload = Some(base64_enc_dec.encode(&encrypted));
-        } else {
-            return Err(JsValue::from_str("Unsupported data type"));
-        }
+        let data = serde_json::to_vec(&Layer8Envelope::WebSocket(ws_exchange)).map_err(|e| e.to_string())?;
+        ws.socket.send_with_u8_array(&data)
+    })
+}
 
-        LAYER8_SOCKETS.with_borrow_mut(|v| {
-            let ws = v.get_mut(&get_base_url(self.0.as_str())).ok_or("Socket not found")?;
-            let data = serde_json::to_vec(&Layer8Envelope::WebSocket(ws_exchange)).map_err(|e| e.to_string())?;
-            ws.socket.send_with_u8_array(&data)
-        })
+// Why an inbound frame was skipped, it is the `reason` in the `detail` of the `error` event reporting it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InboundError {
+    // the proxy sends the envelopes as text frames
+    NotAString,
+    MalformedEnvelope,
+    // the envelope is not a WebSocket payload, or it has no payload
+    UnexpectedEnvelope,
+    Base64,
+    Decrypt,
+    // the message could not be turned into the `binaryType` or into a `MessageEvent`
+    Delivery,
+}
+
+impl InboundError {
+    fn as_str(self) -> &'static str {
+        match self {
+            InboundError::NotAString => "not-a-string",
+            InboundError::MalformedEnvelope => "malformed-envelope",
+            InboundError::UnexpectedEnvelope => "unexpected-envelope",
+            InboundError::Base64 => "base64",
+            InboundError::Decrypt => "decrypt",
+            InboundError::Delivery => "delivery",
+        }
     }
 }
 
-// this block decrypts the incoming message before passing it to the client.
-fn preprocess_on_message(pipeline: Option<Function>) -> Function {
+// Reads the encrypted data and the frame type out of an inbound envelope.
+fn open_envelope(msg: &str) -> Result<(Vec<u8>, FrameType), (InboundError, String)> {
+    let envelope = Layer8Envelope::from_json_bytes(msg.as_bytes()).map_err(|e| (InboundError::MalformedEnvelope, e.to_string()))?;
+
+    // we expect a websocket payload
+    let (payload, frame_type) = match envelope {
+        Layer8Envelope::WebSocket(WebSocketPayload {
+            payload: Some(payload),
+            metadata,
+        }) => (payload, FrameType::from_metadata(&metadata)),
+        _ => return Err((InboundError::UnexpectedEnvelope, "expected a WebSocket payload".to_string())),
+    };
+
+    let encrypted = base64_enc_dec.decode(payload).map_err(|e| (InboundError::Base64, e.to_string()))?;
+    Ok((encrypted, frame_type))
+}
+
+// The `error` event reporting a skipped inbound frame, its `detail` holds the `reason` and a `message`.
+fn inbound_error_event(reason: InboundError, message: &str) -> Result<Event, JsValue> {
+    let detail = js_sys::Object::new();
+    js_sys::Reflect::set(&detail, &"reason".into(), &reason.as_str().into())?;
+    js_sys::Reflect::set(&detail, &"message".into(), &message.into())?;
+
+    let init = CustomEventInit::new();
+    init.set_detail(&detail);
+    Ok(CustomEvent::new_with_event_init_dict("error", &init)?.into())
+}
+
+// this block decrypts the incoming message, with the key of the socket with the provided id, before dispatching it to
+// the listeners. A frame that can't be delivered is skipped and reported with an `error` event, the socket stays open.
+fn preprocess_on_message(id: &str) -> Function {
+    let id = id.to_string();
     let decrypt_callback = Closure::wrap(Box::new(move |message: MessageEvent| {
-        let symmetric_key = match WS_USER_SYMMETRIC_KEY.with_borrow(|v| v.clone()) {
-            Some(v) => v,
-            None => {
-                console_log!("Symmetric key not found");
-                return;
+        if let Err((reason, _message)) = deliver(&id, &message) {
+            console_error!(&format!(
+                "Skipped an inbound frame of the socket {} ({}): {}",
+                id,
+                reason.as_str(),
+                _message
+            ));
+            if let Ok(event) = inbound_error_event(reason, &_message) {
+                dispatch(&id, &event);
             }
-        };
+        }
+    }) as Box<dyn FnMut(MessageEvent)>);
 
-        let data: JsValue = {
-            let payload = {
-                console_log!(&format!("Inbound data: {:?}", &message.data()));
-                let msg = message.data().as_string().expect_throw("expected the message to be a string");
-                let envelope = Layer8Envelope::from_json_bytes(msg.as_bytes()).expect_throw(&format!(
-                    "Failed to parse inbound message: {}",
-                    message.data().as_string().expect_throw("expected the message frame to be a string")
-                ));
+    decrypt_callback.into_js_value().unchecked_into()
+}
 
-                // we expect a websocket payload
-                match envelope {
-                    Layer8Envelope::WebSocket(ws_payload) => ws_payload.payload,
-                    _ => {
-                        console_log!("Expected a WebSocket payload");
-                        return;
-                    }
-                }
-            };
+// Dispatches the decrypted data of the message to the listeners of the socket with the provided id.
+fn deliver(id: &str, message: &MessageEvent) -> Result<(), (InboundError, String)> {
+    let Some((symmetric_key, binary_type)) = LAYER8_SOCKETS.with_borrow(|v| v.get(id).map(|ws| (ws.session.symmetric_key.clone(), ws.binary_type)))
+    else {
+        console_log!("Symmetric key not found");
+        return Ok(());
+    };
+
+    console_log!(&format!("Inbound data: {:?}", &message.data()));
+    let msg = message
+        .data()
+        .as_string()
+        .ok_or((InboundError::NotAString, "expected the message to be a string".to_string()))?;
+
+    let (encrypted, frame_type) = open_envelope(&msg)?;
+    let slice = symmetric_key
+        .symmetric_decrypt(&encrypted)
+        .map_err(|e| (InboundError::Decrypt, e.to_string()))?;
+    let delivery_error = |e: JsValue| (InboundError::Delivery, format!("{:?}", e));
+    let data: JsValue = match (frame_type, binary_type) {
+        (FrameType::Ping | FrameType::Pong, _) => {
+            WasmWebSocket::on_heartbeat(id, Frame { data: slice, frame_type });
+            return Ok(());
+        }
+        (FrameType::Text, _) => JsValue::from_str(&String::from_utf8_lossy(&slice)),
+        (FrameType::Binary, BinaryType::Arraybuffer) => Uint8Array::from(slice.as_slice()).buffer().into(),
+        (FrameType::Binary, _) => Blob::new_with_u8_array_sequence(&js_sys::Array::of1(&Uint8Array::from(slice.as_slice())))
+            .map_err(delivery_error)?
+            .into(),
+    };
+
+    let msg_init = MessageEventInit::new();
+    msg_init.set_data(&data);
+    let msg_event = MessageEvent::new_with_event_init_dict("message", &msg_init).map_err(delivery_error)?;
+
+    dispatch(id, &msg_event);
+    Ok(())
+}
 
-            let slice = symmetric_key
-                .symmetric_decrypt(
-                    &base64_enc_dec
-                        .decode(payload.expect_throw("we expect there to be a payload in the response"))
-                        .expect_throw("Failed to decode base64 payload"),
-                )
-                .expect_throw("Failed to decrypt the message; this is a bug in the code, please report it to the developers");
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::*;
 
-            // we assume we are dealing with a string, here for now; todo!
-            JsValue::from_str(&String::from_utf8_lossy(&slice))
+    use super::*;
 
-            // Uint8Array::from(slice.as_slice()).into()
+    #[test]
+    fn backoff_delay_is_jittered_and_capped() {
+        assert_eq!(backoff_delay(1, 1000, 30_000, 0.0), 500);
+        assert_eq!(backoff_delay(3, 1000, 30_000, 0.0), 2000);
+        assert_eq!(backoff_delay(3, 1000, 30_000, 0.5), 3000);
+        assert!(backoff_delay(3, 1000, 30_000, 0.999) < 4000);
+
+        // capped, including when doubling overflows
+        assert_eq!(backoff_delay(6, 1000, 30_000, 0.0), 15_000);
+        assert_eq!(backoff_delay(40, 1000, 30_000, 0.0), 15_000);
+    }
+
+    #[test]
+    fn envelopes_are_opened_or_rejected_with_a_reason() {
+        let envelope = |payload: Option<&str>| {
+            let payload = WebSocketPayload {
+                metadata: json!({ FRAME_TYPE: "binary" }),
+                payload: payload.map(str::to_string),
+            };
+            serde_json::to_string(&Layer8Envelope::WebSocket(payload)).unwrap()
         };
 
-        let msg_event = {
-            let msg_init = MessageEventInit::new();
-            msg_init.set_data(&data);
-            MessageEvent::new_with_event_init_dict("message", &msg_init).expect_throw("Failed to create MessageEventInit")
+        let opened = open_envelope(&envelope(Some(&base64_enc_dec.encode(b"encrypted"))));
+        assert_eq!(opened, Ok((b"encrypted".to_vec(), FrameType::Binary)));
+
+        let reason = |msg: &str| open_envelope(msg).unwrap_err().0;
+        assert_eq!(reason("{ not json"), InboundError::MalformedEnvelope);
+        assert_eq!(reason(&envelope(None)), InboundError::UnexpectedEnvelope);
+        assert_eq!(reason(&envelope(Some("not base64!"))), InboundError::Base64);
+    }
+
+    #[test]
+    fn protocols_are_validated_and_negotiated() {
+        let requested = ["chat.v2".to_string(), "chat".to_string()];
+        assert_eq!(validate_protocols(&requested), Ok(()));
+        assert!(validate_protocols(&["chat".to_string(), "chat".to_string()]).is_err());
+        assert!(validate_protocols(&["chat v2".to_string()]).is_err());
+        assert!(validate_protocols(&[String::new()]).is_err());
+
+        assert_eq!(negotiated_protocol(Some(&requested), Some("chat")), Ok("chat".to_string()));
+        assert!(negotiated_protocol(Some(&requested), Some("mqtt")).is_err());
+        assert!(negotiated_protocol(Some(&requested), None).is_err());
+        assert_eq!(negotiated_protocol(None, Some("chat")), Ok(String::new()));
+    }
+
+    #[test]
+    fn frame_type_defaults_to_text() {
+        assert_eq!(FrameType::from_metadata(&json!({ FRAME_TYPE: "binary" })), FrameType::Binary);
+        assert_eq!(FrameType::from_metadata(&json!({ FRAME_TYPE: "pong" })), FrameType::Pong);
+        assert_eq!(FrameType::from_metadata(&json!({ FRAME_TYPE: "text" })), FrameType::Text);
+
+        // frames of peers that don't set the type
+        assert_eq!(FrameType::from_metadata(&json!({ "backend_url": "ws://localhost" })), FrameType::Text);
+    }
+
+    #[wasm_bindgen_test]
+    fn blocked_frames_wait_for_room_in_the_queue() {
+        let mut queue = SendQueue {
+            frames: VecDeque::new(),
+            blocked: VecDeque::new(),
+            size: 2,
+            policy: QueuePolicy::Block,
+        };
+        let frame = |data: &[u8]| Frame {
+            data: data.to_vec(),
+            frame_type: FrameType::Text,
         };
 
-        if let Some(pipeline) = pipeline.as_ref() {
-            if let Err(_err) = pipeline.call1(&JsValue::NULL, &msg_event) {
-                console_error!(&format!("Failed to call pipeline: {:?}", _err));
-            }
-        }
-    }) as Box<dyn FnMut(MessageEvent)>);
+        assert!(queue.push(frame(b"1")).unwrap().is_none());
+        assert!(queue.push(frame(b"2")).unwrap().is_none());
+        assert!(queue.push(frame(b"3")).unwrap().is_some());
+        assert!(queue.push(frame(b"4")).unwrap().is_some());
+        assert_eq!((queue.frames.len(), queue.blocked.len()), (2, 2));
 
-    decrypt_callback.into_js_value().dyn_into().unwrap()
+        // each frame sent makes room for a blocked one, in order
+        let sent = std::iter::from_fn(|| queue.pop()).map(|queued| queued.frame.data).collect::<Vec<_>>();
+        assert_eq!(sent, [b"1", b"2", b"3", b"4"]);
+    }
 }
 
-// TODO: map API 1:1 from socket.io
-pub mod socket_io {}
+pub mod socket_io;
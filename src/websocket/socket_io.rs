@@ -0,0 +1,1038 @@
+//! A Socket.IO client, protocol v5 over Engine.IO v4, on top of the encrypted `L8WebSocket` transport. The namespaces
+//! of a server share one L8WebSocket:
+//!
+//! ```js
+//! import init, { L8SocketIO } from 'layer8-interceptor-rs';
+//! await init();
+//! const socket = await L8SocketIO.connect({ url: 'http://localhost:8000/chat', proxy: 'ws://localhost:5001' });
+//! socket.on('message', (from, text) => console.log(from, text));
+//! socket.emit('message', ['hello']);
+//! const joined = await socket.emitWithAck('join', ['room-1']);
+//! ```
+//!
+//! Exported functions can't be variadic, the arguments of `emit` are passed as an array. ArrayBuffers and typed arrays
+//! anywhere in them are sent as binary attachments, the attachments are received as ArrayBuffers.
+//!
+//! Rooms are a server-side concept of Socket.IO: the server puts the sockets in rooms, usually on an event they emit,
+//! and the events broadcast to a room are received as any other event.
+
+use std::{cell::RefCell, collections::HashMap};
+
+use js_sys::{Array, ArrayBuffer, Function, JSON, Object, Promise, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{JsFuture, future_to_promise};
+use web_sys::{BinaryType, Blob, MessageEvent};
+
+use super::{Frame, FrameType, WasmWebSocket, WasmWebSocketRef, send_frame};
+use crate::{js_glue::js_imports::collect_args, js_imports_prelude::*};
+
+thread_local! {
+    // The connections to the Socket.IO servers, keyed by their Engine.IO URL.
+    static MANAGERS: RefCell<HashMap<String, Manager>> = RefCell::new(HashMap::new());
+    // The L8WebSockets being opened, keyed by their Engine.IO URL; the concurrent `connect` calls wait for them.
+    static OPENING: RefCell<HashMap<String, Promise>> = RefCell::new(HashMap::new());
+}
+
+const DEFAULT_PATH: &str = "/socket.io/";
+
+// The events of the socket itself, they can't be emitted.
+const RESERVED_EVENTS: [&str; 3] = ["connect", "connect_error", "disconnect"];
+
+// The connection to a Socket.IO server.
+struct Manager {
+    // the id of the L8WebSocket
+    socket: String,
+    // set once the Engine.IO handshake is received, the namespaces are connected after it
+    open: bool,
+    namespaces: HashMap<String, Namespace>,
+    // the binary packet waiting for its attachments
+    partial: Option<(Packet, Vec<ArrayBuffer>)>,
+}
+
+#[derive(Default)]
+struct Namespace {
+    // the JSON of the `auth` payload, sent with each connection of the namespace
+    auth: Option<String>,
+    // the id the server gave the socket, set while it is connected
+    sid: Option<String>,
+    handlers: HashMap<String, Vec<Function>>,
+    // the callback of each pending acknowledgement, with the `reject` of the `emitWithAck` promise
+    acks: HashMap<u64, (Function, Option<Function>)>,
+    next_ack: u64,
+    // the frames emitted while the namespace is not connected
+    buffer: Vec<Frame>,
+    // the `resolve` and `reject` of the pending `connect`
+    connecting: Option<(Function, Function)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PacketType {
+    Connect,
+    Disconnect,
+    Event,
+    Ack,
+    ConnectError,
+    BinaryEvent,
+    BinaryAck,
+}
+
+impl PacketType {
+    fn from_digit(digit: char) -> Option<Self> {
+        Some(match digit {
+            '0' => PacketType::Connect,
+            '1' => PacketType::Disconnect,
+            '2' => PacketType::Event,
+            '3' => PacketType::Ack,
+            '4' => PacketType::ConnectError,
+            '5' => PacketType::BinaryEvent,
+            '6' => PacketType::BinaryAck,
+            _ => return None,
+        })
+    }
+
+    fn digit(self) -> char {
+        match self {
+            PacketType::Connect => '0',
+            PacketType::Disconnect => '1',
+            PacketType::Event => '2',
+            PacketType::Ack => '3',
+            PacketType::ConnectError => '4',
+            PacketType::BinaryEvent => '5',
+            PacketType::BinaryAck => '6',
+        }
+    }
+
+    fn is_binary(self) -> bool {
+        matches!(self, PacketType::BinaryEvent | PacketType::BinaryAck)
+    }
+}
+
+// A Socket.IO packet, it is the payload of an Engine.IO message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Packet {
+    type_: PacketType,
+    nsp: String,
+    id: Option<u64>,
+    // the number of binary frames following the packet
+    attachments: usize,
+    // the JSON payload, the attachments are replaced with placeholders
+    data: Option<String>,
+}
+
+impl Packet {
+    // <type>[<attachments>-][<nsp>,][<id>][<data>]
+    fn encode(&self) -> String {
+        let mut packet = self.type_.digit().to_string();
+        if self.type_.is_binary() {
+            packet.push_str(&format!("{}-", self.attachments));
+        }
+
+        if self.nsp != "/" {
+            packet.push_str(&self.nsp);
+            packet.push(',');
+        }
+
+        if let Some(id) = self.id {
+            packet.push_str(&id.to_string());
+        }
+
+        if let Some(data) = &self.data {
+            packet.push_str(data);
+        }
+
+        packet
+    }
+
+    fn decode(packet: &str) -> Result<Self, String> {
+        let type_ = packet
+            .chars()
+            .next()
+            .and_then(PacketType::from_digit)
+            .ok_or(format!("unknown packet type: {}", packet))?;
+        let mut rest = &packet[1..];
+
+        let mut attachments = 0;
+        if type_.is_binary() {
+            let (count, tail) = rest.split_once('-').ok_or("expected the attachment count of the binary packet")?;
+            attachments = count.parse().map_err(|_| format!("invalid attachment count: {}", count))?;
+            rest = tail;
+        }
+
+        let mut nsp = "/";
+        if rest.starts_with('/') {
+            (nsp, rest) = rest.split_once(',').unwrap_or((rest, ""));
+        }
+
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let id = match digits {
+            0 => None,
+            _ => Some(rest[..digits].parse().map_err(|_| format!("invalid ack id: {}", &rest[..digits]))?),
+        };
+        rest = &rest[digits..];
+
+        Ok(Packet {
+            type_,
+            nsp: nsp.to_string(),
+            id,
+            attachments,
+            data: (!rest.is_empty()).then(|| rest.to_string()),
+        })
+    }
+}
+
+// The Engine.IO URL of the server of `url` and the namespace in its path, as `io(url)` resolves them.
+fn engine_url(url: &str, path: &str) -> Result<(String, String), url::ParseError> {
+    let mut engine = url::Url::parse(url)?;
+    let nsp = match engine.path().trim_end_matches('/') {
+        "" => "/".to_string(),
+        nsp => nsp.to_string(),
+    };
+
+    engine.set_path(path);
+    engine.query_pairs_mut().append_pair("EIO", "4").append_pair("transport", "websocket");
+    Ok((engine.to_string(), nsp))
+}
+
+/// A socket connected to a namespace of a Socket.IO server, see `L8SocketIO.connect`.
+#[wasm_bindgen(js_name = L8SocketIO)]
+pub struct SocketIo {
+    // the Engine.IO URL of the server
+    manager: String,
+    nsp: String,
+}
+
+#[wasm_bindgen(js_class = L8SocketIO)]
+impl SocketIo {
+    /// Connects to the namespace in the path of `url`, it resolves once the server accepted the connection. The
+    /// options are the ones of `L8WebSocket.init` with two more:
+    /// ```js
+    /// export interface SocketIOConfig extends InitConfig {
+    ///     // The path the Socket.IO server is served on, defaults to "/socket.io/".
+    ///     path?: string;
+    ///     // The payload sent with the connection requests of the namespace, `socket.handshake.auth` on the server.
+    ///     auth?: object;
+    /// }
+    /// ```
+    pub async fn connect(options: Object) -> Result<SocketIo, JsValue> {
+        let mut path = DEFAULT_PATH.to_string();
+        let mut auth = None;
+        let config = Object::new();
+        for entry in object_entries(&options).iter() {
+            let entry = Array::from(&entry); // [key, value] result from Object.entries
+            match entry.get(0).as_string().unwrap_or_default().as_str() {
+                "path" => {
+                    path = entry
+                        .get(1)
+                        .as_string()
+                        .ok_or(JsError::new("expected `SocketIOConfig.path` value to be a string"))?;
+                }
+                "auth" => auth = Some(JSON::stringify(&entry.get(1))?.into()),
+                _ => {
+                    Reflect::set(&config, &entry.get(0), &entry.get(1))?;
+                }
+            }
+        }
+
+        let url = Reflect::get(&options, &"url".into())?
+            .as_string()
+            .ok_or(JsError::new("expected `InitConfig.url` value to be a string"))?;
+        let (manager, nsp) = engine_url(&url, &path).map_err(|e| JsError::new(&format!("expected `InitConfig.url` to be a valid URL: {}", e)))?;
+        Reflect::set(&config, &"url".into(), &manager.as_str().into())?;
+
+        if !MANAGERS.with_borrow(|managers| managers.contains_key(&manager)) {
+            let opening = OPENING.with_borrow_mut(|opening| {
+                opening
+                    .entry(manager.clone())
+                    .or_insert_with(|| {
+                        let key = manager.clone();
+                        future_to_promise(async move {
+                            let opened = open(&key, config).await;
+                            OPENING.with_borrow_mut(|opening| opening.remove(&key));
+                            opened.map(|_| JsValue::UNDEFINED)
+                        })
+                    })
+                    .clone()
+            });
+            JsFuture::from(opening).await?;
+        }
+
+        let socket = SocketIo { manager, nsp };
+        let mut connected = None;
+        let connecting = Promise::new(&mut |resolve, reject| {
+            connected = Some(MANAGERS.with_borrow_mut(|managers| {
+                let manager = managers.get_mut(&socket.manager).ok_or("The connection to the server closed")?;
+                if manager.namespaces.contains_key(&socket.nsp) {
+                    return Ok(true);
+                }
+
+                let open = manager.open;
+                let namespace = manager.namespaces.entry(socket.nsp.clone()).or_default();
+                namespace.auth = auth.clone();
+                namespace.connecting = Some((resolve, reject));
+                if open {
+                    send(&manager.socket, connect_packet(&socket.nsp, namespace));
+                }
+
+                Ok::<_, JsValue>(false)
+            }));
+        });
+
+        // the namespace is already connected, or connecting, the socket is shared
+        if connected.transpose()? != Some(true) {
+            JsFuture::from(connecting).await?;
+        }
+
+        Ok(socket)
+    }
+
+    /// The id the server gave this socket, it is set while the socket is connected.
+    #[wasm_bindgen(getter)]
+    pub fn id(&self) -> Option<String> {
+        self.with_namespace(|namespace| namespace.sid.clone()).flatten()
+    }
+
+    /// Whether the socket is connected to its namespace.
+    #[wasm_bindgen(getter)]
+    pub fn connected(&self) -> bool {
+        self.id().is_some()
+    }
+
+    /// The namespace of this socket.
+    #[wasm_bindgen(getter)]
+    pub fn nsp(&self) -> String {
+        self.nsp.clone()
+    }
+
+    /// Registers a listener of the event, it is called with the arguments of the event. An event that asks for an
+    /// acknowledgement gets a callback as its last argument, the arguments it is called with are sent back.
+    /// On top of the events of the server the socket emits:
+    /// - `connect` each time the namespace is connected, the connection is restored along with the L8WebSocket;
+    /// - `disconnect` with the reason: "io server disconnect", "io client disconnect" or "transport close";
+    /// - `connect_error` with an Error when the server refuses a connection that is being restored.
+    pub fn on(&self, event: String, listener: Function) {
+        self.with_namespace(|namespace| namespace.handlers.entry(event).or_default().push(listener));
+    }
+
+    /// Removes the listener of the event, or all its listeners if none is provided.
+    pub fn off(&self, event: &str, listener: Option<Function>) {
+        self.with_namespace(|namespace| match listener {
+            Some(listener) => {
+                if let Some(handlers) = namespace.handlers.get_mut(event) {
+                    handlers.retain(|handler| handler != &listener);
+                }
+            }
+            None => _ = namespace.handlers.remove(event),
+        });
+    }
+
+    /// Emits the event with the arguments. If the last argument is a function it is called with the arguments of the
+    /// acknowledgement of the server. The events emitted while the socket is disconnected are sent once it connects.
+    pub fn emit(&self, event: &str, args: Option<Array>) -> Result<(), JsValue> {
+        let args = args.unwrap_or_default();
+        let ack = match args.length() {
+            0 => None,
+            len => args.get(len - 1).dyn_into::<Function>().ok(),
+        };
+
+        // the array of the caller is left as is
+        let args = match ack {
+            Some(_) => args.slice(0, args.length() - 1),
+            None => args,
+        };
+
+        self.emit_(event, &args, ack.map(|ack| (ack, None)))
+    }
+
+    /// Emits the event with the arguments, it resolves with the first argument of the acknowledgement of the server.
+    /// It rejects if the socket is disconnected, or its connection lost, before the acknowledgement is received.
+    #[wasm_bindgen(js_name = emitWithAck)]
+    pub fn emit_with_ack(&self, event: &str, args: Option<Array>) -> Result<Promise, JsValue> {
+        let mut emitted = Ok(());
+        let promise = Promise::new(&mut |resolve, reject| {
+            emitted = self.emit_(event, &args.clone().unwrap_or_default(), Some((resolve, Some(reject))));
+        });
+
+        emitted.map(|_| promise)
+    }
+
+    /// Disconnects the socket from its namespace, the L8WebSocket closes with the last namespace of the server.
+    pub fn disconnect(&self) -> Result<(), JsValue> {
+        let Some(namespace) = remove_namespace(&self.manager, &self.nsp)? else {
+            return Ok(());
+        };
+
+        if namespace.sid.is_some() {
+            call_all(namespace.handlers.get("disconnect"), &Array::of1(&"io client disconnect".into()));
+        }
+
+        reject_acks(namespace.acks, "The socket disconnected");
+        Ok(())
+    }
+}
+
+impl SocketIo {
+    fn with_namespace<T>(&self, f: impl FnOnce(&mut Namespace) -> T) -> Option<T> {
+        MANAGERS.with_borrow_mut(|managers| {
+            managers
+                .get_mut(&self.manager)
+                .and_then(|manager| manager.namespaces.get_mut(&self.nsp))
+                .map(f)
+        })
+    }
+
+    fn emit_(&self, event: &str, args: &Array, ack: Option<(Function, Option<Function>)>) -> Result<(), JsValue> {
+        if RESERVED_EVENTS.contains(&event) {
+            return Err(JsError::new(&format!("\"{}\" is a reserved event name", event)).into());
+        }
+
+        let args = Array::of1(&event.into()).concat(args);
+        MANAGERS.with_borrow_mut(|managers| {
+            let manager = managers.get_mut(&self.manager).ok_or("The socket is disconnected")?;
+            let namespace = manager.namespaces.get_mut(&self.nsp).ok_or("The socket is disconnected")?;
+
+            let id = ack.map(|ack| {
+                let id = namespace.next_ack;
+                namespace.next_ack += 1;
+                namespace.acks.insert(id, ack);
+                id
+            });
+
+            let frames = data_frames(PacketType::Event, &self.nsp, id, &args)?;
+            match namespace.sid {
+                Some(_) => send(&manager.socket, frames),
+                None => namespace.buffer.extend(frames),
+            }
+
+            Ok(())
+        })
+    }
+}
+
+// Opens the L8WebSocket to the server with the provided Engine.IO URL, the `connect` calls made meanwhile wait for it,
+// see `OPENING`.
+async fn open(key: &str, config: Object) -> Result<(), JsValue> {
+    let socket = WasmWebSocketRef::default();
+    WasmWebSocket::init_(&socket, config).await?;
+    socket.set_binary_type(BinaryType::Arraybuffer);
+
+    // the events of the L8WebSocket are ignored once the server has another one, after a `disconnect`
+    let listen = |type_: &str, listener: fn(&str, web_sys::Event)| {
+        let (key, id) = (key.to_string(), socket.id());
+        let listener = Closure::<dyn FnMut(web_sys::Event)>::new(move |event: web_sys::Event| {
+            if MANAGERS.with_borrow(|managers| managers.get(&key).is_some_and(|manager| manager.socket == id)) {
+                listener(&key, event);
+            }
+        });
+        socket.add_event_listener(type_, Some(listener.into_js_value().unchecked_into()), JsValue::UNDEFINED)
+    };
+
+    listen("message", |key, event| on_frame(key, event.unchecked_into::<MessageEvent>().data()))?;
+    listen("reconnecting", |key, _| on_transport_lost(key, false))?;
+    listen("close", |key, _| on_transport_lost(key, true))?;
+
+    MANAGERS.with_borrow_mut(|managers| {
+        managers.insert(
+            key.to_string(),
+            Manager {
+                socket: socket.id(),
+                open: false,
+                namespaces: HashMap::new(),
+                partial: None,
+            },
+        )
+    });
+
+    Ok(())
+}
+
+// Handles a frame of the server with the provided Engine.IO URL. The text frames are Engine.IO packets, the binary
+// frames are the attachments of the pending binary packet.
+fn on_frame(key: &str, data: JsValue) {
+    let Some(text) = data.as_string() else {
+        let complete = MANAGERS.with_borrow_mut(|managers| {
+            let manager = managers.get_mut(key)?;
+            let (packet, attachments) = manager.partial.as_mut()?;
+            attachments.push(data.dyn_into().ok()?);
+            match attachments.len() == packet.attachments {
+                true => manager.partial.take(),
+                false => None,
+            }
+        });
+
+        if let Some((packet, attachments)) = complete {
+            on_packet(key, packet, &attachments);
+        }
+
+        return;
+    };
+
+    match text.chars().next() {
+        // open, the namespaces are connected
+        Some('0') => {
+            MANAGERS.with_borrow_mut(|managers| {
+                if let Some(manager) = managers.get_mut(key) {
+                    manager.open = true;
+                    for (nsp, namespace) in manager.namespaces.iter() {
+                        send(&manager.socket, connect_packet(nsp, namespace));
+                    }
+                }
+            });
+        }
+
+        // close
+        Some('1') => {
+            if let Some(socket) = MANAGERS.with_borrow(|managers| managers.get(key).map(|manager| manager.socket.clone())) {
+                _ = WasmWebSocketRef::of(socket).close(None, None);
+            }
+        }
+
+        // ping
+        Some('2') => {
+            if let Some(socket) = MANAGERS.with_borrow(|managers| managers.get(key).map(|manager| manager.socket.clone())) {
+                send(&socket, vec![engine_frame("3")]);
+            }
+        }
+
+        // message
+        Some('4') => match Packet::decode(&text[1..]) {
+            Ok(packet) if packet.type_.is_binary() && packet.attachments > 0 => {
+                MANAGERS.with_borrow_mut(|managers| {
+                    if let Some(manager) = managers.get_mut(key) {
+                        manager.partial = Some((packet, Vec::new()));
+                    }
+                });
+            }
+            Ok(packet) => on_packet(key, packet, &[]),
+            Err(_e) => console_error!(&format!("Failed to decode the Socket.IO packet {}: {}", text, _e)),
+        },
+
+        // pong, upgrade and noop
+        _ => {}
+    }
+}
+
+fn on_packet(key: &str, packet: Packet, attachments: &[ArrayBuffer]) {
+    let data = match packet.data.as_deref().map(JSON::parse).transpose().and_then(|data| match data {
+        Some(data) => reconstruct(data, attachments),
+        None => Ok(JsValue::UNDEFINED),
+    }) {
+        Ok(data) => data,
+        Err(_e) => {
+            console_error!(&format!("Failed to parse the data of the Socket.IO packet: {:?}", _e));
+            return;
+        }
+    };
+
+    match packet.type_ {
+        PacketType::Connect => {
+            let sid = Reflect::get(&data, &"sid".into()).ok().and_then(|sid| sid.as_string());
+            let Some((handlers, connecting)) = with_namespace(key, &packet.nsp, |socket, namespace| {
+                namespace.sid = sid;
+                send(socket, std::mem::take(&mut namespace.buffer));
+                (namespace.handlers.get("connect").cloned(), namespace.connecting.take())
+            }) else {
+                return;
+            };
+
+            if let Some((resolve, _)) = connecting {
+                _ = resolve.call0(&JsValue::NULL);
+            }
+            call_all(handlers.as_ref(), &Array::new());
+        }
+
+        PacketType::Disconnect => {
+            let Some((handlers, acks)) = with_namespace(key, &packet.nsp, |_, namespace| {
+                namespace.sid = None;
+                (namespace.handlers.get("disconnect").cloned(), std::mem::take(&mut namespace.acks))
+            }) else {
+                return;
+            };
+
+            call_all(handlers.as_ref(), &Array::of1(&"io server disconnect".into()));
+            reject_acks(acks, "The server disconnected the socket");
+        }
+
+        PacketType::ConnectError => {
+            let message = Reflect::get(&data, &"message".into())
+                .ok()
+                .and_then(|message| message.as_string())
+                .unwrap_or_else(|| "The server refused the connection".to_string());
+            let error = js_sys::Error::new(&message);
+
+            let Some((handlers, connecting)) = with_namespace(key, &packet.nsp, |_, namespace| {
+                (namespace.handlers.get("connect_error").cloned(), namespace.connecting.take())
+            }) else {
+                return;
+            };
+
+            // the socket of a refused `connect` is never handed out
+            match connecting {
+                Some((_, reject)) => {
+                    _ = reject.call1(&JsValue::NULL, &error);
+                    if let Err(_e) = remove_namespace(key, &packet.nsp) {
+                        console_error!(&format!("Failed to close the connection to the server: {:?}", _e));
+                    }
+                }
+                None => call_all(handlers.as_ref(), &Array::of1(&error)),
+            }
+        }
+
+        PacketType::Event | PacketType::BinaryEvent => {
+            let args = Array::from(&data);
+            let Some(event) = args.shift().as_string() else {
+                console_error!("Expected the Socket.IO event to start with its name");
+                return;
+            };
+
+            if let Some(id) = packet.id {
+                args.push(&ack_callback(key, &packet.nsp, id));
+            }
+
+            let handlers = with_namespace(key, &packet.nsp, |_, namespace| namespace.handlers.get(&event).cloned());
+            call_all(handlers.flatten().as_ref(), &args);
+        }
+
+        PacketType::Ack | PacketType::BinaryAck => {
+            let ack = packet
+                .id
+                .and_then(|id| with_namespace(key, &packet.nsp, |_, namespace| namespace.acks.remove(&id)))
+                .flatten();
+
+            if let Some((ack, _)) = ack {
+                if let Err(_e) = ack.apply(&JsValue::NULL, &Array::from(&data)) {
+                    console_error!(&format!("The acknowledgement callback threw: {:?}", _e));
+                }
+            }
+        }
+    }
+}
+
+// The L8WebSocket of the server dropped: the namespaces are disconnected until it is restored, unless it `closed`.
+fn on_transport_lost(key: &str, closed: bool) {
+    let lost: Vec<_> = MANAGERS
+        .with_borrow_mut(|managers| {
+            if closed {
+                let manager = managers.remove(key)?;
+                return Some(
+                    manager
+                        .namespaces
+                        .into_values()
+                        .map(|namespace| {
+                            (
+                                namespace.sid.is_some(),
+                                namespace.handlers.get("disconnect").cloned(),
+                                namespace.connecting,
+                                namespace.acks,
+                            )
+                        })
+                        .collect(),
+                );
+            }
+
+            // the pending `connect` calls resolve once the connection is restored, the acknowledgements of the packets
+            // sent over the lost one never come
+            let manager = managers.get_mut(key)?;
+            manager.open = false;
+            manager.partial = None;
+            Some(
+                manager
+                    .namespaces
+                    .values_mut()
+                    .map(|namespace| {
+                        (
+                            namespace.sid.take().is_some(),
+                            namespace.handlers.get("disconnect").cloned(),
+                            None,
+                            std::mem::take(&mut namespace.acks),
+                        )
+                    })
+                    .collect(),
+            )
+        })
+        .unwrap_or_default();
+
+    for (connected, handlers, connecting, acks) in lost {
+        if let Some((_, reject)) = connecting {
+            _ = reject.call1(&JsValue::NULL, &js_sys::Error::new("The connection to the server closed"));
+        }
+
+        if connected {
+            call_all(handlers.as_ref(), &Array::of1(&"transport close".into()));
+        }
+
+        reject_acks(acks, "The connection to the server closed");
+    }
+}
+
+// Removes the namespace from the connection to the server with the provided Engine.IO URL, the L8WebSocket closes with
+// the last namespace.
+fn remove_namespace(key: &str, nsp: &str) -> Result<Option<Namespace>, JsValue> {
+    let Some((namespace, socket)) = MANAGERS.with_borrow_mut(|managers| {
+        let manager = managers.get_mut(key)?;
+        let namespace = manager.namespaces.remove(nsp)?;
+        if namespace.sid.is_some() {
+            send(&manager.socket, vec![message_frame(&packet(PacketType::Disconnect, nsp, None, None))]);
+        }
+
+        let socket = manager.socket.clone();
+        if manager.namespaces.is_empty() {
+            managers.remove(key);
+            return Some((namespace, Some(socket)));
+        }
+
+        Some((namespace, None))
+    }) else {
+        return Ok(None);
+    };
+
+    if let Some(socket) = socket {
+        WasmWebSocketRef::of(socket).close(None, None)?;
+    }
+
+    Ok(Some(namespace))
+}
+
+// Rejects the `emitWithAck` promises of the acknowledgements that won't come, the callbacks are dropped.
+fn reject_acks(acks: HashMap<u64, (Function, Option<Function>)>, reason: &str) {
+    for (_, reject) in acks.into_values() {
+        if let Some(reject) = reject {
+            _ = reject.call1(&JsValue::NULL, &js_sys::Error::new(reason));
+        }
+    }
+}
+
+fn with_namespace<T>(key: &str, nsp: &str, f: impl FnOnce(&str, &mut Namespace) -> T) -> Option<T> {
+    MANAGERS.with_borrow_mut(|managers| {
+        let manager = managers.get_mut(key)?;
+        let namespace = manager.namespaces.get_mut(nsp)?;
+        Some(f(&manager.socket, namespace))
+    })
+}
+
+// The callback handed to a listener of an event the server wants acknowledged, it sends its arguments back.
+fn ack_callback(key: &str, nsp: &str, id: u64) -> Function {
+    let (key, nsp) = (key.to_string(), nsp.to_string());
+    let mut acked = false;
+    let ack = Closure::<dyn FnMut(Array)>::new(move |args: Array| {
+        // an event is acknowledged once
+        if std::mem::replace(&mut acked, true) {
+            return;
+        }
+
+        let sent = with_namespace(&key, &nsp, |socket, _| {
+            data_frames(PacketType::Ack, &nsp, Some(id), &args).map(|frames| send(socket, frames))
+        });
+        if let Some(Err(_e)) = sent {
+            console_error!(&format!("Failed to send the acknowledgement: {:?}", _e));
+        }
+    });
+
+    collect_args(&ack.into_js_value().unchecked_into())
+}
+
+fn call_all(handlers: Option<&Vec<Function>>, args: &Array) {
+    for handler in handlers.into_iter().flatten() {
+        if let Err(_e) = handler.apply(&JsValue::NULL, args) {
+            console_error!(&format!("A Socket.IO listener threw: {:?}", _e));
+        }
+    }
+}
+
+fn packet(type_: PacketType, nsp: &str, id: Option<u64>, data: Option<String>) -> String {
+    let packet = Packet {
+        type_,
+        nsp: nsp.to_string(),
+        id,
+        attachments: 0,
+        data,
+    };
+
+    packet.encode()
+}
+
+fn connect_packet(nsp: &str, namespace: &Namespace) -> Vec<Frame> {
+    vec![message_frame(&packet(PacketType::Connect, nsp, None, namespace.auth.clone()))]
+}
+
+// The frames of an event or of an acknowledgement: the packet, then its binary attachments.
+fn data_frames(type_: PacketType, nsp: &str, id: Option<u64>, args: &Array) -> Result<Vec<Frame>, JsValue> {
+    let mut attachments = Vec::new();
+    let data = deconstruct(args, &mut attachments)?;
+
+    let type_ = match (type_, attachments.is_empty()) {
+        (PacketType::Event, false) => PacketType::BinaryEvent,
+        (PacketType::Ack, false) => PacketType::BinaryAck,
+        (type_, _) => type_,
+    };
+    let packet = Packet {
+        type_,
+        nsp: nsp.to_string(),
+        id,
+        attachments: attachments.len(),
+        data: JSON::stringify(&data)?.as_string(),
+    };
+
+    let mut frames = vec![message_frame(&packet.encode())];
+    frames.extend(attachments.iter().map(|attachment| Frame {
+        data: attachment.to_vec(),
+        frame_type: FrameType::Binary,
+    }));
+    Ok(frames)
+}
+
+// The Engine.IO message carrying the Socket.IO packet.
+fn message_frame(packet: &str) -> Frame {
+    engine_frame(&format!("4{}", packet))
+}
+
+fn engine_frame(packet: &str) -> Frame {
+    Frame {
+        data: packet.as_bytes().to_vec(),
+        frame_type: FrameType::Text,
+    }
+}
+
+fn send(socket: &str, frames: Vec<Frame>) {
+    for frame in frames {
+        if let Err(_e) = send_frame(socket, frame) {
+            console_error!(&format!("Failed to send the Socket.IO frame: {:?}", _e));
+        }
+    }
+}
+
+// Replaces the binary data in `value` with placeholders, the data is appended to `attachments`.
+fn deconstruct(value: &JsValue, attachments: &mut Vec<Uint8Array>) -> Result<JsValue, JsValue> {
+    if value.is_instance_of::<ArrayBuffer>() || ArrayBuffer::is_view(value) {
+        let data = match value.dyn_ref::<ArrayBuffer>() {
+            Some(buffer) => Uint8Array::new(buffer),
+            None => {
+                let view = |field: &str| Reflect::get(value, &field.into());
+                let offset = view("byteOffset")?.as_f64().unwrap_or_default() as u32;
+                let length = view("byteLength")?.as_f64().unwrap_or_default() as u32;
+                Uint8Array::new_with_byte_offset_and_length(&view("buffer")?, offset, length)
+            }
+        };
+
+        let placeholder = Object::new();
+        Reflect::set(&placeholder, &"_placeholder".into(), &JsValue::TRUE)?;
+        Reflect::set(&placeholder, &"num".into(), &(attachments.len() as u32).into())?;
+        attachments.push(data);
+        return Ok(placeholder.into());
+    }
+
+    if value.is_instance_of::<Blob>() {
+        return Err(JsError::new("Blobs can't be emitted, read them into an ArrayBuffer first").into());
+    }
+
+    if Array::is_array(value) {
+        return Array::from(value)
+            .iter()
+            .map(|item| deconstruct(&item, attachments))
+            .collect::<Result<Array, _>>()
+            .map(Into::into);
+    }
+
+    // the other objects, dates and co, are sent as they serialize
+    if value.is_object() && is_plain_object(value) {
+        let object = Object::new();
+        for entry in object_entries(value.unchecked_ref()).iter() {
+            let entry = Array::from(&entry);
+            Reflect::set(&object, &entry.get(0), &deconstruct(&entry.get(1), attachments)?)?;
+        }
+        return Ok(object.into());
+    }
+
+    Ok(value.clone())
+}
+
+fn is_plain_object(value: &JsValue) -> bool {
+    let prototype = Object::get_prototype_of(value);
+    prototype.is_null() || JsValue::from(prototype) == JsValue::from(Object::get_prototype_of(&Object::new()))
+}
+
+// Replaces the placeholders in the parsed `value` with their attachments.
+fn reconstruct(value: JsValue, attachments: &[ArrayBuffer]) -> Result<JsValue, JsValue> {
+    if Array::is_array(&value) {
+        let array = Array::from(&value);
+        for (index, item) in array.iter().enumerate() {
+            array.set(index as u32, reconstruct(item, attachments)?);
+        }
+    } else if value.is_object() {
+        if Reflect::get(&value, &"_placeholder".into())?.as_bool() == Some(true) {
+            let num = Reflect::get(&value, &"num".into())?
+                .as_f64()
+                .filter(|num| *num >= 0.0 && num.fract() == 0.0)
+                .ok_or(JsError::new(
+                    "expected the `num` of the attachment placeholder to be a non-negative integer",
+                ))?;
+            return attachments
+                .get(num as usize)
+                .map(|attachment| attachment.clone().into())
+                .ok_or(JsError::new(&format!("missing attachment {}", num)).into());
+        }
+
+        for entry in object_entries(value.unchecked_ref()).iter() {
+            let entry = Array::from(&entry);
+            Reflect::set(&value, &entry.get(0), &reconstruct(entry.get(1), attachments)?)?;
+        }
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    #[test]
+    fn packets_round_trip_through_their_encoding() {
+        let packets = [
+            (
+                "0",
+                Packet {
+                    type_: PacketType::Connect,
+                    nsp: "/".into(),
+                    id: None,
+                    attachments: 0,
+                    data: None,
+                },
+            ),
+            (
+                "0/admin,{\"token\":\"123\"}",
+                Packet {
+                    type_: PacketType::Connect,
+                    nsp: "/admin".into(),
+                    id: None,
+                    attachments: 0,
+                    data: Some("{\"token\":\"123\"}".into()),
+                },
+            ),
+            (
+                "1/admin,",
+                Packet {
+                    type_: PacketType::Disconnect,
+                    nsp: "/admin".into(),
+                    id: None,
+                    attachments: 0,
+                    data: None,
+                },
+            ),
+            (
+                "2/chat,12[\"message\",\"hi\"]",
+                Packet {
+                    type_: PacketType::Event,
+                    nsp: "/chat".into(),
+                    id: Some(12),
+                    attachments: 0,
+                    data: Some("[\"message\",\"hi\"]".into()),
+                },
+            ),
+            (
+                "51-[\"upload\",{\"_placeholder\":true,\"num\":0}]",
+                Packet {
+                    type_: PacketType::BinaryEvent,
+                    nsp: "/".into(),
+                    id: None,
+                    attachments: 1,
+                    data: Some("[\"upload\",{\"_placeholder\":true,\"num\":0}]".into()),
+                },
+            ),
+            (
+                "32[]",
+                Packet {
+                    type_: PacketType::Ack,
+                    nsp: "/".into(),
+                    id: Some(2),
+                    attachments: 0,
+                    data: Some("[]".into()),
+                },
+            ),
+        ];
+
+        for (encoded, packet) in packets {
+            assert_eq!(Packet::decode(encoded).as_ref(), Ok(&packet));
+            assert_eq!(packet.encode(), encoded);
+        }
+
+        assert!(Packet::decode("7").is_err());
+        assert!(Packet::decode("5[\"upload\"]").is_err());
+    }
+
+    #[test]
+    fn engine_url_moves_the_namespace_to_the_socket_io_path() {
+        assert_eq!(
+            engine_url("http://localhost:8000/chat?room=1", DEFAULT_PATH),
+            Ok((
+                "http://localhost:8000/socket.io/?room=1&EIO=4&transport=websocket".to_string(),
+                "/chat".to_string()
+            ))
+        );
+
+        assert_eq!(engine_url("https://example.com", "/io/").map(|(_, nsp)| nsp), Ok("/".to_string()));
+    }
+
+    #[wasm_bindgen_test]
+    fn binary_data_round_trips_through_placeholders() {
+        let nested = Object::new();
+        Reflect::set(&nested, &"file".into(), &Uint8Array::from(&b"layer8"[..]).subarray(1, 3)).unwrap();
+        let args = Array::of3(&"upload".into(), &Uint8Array::from(&b"layer8"[..]).buffer(), &nested);
+
+        let mut attachments = Vec::new();
+        let data = JSON::stringify(&deconstruct(&args, &mut attachments).unwrap()).unwrap();
+        assert_eq!(data, r#"["upload",{"_placeholder":true,"num":0},{"file":{"_placeholder":true,"num":1}}]"#);
+
+        // the attachments are received as ArrayBuffers
+        let attachments = attachments
+            .iter()
+            .map(|attachment| Uint8Array::from(&attachment.to_vec()[..]).buffer())
+            .collect::<Vec<_>>();
+        let args = Array::from(&reconstruct(JSON::parse(&data.as_string().unwrap()).unwrap(), &attachments).unwrap());
+        assert_eq!(args.get(0), "upload");
+        assert_eq!(Uint8Array::new(&args.get(1)).to_vec(), b"layer8");
+        assert_eq!(Uint8Array::new(&Reflect::get(&args.get(2), &"file".into()).unwrap()).to_vec(), b"ay");
+
+        for placeholder in [
+            r#"{"_placeholder":true}"#,
+            r#"{"_placeholder":true,"num":-1}"#,
+            r#"{"_placeholder":true,"num":0.5}"#,
+            r#"{"_placeholder":true,"num":2}"#,
+        ] {
+            assert!(reconstruct(JSON::parse(placeholder).unwrap(), &attachments).is_err(), "{}", placeholder);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn acknowledgements_resolve_emit_with_ack() {
+        let key = "ws://ack.test/socket.io/?EIO=4&transport=websocket";
+        MANAGERS.with_borrow_mut(|managers| {
+            managers.insert(
+                key.to_string(),
+                Manager {
+                    socket: String::new(),
+                    open: true,
+                    namespaces: HashMap::from([("/chat".to_string(), Namespace::default())]),
+                    partial: None,
+                },
+            )
+        });
+        let socket = SocketIo {
+            manager: key.to_string(),
+            nsp: "/chat".to_string(),
+        };
+
+        // the namespace is not connected yet, the packet is buffered
+        let acked = socket.emit_with_ack("join", Some(Array::of1(&"room-1".into()))).unwrap();
+        let buffered = socket
+            .with_namespace(|namespace| namespace.buffer.iter().map(|frame| frame.data.clone()).collect::<Vec<_>>())
+            .unwrap();
+        assert_eq!(buffered, [br#"42/chat,0["join","room-1"]"#]);
+
+        on_frame(key, r#"43/chat,0["joined",2]"#.into());
+        assert_eq!(JsFuture::from(acked).await.unwrap(), "joined");
+        assert!(socket.with_namespace(|namespace| namespace.acks.is_empty()).unwrap());
+
+        MANAGERS.with_borrow_mut(|managers| managers.remove(key));
+    }
+}
@@ -0,0 +1,209 @@
+//! Running the interceptor in a dedicated worker, so the encryption, the content decoding and the FormData encoding
+//! don't block the UI. The worker loads the module and calls `serveWorker`, the main thread loads it as well and talks
+//! to the worker through `connectWorker`:
+//!
+//! ```js
+//! // worker.js
+//! import init, { serveWorker } from 'layer8-interceptor-rs';
+//! await init();
+//! serveWorker();
+//!
+//! // main thread
+//! import init, { connectWorker } from 'layer8-interceptor-rs';
+//! await init();
+//! const interceptor = connectWorker(new Worker('worker.js', { type: 'module' }));
+//! const handler = await interceptor.initEncryptedTunnel({ provider, proxy });
+//! const response = await handler.fetch(url, options);
+//! ```
+
+use js_sys::{Array, Object, Promise};
+use wasm_bindgen::{JsCast, JsError, JsValue, prelude::*};
+use wasm_bindgen_futures::future_to_promise;
+use web_sys::Response;
+
+use crate::{
+    js::{check_encrypted_tunnel, init_encrypted_tunnel},
+    js_glue::js_imports::{self, WorkerBridge, create_media_source_url},
+    network_state::{MEDIA_CHUNK_SIZE, NetworkStateHandler},
+};
+
+/// This function serves the interceptor API to the main thread. It is called once, in the dedicated worker that loaded
+/// the module.
+#[wasm_bindgen(js_name = serveWorker)]
+pub fn serve_worker() {
+    let dispatch = Closure::<dyn FnMut(String, Array) -> Promise>::new(|op: String, args: Array| {
+        future_to_promise(async move { dispatch(&op, args).await.map_err(JsValue::from) })
+    });
+
+    js_imports::serve_worker(dispatch.into_js_value().unchecked_into());
+}
+
+async fn dispatch(op: &str, args: Array) -> Result<JsValue, JsError> {
+    let string_arg = |index: u32| {
+        args.get(index)
+            .as_string()
+            .ok_or(JsError::new(&format!("expected argument {} of `{}` to be a string", index, op)))
+    };
+
+    match op {
+        "initEncryptedTunnel" => {
+            let config = args
+                .get(0)
+                .dyn_into::<Object>()
+                .map_err(|_| JsError::new("expected the `InitConfig` to be an object"))?;
+            let handler = init_encrypted_tunnel(config, None).await?;
+            Ok(Array::of2(&JsValue::from_str(&handler.0), &handler.cache_migration()).into())
+        }
+        "checkEncryptedTunnel" => Ok(JsValue::from_bool(check_encrypted_tunnel(Some(string_arg(0)?)).await)),
+        "fetch" => {
+            let handler = NetworkStateHandler(string_arg(0)?);
+            Ok(handler.fetch(string_arg(1)?, args.get(2)).await?.into())
+        }
+        "fetchRange" => {
+            let handler = NetworkStateHandler(string_arg(0)?);
+            let start = args
+                .get(2)
+                .as_f64()
+                .ok_or(JsError::new("expected argument 2 of `fetchRange` to be a number"))?;
+            Ok(handler.fetch_range(string_arg(1)?, start, args.get(3).as_f64()).await?.into())
+        }
+        "static" => {
+            let handler = NetworkStateHandler(string_arg(0)?);
+            Ok(handler.get_static(string_arg(1)?).await?.into())
+        }
+        "clearCache" => {
+            NetworkStateHandler(string_arg(0)?).clear_cache().await?;
+            Ok(JsValue::UNDEFINED)
+        }
+        _ => Err(JsError::new(&format!("unknown worker operation: {}", op))),
+    }
+}
+
+/// This function connects the main thread to the interceptor served by `worker`, see `serveWorker`.
+#[wasm_bindgen(js_name = connectWorker)]
+pub fn connect_worker(worker: web_sys::Worker) -> WorkerInterceptor {
+    WorkerInterceptor(js_imports::connect_worker(&worker))
+}
+
+/// This is the main thread's view of the interceptor running in a worker.
+#[wasm_bindgen]
+pub struct WorkerInterceptor(WorkerBridge);
+
+#[wasm_bindgen]
+impl WorkerInterceptor {
+    /// This function initializes the encrypted tunnel in the worker, it takes the same `InitConfig` as
+    /// `initEncryptedTunnel`.
+    #[wasm_bindgen(js_name = initEncryptedTunnel)]
+    pub async fn init_encrypted_tunnel(&self, init_config: Object) -> Result<WorkerNetworkStateHandler, JsError> {
+        // [provider, cacheMigration]
+        let reply = Array::from(&self.0.init_encrypted_tunnel(&init_config).await.map_err(bridge_error)?);
+
+        Ok(WorkerNetworkStateHandler {
+            bridge: self.0.clone(),
+            provider: reply
+                .get(0)
+                .as_string()
+                .ok_or(JsError::new("expected the worker to respond with the provider"))?,
+            cache_migration: reply.get(1),
+        })
+    }
+
+    /// This function checks if the encrypted tunnel with the provider is open in the worker.
+    #[wasm_bindgen(js_name = checkEncryptedTunnel)]
+    pub async fn check_encrypted_tunnel(&self, provider: String) -> Result<bool, JsError> {
+        let open = self
+            .0
+            .call("checkEncryptedTunnel", &Array::of1(&provider.into()))
+            .await
+            .map_err(bridge_error)?;
+        Ok(open.as_bool().unwrap_or(false))
+    }
+}
+
+/// This is the counterpart of `NetworkStateHandler` for a tunnel held by the worker.
+#[wasm_bindgen]
+pub struct WorkerNetworkStateHandler {
+    bridge: WorkerBridge,
+    provider: String,
+    cache_migration: JsValue,
+}
+
+#[wasm_bindgen]
+impl WorkerNetworkStateHandler {
+    /// This function is an override of the fetch function, run by the worker. The body is copied to the worker, a
+    /// FormData as its entries so it is encoded there. The `signal` option is not passed on so the request can't be
+    /// aborted.
+    pub async fn fetch(&self, url: String, options: JsValue) -> Result<Response, JsError> {
+        self.bridge
+            .fetch(&self.provider, &url, &options)
+            .await
+            .map_err(bridge_error)?
+            .dyn_into::<Response>()
+            .map_err(|_| JsError::new("expected the worker to respond with a Response"))
+    }
+
+    /// This function fetches the `[start, end]` byte range of the resource in the worker, see
+    /// `NetworkStateHandler.fetchRange`.
+    #[wasm_bindgen(js_name = fetchRange)]
+    pub async fn fetch_range(&self, url: String, start: f64, end: Option<f64>) -> Result<Response, JsError> {
+        self.bridge
+            .fetch_range(&self.provider, &url, start, end)
+            .await
+            .map_err(bridge_error)?
+            .dyn_into::<Response>()
+            .map_err(|_| JsError::new("expected the worker to respond with a Response"))
+    }
+
+    /// This function streams a media asset into a `MediaSource`, see `NetworkStateHandler.streamStatic`. The
+    /// `MediaSource` lives on the main thread, the chunks are fetched by the worker.
+    #[wasm_bindgen(js_name = streamStatic)]
+    pub fn stream_static(
+        &self,
+        url: String,
+        mime_type: String,
+        chunk_size: Option<u32>,
+        media: Option<web_sys::HtmlMediaElement>,
+    ) -> Result<String, JsError> {
+        let (bridge, provider) = (self.bridge.clone(), self.provider.clone());
+        let fetch_chunk = Closure::<dyn FnMut(f64, f64) -> Promise>::new(move |start: f64, end: f64| {
+            let (bridge, provider, url) = (bridge.clone(), provider.clone(), url.clone());
+            future_to_promise(async move { bridge.fetch_range(&provider, &url, start, Some(end)).await })
+        });
+
+        create_media_source_url(
+            &mime_type,
+            fetch_chunk.into_js_value().unchecked_into(),
+            chunk_size.unwrap_or(MEDIA_CHUNK_SIZE),
+            media,
+        )
+        .map_err(|e| JsError::new(&format!("failed to create a MediaSource: {:?}", e)))
+    }
+
+    /// This function is called to retrieve the static file, it resolves with an object URL of the main thread.
+    #[wasm_bindgen(js_name = _static)]
+    pub async fn get_static(&self, url: String) -> Result<String, JsError> {
+        self.bridge
+            .get_static(&self.provider, &url)
+            .await
+            .map_err(bridge_error)?
+            .as_string()
+            .ok_or(JsError::new("expected the worker to respond with an object URL"))
+    }
+
+    /// This function clears the cached static assets of this provider.
+    #[wasm_bindgen(js_name = clearCache)]
+    pub async fn clear_cache(&self) -> Result<(), JsError> {
+        self.bridge.clear_cache(&self.provider).await.map_err(bridge_error)?;
+        Ok(())
+    }
+
+    /// The `CacheMigrationReport` of the worker's IndexedDB cache, see `NetworkStateHandler.cacheMigration`.
+    #[wasm_bindgen(getter, js_name = cacheMigration)]
+    pub fn cache_migration(&self) -> JsValue {
+        self.cache_migration.clone()
+    }
+}
+
+fn bridge_error(e: JsValue) -> JsError {
+    JsError::new(&format!("the worker failed to serve the call: {}", js_imports::error_message(&e)))
+}
@@ -0,0 +1,24 @@
+//! Blobs uploaded in a window, see `common`.
+
+mod common;
+
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen_test]
+async fn fetch_uploads_a_blob() {
+    common::fetch_uploads_a_blob().await
+}
+
+#[cfg(feature = "websocket")]
+#[wasm_bindgen_test]
+async fn websocket_sends_a_blob() {
+    common::websocket_sends_a_blob().await
+}
+
+#[cfg(feature = "websocket")]
+#[wasm_bindgen_test]
+async fn websocket_queues_frames_sent_while_opening() {
+    common::websocket_queues_frames_sent_while_opening().await
+}
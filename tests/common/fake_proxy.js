@@ -0,0 +1,78 @@
+// A Layer8 proxy faked in the global scope: the `fetch` and `WebSocket` globals are replaced so the interceptor reaches
+// it instead of the network. The requests and frames it receives are recorded.
+
+const recorded = { tunnels: [], requests: [], frames: [] }
+
+// `tunnel_response` answers the `/init-tunnel` requests and `handshake_response` the first frame of each socket. The
+// other requests are recorded and refused.
+export function install_fake_proxy(tunnel_response, handshake_response) {
+    clear_recorded()
+
+    globalThis.fetch = async function (input, init) {
+        const request = new Request(input, init)
+        const body = new Uint8Array(await request.arrayBuffer())
+        if (new URL(request.url).pathname.endsWith('/init-tunnel')) {
+            recorded.tunnels.push(request.headers.get('x-ecdh-init'))
+            return new Response(tunnel_response, { headers: { 'content-type': 'application/json' } })
+        }
+
+        recorded.requests.push({ url: request.url, body: body })
+        return new Response('refused by the fake proxy', { status: 400 })
+    }
+
+    globalThis.WebSocket = class extends EventTarget {
+        constructor(url) {
+            super()
+            this.url = url
+            this.readyState = 0
+            this.bufferedAmount = 0
+            this.handshaken = false
+            setTimeout(() => this.emit(new Event('open'), 1))
+        }
+
+        // dispatches to the listeners and the `on<type>` handler, as the browser does
+        emit(event, ready_state) {
+            this.readyState = ready_state
+            this.dispatchEvent(event)
+            const handler = this['on' + event.type]
+            if (handler)
+                handler.call(this, event)
+        }
+
+        send(data) {
+            // the data is a view of the wasm memory, it is decoded before it changes
+            recorded.frames.push(new TextDecoder().decode(data))
+            if (this.handshaken)
+                return
+
+            this.handshaken = true
+            setTimeout(() => this.emit(new MessageEvent('message', { data: handshake_response }), 1))
+        }
+
+        close(code, reason) {
+            this.readyState = 2
+            setTimeout(() => this.emit(new CloseEvent('close', { code: code || 1005, reason: reason || '', wasClean: true }), 3))
+        }
+    }
+}
+
+// the public keys the tunnels were initialized with, the `x-ecdh-init` headers
+export function recorded_tunnels() {
+    return recorded.tunnels
+}
+
+// the tunneled requests, `{ url, body }` objects
+export function recorded_requests() {
+    return recorded.requests
+}
+
+// the frames sent over the sockets, as text
+export function recorded_frames() {
+    return recorded.frames
+}
+
+export function clear_recorded() {
+    recorded.tunnels = []
+    recorded.requests = []
+    recorded.frames = []
+}
@@ -0,0 +1,24 @@
+// A follower tab faked on the BroadcastChannel of a provider's tab group, see `glue_tab_share.js`.
+
+// Posts a request to the leader tab, it resolves with the messages the leader addressed to this tab, up to its
+// response.
+export function post_to_leader(provider, kind, url, options) {
+    const channel = new BroadcastChannel('layer8::tunnel::' + provider)
+    const id = 'fake-tab:0'
+    const received = []
+
+    return new Promise(resolve => {
+        channel.onmessage = event => {
+            if (event.data.to !== 'fake-tab' || event.data.id !== id)
+                return
+
+            received.push(event.data)
+            if (event.data.type === 'response') {
+                channel.close()
+                resolve(received)
+            }
+        }
+
+        channel.postMessage({ type: 'request', id: id, from: 'fake-tab', kind: kind, url: url, options: options })
+    })
+}
@@ -0,0 +1,24 @@
+// A dedicated worker faked in the current scope: the messages posted to it are dispatched on the global scope, where
+// `serveWorker` listens, and the replies the scope posts are dispatched on the fake worker. The messages are cloned and
+// their buffers transferred as `postMessage` does.
+
+export function fake_worker() {
+    const worker = new EventTarget()
+    const deliver = (target, data, transfer) => {
+        const message = structuredClone(data, { transfer: transfer || [] })
+        setTimeout(() => target.dispatchEvent(new MessageEvent('message', { data: message })))
+    }
+
+    worker.postMessage = (data, transfer) => deliver(self, data, transfer)
+
+    // the other messages of the scope, those of the test runner, go through
+    const post = self.postMessage
+    self.postMessage = function (data, transfer) {
+        if (data && data.type === 'layer8::reply')
+            return deliver(worker, data, transfer)
+
+        return post.apply(self, arguments)
+    }
+
+    return worker
+}
@@ -0,0 +1,340 @@
+//! The integration tests run against the fake proxy of `fake_proxy.js`, through the real `fetch` and `L8WebSocket`
+//! paths. Each test binary runs some of them, in a window or in a worker.
+
+#![allow(dead_code)]
+
+use base64::{
+    Engine as _,
+    engine::general_purpose::{STANDARD, URL_SAFE},
+};
+use js_sys::{Array, Object, Reflect, Uint8Array};
+use layer8_primitives::{
+    crypto::{Jwk, KeyUse, generate_key_pair},
+    types::{Layer8Envelope, Request, WebSocketPayload},
+};
+use serde_json::{Value, json};
+use wasm_bindgen::{JsCast, JsValue, prelude::wasm_bindgen};
+use web_sys::Blob;
+
+use layer8_interceptor_rs::js::init_encrypted_tunnel;
+
+const PROVIDER: &str = "http://provider.test";
+const PROXY: &str = "http://proxy.test";
+
+#[wasm_bindgen(module = "/tests/common/fake_proxy.js")]
+extern "C" {
+    fn install_fake_proxy(tunnel_response: &str, handshake_response: &str);
+    fn recorded_tunnels() -> Array;
+    fn recorded_requests() -> Array;
+    fn recorded_frames() -> Array;
+    fn clear_recorded();
+}
+
+#[wasm_bindgen(module = "/tests/common/fake_worker.js")]
+extern "C" {
+    fn fake_worker() -> JsValue;
+}
+
+#[wasm_bindgen(module = "/tests/common/fake_tab.js")]
+extern "C" {
+    fn post_to_leader(provider: &str, kind: &str, url: &str, options: &JsValue) -> js_sys::Promise;
+}
+
+// The proxy's end of the tunnels established once it is installed.
+struct FakeProxy {
+    private_key: Jwk,
+}
+
+impl FakeProxy {
+    fn install() -> Self {
+        let (private_key, public_key) = generate_key_pair(KeyUse::Ecdh).expect("failed to generate the proxy keys");
+        let Value::Object(mut proxy_data) = serde_json::to_value(&public_key).expect("failed to serialize the proxy key") else {
+            panic!("expected the proxy key to serialize to an object");
+        };
+        proxy_data.insert("up-JWT".to_string(), json!("up-jwt"));
+
+        let handshake = Layer8Envelope::WebSocket(WebSocketPayload {
+            payload: None,
+            metadata: Value::Object(proxy_data.clone()),
+        });
+
+        install_fake_proxy(
+            &Value::Object(proxy_data).to_string(),
+            &serde_json::to_string(&handshake).expect("failed to serialize the handshake"),
+        );
+
+        FakeProxy { private_key }
+    }
+
+    // The key of the tunnel initialized with `client_key`, the base64 encoded public key of the client.
+    fn tunnel_key(&self, client_key: &str) -> Jwk {
+        let client_key = URL_SAFE
+            .decode(client_key)
+            .or_else(|_| STANDARD.decode(client_key))
+            .expect("expected the client key to be base64 encoded");
+        let client_key = serde_json::from_slice::<Jwk>(&client_key).expect("expected the client key to be a JWK");
+
+        self.private_key
+            .get_ecdh_shared_secret(&client_key)
+            .expect("failed to derive the tunnel key")
+    }
+
+    // The key of the socket tunnel opened by `handshake`, the first frame the socket sent.
+    fn socket_key(&self, handshake: &str) -> Jwk {
+        let Layer8Envelope::WebSocket(handshake) = Layer8Envelope::from_json_bytes(handshake.as_bytes()).expect("failed to decode the handshake")
+        else {
+            panic!("expected the handshake to be a WebSocket envelope");
+        };
+
+        let client_key = handshake.metadata["x-ecdh-init"]
+            .as_str()
+            .expect("expected the handshake to carry the client key");
+        self.tunnel_key(client_key)
+    }
+}
+
+// The request tunneled in `sent`, the body of a request to the proxy. The request is encrypted with `key`, either as the
+// whole body or as a base64 encoded string of its JSON envelope.
+fn open_request(key: &Jwk, sent: &[u8]) -> Request {
+    let open = |data: &[u8]| {
+        key.symmetric_decrypt(data)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<Request>(&data).ok())
+    };
+
+    if let Some(request) = open(sent) {
+        return request;
+    }
+
+    let mut values = vec![serde_json::from_slice::<Value>(sent).expect("expected the tunneled request to be encrypted or JSON")];
+    while let Some(value) = values.pop() {
+        match value {
+            Value::String(data) => {
+                let data = URL_SAFE.decode(&data).or_else(|_| STANDARD.decode(&data));
+                if let Some(request) = data.ok().and_then(|data| open(&data)) {
+                    return request;
+                }
+            }
+            Value::Array(items) => values.extend(items),
+            Value::Object(entries) => values.extend(entries.into_iter().map(|(_, value)| value)),
+            _ => {}
+        }
+    }
+
+    panic!("expected the tunneled request to carry the encrypted request");
+}
+
+fn blob(data: &[u8]) -> Blob {
+    Blob::new_with_u8_array_sequence(&Array::of1(&Uint8Array::from(data))).expect("failed to create the Blob")
+}
+
+fn options(entries: &[(&str, JsValue)]) -> Object {
+    let options = Object::new();
+    for (key, value) in entries {
+        Reflect::set(&options, &JsValue::from_str(key), value).unwrap();
+    }
+
+    options
+}
+
+pub async fn fetch_uploads_a_blob() {
+    let proxy = FakeProxy::install();
+    let config = options(&[("provider", PROVIDER.into()), ("proxy", PROXY.into())]);
+    let handler = init_encrypted_tunnel(config, None)
+        .await
+        .map_err(JsValue::from)
+        .expect("failed to establish the tunnel");
+
+    let tunnels = recorded_tunnels();
+    assert_eq!(tunnels.length(), 1, "expected a single tunnel to be initialized");
+    let key = proxy.tunnel_key(&tunnels.get(0).as_string().expect("expected the tunnel to be initialized with a key"));
+
+    // the body of the request tunneled by the upload
+    let upload = async |body: JsValue| {
+        clear_recorded();
+        let init = options(&[("method", "POST".into()), ("body", body)]);
+        // the fake proxy refuses the request once it has recorded it
+        _ = handler.fetch(format!("{PROVIDER}/upload"), init.into()).await;
+
+        let requests = recorded_requests();
+        assert!(requests.length() > 0, "expected the upload to be tunneled");
+        let sent = Reflect::get(&requests.get(0), &"body".into())
+            .unwrap()
+            .unchecked_into::<Uint8Array>()
+            .to_vec();
+        open_request(&key, &sent).body
+    };
+
+    assert_eq!(upload(blob(b"layer8").into()).await, b"layer8");
+
+    let large = (0..64 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+    assert_eq!(upload(blob(&large).into()).await, large);
+}
+
+// The metadata and the decrypted payload of `frame`, a frame sent over a socket opened with `key`.
+pub async fn worker_encodes_form_data_bodies() {
+    use layer8_interceptor_rs::worker::{connect_worker, serve_worker};
+    use web_sys::FormData;
+
+    let proxy = FakeProxy::install();
+    serve_worker();
+    let interceptor = connect_worker(fake_worker().unchecked_into());
+
+    let config = options(&[("provider", PROVIDER.into()), ("proxy", PROXY.into())]);
+    let handler = interceptor
+        .init_encrypted_tunnel(config)
+        .await
+        .map_err(JsValue::from)
+        .expect("failed to establish the tunnel through the bridge");
+
+    let tunnels = recorded_tunnels();
+    assert_eq!(tunnels.length(), 1, "expected a single tunnel to be initialized");
+    let key = proxy.tunnel_key(&tunnels.get(0).as_string().expect("expected the tunnel to be initialized with a key"));
+
+    let form = FormData::new().expect("failed to create the FormData");
+    form.append_with_str("name", "layer8").unwrap();
+    form.append_with_blob_and_filename("file", &blob(b"data"), "data.txt").unwrap();
+
+    clear_recorded();
+    let init = options(&[("method", "POST".into()), ("body", form.into())]);
+    // the fake proxy refuses the request once it has recorded it
+    _ = handler.fetch(format!("{PROVIDER}/upload"), init.into()).await;
+
+    let requests = recorded_requests();
+    assert!(requests.length() > 0, "expected the upload to be tunneled");
+    let sent = Reflect::get(&requests.get(0), &"body".into())
+        .unwrap()
+        .unchecked_into::<Uint8Array>()
+        .to_vec();
+    let body = String::from_utf8(open_request(&key, &sent).body).expect("expected the form body to be text");
+
+    // the boundary is the interceptor's, the browser didn't encode the form
+    assert!(body.starts_with("------------------------------layer8"), "{}", body);
+    assert!(
+        body.contains("Content-Disposition: form-data; name=\"name\"\r\n\r\nlayer8\r\n"),
+        "{}",
+        body
+    );
+    assert!(
+        body.contains("Content-Disposition: form-data; name=\"file\"; filename=\"data.txt\"\r\n"),
+        "{}",
+        body
+    );
+    assert!(body.contains("\r\n\r\ndata\r\n"), "{}", body);
+}
+
+pub async fn leader_serves_the_requests_of_other_tabs() {
+    use wasm_bindgen_futures::JsFuture;
+
+    let proxy = FakeProxy::install();
+    let config = options(&[("provider", PROVIDER.into()), ("proxy", PROXY.into()), ("shareTunnel", true.into())]);
+    init_encrypted_tunnel(config, None)
+        .await
+        .map_err(JsValue::from)
+        .expect("failed to establish the tunnel");
+
+    // no other tab leads, this one established the tunnel
+    let tunnels = recorded_tunnels();
+    assert_eq!(tunnels.length(), 1, "expected this tab to establish the tunnel");
+    let key = proxy.tunnel_key(&tunnels.get(0).as_string().expect("expected the tunnel to be initialized with a key"));
+
+    let init = options(&[("method", "POST".into()), ("body", "forwarded".into())]);
+    let received = JsFuture::from(post_to_leader(PROVIDER, "fetch", &format!("{PROVIDER}/forwarded"), &init.into()))
+        .await
+        .expect("expected the leader to respond");
+
+    // the leader picks the request up before serving it, the follower doesn't post it again after
+    let types = Array::from(&received)
+        .iter()
+        .map(|message| Reflect::get(&message, &"type".into()).unwrap().as_string().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(types, ["accepted", "response"]);
+
+    let requests = recorded_requests();
+    assert_eq!(requests.length(), 1, "expected the forwarded request to be tunneled once");
+    let sent = Reflect::get(&requests.get(0), &"body".into())
+        .unwrap()
+        .unchecked_into::<Uint8Array>()
+        .to_vec();
+    assert_eq!(open_request(&key, &sent).body, b"forwarded");
+}
+
+#[cfg(feature = "websocket")]
+fn open_frame(key: &Jwk, frame: &str) -> (Value, Vec<u8>) {
+    let Layer8Envelope::WebSocket(WebSocketPayload {
+        payload: Some(payload),
+        metadata,
+    }) = Layer8Envelope::from_json_bytes(frame.as_bytes()).expect("failed to decode the frame")
+    else {
+        panic!("expected the frame to be a WebSocket envelope with a payload");
+    };
+
+    let payload = URL_SAFE.decode(payload).expect("expected the payload to be base64 encoded");
+    (metadata, key.symmetric_decrypt(&payload).expect("failed to decrypt the frame"))
+}
+
+#[cfg(feature = "websocket")]
+fn socket_options() -> Object {
+    options(&[
+        ("url", format!("{}/socket", PROVIDER.replace("http", "ws")).into()),
+        ("proxy", PROXY.replace("http", "ws").into()),
+        ("reconnect", false.into()),
+    ])
+}
+
+#[cfg(feature = "websocket")]
+pub async fn websocket_sends_a_blob() {
+    use layer8_interceptor_rs::websocket::WasmWebSocketRef;
+    use wasm_bindgen_futures::JsFuture;
+
+    let proxy = FakeProxy::install();
+    let socket = WasmWebSocketRef::new();
+    socket.init(socket_options()).await.expect("failed to open the socket");
+
+    let sent = socket
+        .send(blob(b"layer8").into())
+        .expect("failed to send the Blob")
+        .expect("expected the Blob to be sent asynchronously");
+    JsFuture::from(sent).await.expect("failed to send the Blob");
+
+    let frames = recorded_frames();
+    assert_eq!(frames.length(), 2, "expected the handshake and the Blob frames");
+    let key = proxy.socket_key(&frames.get(0).as_string().unwrap());
+
+    let (metadata, data) = open_frame(&key, &frames.get(1).as_string().unwrap());
+    assert_eq!(metadata["x-frame-type"], "binary");
+    assert_eq!(data, b"layer8");
+
+    socket.close(None, None).expect("failed to close the socket");
+}
+
+#[cfg(feature = "websocket")]
+pub async fn websocket_queues_frames_sent_while_opening() {
+    use std::rc::Rc;
+
+    use js_sys::Promise;
+    use layer8_interceptor_rs::websocket::WasmWebSocketRef;
+    use wasm_bindgen_futures::{JsFuture, future_to_promise};
+
+    let proxy = FakeProxy::install();
+    let socket = Rc::new(WasmWebSocketRef::new());
+    let opening = future_to_promise({
+        let socket = socket.clone();
+        async move { socket.init(socket_options()).await.map(|_| JsValue::UNDEFINED) }
+    });
+
+    // `init` runs up to the handshake, the fake proxy answers it in a later task
+    JsFuture::from(Promise::resolve(&JsValue::UNDEFINED)).await.unwrap();
+    assert_eq!(socket.ready_state(), 0, "expected the socket to be opening");
+    assert!(socket.send("queued".into()).expect("failed to queue the frame").is_none());
+    assert_eq!(recorded_frames().length(), 0, "expected the frame to wait for the handshake");
+
+    JsFuture::from(opening).await.expect("failed to open the socket");
+
+    let frames = recorded_frames();
+    assert_eq!(frames.length(), 2, "expected the handshake and the queued frames");
+    let key = proxy.socket_key(&frames.get(0).as_string().unwrap());
+    assert_eq!(open_frame(&key, &frames.get(1).as_string().unwrap()).1, b"queued");
+
+    socket.close(None, None).expect("failed to close the socket");
+}
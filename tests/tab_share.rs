@@ -0,0 +1,12 @@
+//! Requests of other tabs served by the leader of the tab group, see `common`.
+
+mod common;
+
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen_test]
+async fn leader_serves_the_requests_of_other_tabs() {
+    common::leader_serves_the_requests_of_other_tabs().await
+}
@@ -0,0 +1,12 @@
+//! Requests made through the `connectWorker` bridge and served by `serveWorker`, see `common`.
+
+mod common;
+
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+#[wasm_bindgen_test]
+async fn worker_encodes_form_data_bodies() {
+    common::worker_encodes_form_data_bodies().await
+}